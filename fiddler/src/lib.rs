@@ -399,6 +399,13 @@ pub trait Processor: Closer {
     /// process a givent [crate::Message] and return the transformed, one to many messages to continue
     /// on the pipeline.
     async fn process(&self, message: Message) -> Result<MessageBatch, Error>;
+
+    /// emit messages held back by stateful processors.  The runtime calls this periodically
+    /// with `shutdown` set to `false`, and once more with `shutdown` set to `true` before the
+    /// processor is closed.  Flushed messages are tracked as new messages by the runtime.
+    async fn flush(&self, _shutdown: bool) -> Result<MessageBatch, Error> {
+        Ok(Vec::new())
+    }
 }
 
 /// Trait for metrics backends.
@@ -503,6 +510,18 @@ pub enum Error {
     #[error("Conditional check failed")]
     ConditionalCheckfailed,
 
+    /// Processing of a single [crate::Message] has failed.  The message is marked as errored and
+    /// [crate::Status::Errored] is returned to the input module, while the pipeline continues with
+    /// other messages
+    #[error("Message failed: {0}")]
+    MessageFailed(String),
+
+    /// Processing of a [crate::Message] has failed, but produced messages that continue down the
+    /// pipeline, such as the output of the `catch` processors of `try`.  The failure is returned to
+    /// the input module once those messages have been processed
+    #[error("Message failed: {0}")]
+    MessageFailedWithOutput(String, MessageBatch),
+
     /// Error encountered while calling [crate::Input::read] on an input module
     #[error("Input error: {0}")]
     InputError(String),
@@ -10,6 +10,7 @@ use fiddler_macros::fiddler_registration_func;
 use flume::{bounded, Receiver, Sender};
 use serde::Deserialize;
 use serde_yaml::Value;
+use std::collections::HashMap;
 use std::fs::{self, read_to_string, File};
 use std::io::{prelude::*, BufReader, SeekFrom};
 use std::path::Path;
@@ -22,6 +23,9 @@ const MAX_FILE_NOT_FOUND_RETRIES: u32 = 5;
 /// Duration to wait when file is not found before retrying
 const FILE_NOT_FOUND_RETRY_DELAY_SECS: u64 = 5;
 
+/// Metadata key holding the path of the file a message was read from
+const FILE_NAME_METADATA: &str = "file_name";
+
 #[derive(Deserialize, Default)]
 enum CodecType {
     #[default]
@@ -33,7 +37,7 @@ enum CodecType {
 enum ReaderType {
     Lines(std::io::Lines<BufReader<File>>),
     ToEnd(File),
-    Tail(String, u64, Sender<u64>),
+    Tail(u64, Sender<u64>),
 }
 
 #[derive(Deserialize, Default)]
@@ -63,8 +67,13 @@ pub struct FileReader {
     receiver: Receiver<Result<(Message, Option<CallbackChan>), Error>>,
 }
 
+fn file_metadata(filename: &str) -> HashMap<String, Value> {
+    HashMap::from([(FILE_NAME_METADATA.into(), Value::String(filename.into()))])
+}
+
 async fn read_file(
     reader: ReaderType,
+    filename: String,
     sender: Sender<Result<(Message, Option<CallbackChan>), Error>>,
 ) -> Result<(), Error> {
     match reader {
@@ -76,6 +85,7 @@ async fn read_file(
                             .send_async(Ok((
                                 Message {
                                     bytes: line.into_bytes(),
+                                    metadata: file_metadata(&filename),
                                     ..Default::default()
                                 },
                                 None,
@@ -116,6 +126,7 @@ async fn read_file(
                 .send_async(Ok((
                     Message {
                         bytes: contents.into_bytes(),
+                        metadata: file_metadata(&filename),
                         ..Default::default()
                     },
                     None,
@@ -130,7 +141,7 @@ async fn read_file(
 
             return Ok(());
         }
-        ReaderType::Tail(filename, pos, sync) => {
+        ReaderType::Tail(pos, sync) => {
             let mut file = File::open(&filename)
                 .map_err(|e| Error::InputError(format!("{}: {}", filename, e)))?;
             let mut current_pos = pos;
@@ -220,6 +231,7 @@ async fn read_file(
                     .send_async(Ok((
                         Message {
                             bytes: line.into_bytes(),
+                            metadata: file_metadata(&filename),
                             ..Default::default()
                         },
                         Some(tx),
@@ -346,11 +358,12 @@ fn create_file(conf: Value) -> Result<ExecutionType, Error> {
                 }
             });
 
-            ReaderType::Tail(c.filename.clone(), passed_position_reader, sync_sender)
+            ReaderType::Tail(passed_position_reader, sync_sender)
         }
     };
 
     let (sender, receiver) = bounded(0);
+    let filename = c.filename.clone();
 
     tokio::task::spawn_blocking(move || {
         let runtime = tokio::runtime::Builder::new_multi_thread()
@@ -360,7 +373,7 @@ fn create_file(conf: Value) -> Result<ExecutionType, Error> {
             // .on_thread_start(move || set_current_thread_priority_low())
             .build()
             .map_err(|e| Error::ExecutionError(format!("Failed to create tokio runtime: {e}")))?;
-        runtime.block_on(read_file(inner, sender))
+        runtime.block_on(read_file(inner, filename, sender))
     });
 
     Ok(ExecutionType::Input(Box::new(FileReader { receiver })))
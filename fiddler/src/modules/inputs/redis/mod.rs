@@ -55,35 +55,19 @@
 use crate::config::register_plugin;
 use crate::config::ItemType;
 use crate::config::{ConfigSpec, ExecutionType};
+use crate::modules::redis::{is_auth_error, redis_error_to_fiddler_error};
 use crate::{new_callback_chan, CallbackChan, Closer, Error, Input, Message, Status};
 use async_trait::async_trait;
 use fiddler_macros::fiddler_registration_func;
 use flume::{bounded, Receiver, Sender};
 use futures::StreamExt;
-use redis::{Client, ErrorKind};
+use redis::Client;
 use serde::Deserialize;
 use serde_yaml::Value;
 use std::collections::HashMap;
 use tokio::sync::oneshot;
 use tracing::{debug, error, warn};
 
-/// Check if a Redis error is an authentication failure.
-fn is_auth_error(e: &redis::RedisError) -> bool {
-    matches!(e.kind(), ErrorKind::AuthenticationFailed)
-        || e.to_string().to_lowercase().contains("noauth")
-        || e.to_string().to_lowercase().contains("wrongpass")
-        || e.to_string().to_lowercase().contains("invalid password")
-}
-
-/// Convert a Redis error to a fiddler Error, with special handling for auth failures.
-fn redis_error_to_fiddler_error(e: redis::RedisError, context: &str) -> Error {
-    if is_auth_error(&e) {
-        Error::ConfigFailedValidation(format!("Redis authentication failed: {}", e))
-    } else {
-        Error::ExecutionError(format!("{}: {}", context, e))
-    }
-}
-
 const DEFAULT_MODE: &str = "list";
 const DEFAULT_LIST_COMMAND: &str = "brpop";
 const DEFAULT_TIMEOUT: u64 = 1;
@@ -9,6 +9,9 @@ pub mod tls;
 #[cfg(feature = "aws")]
 pub mod aws;
 
+#[cfg(feature = "redis")]
+pub(crate) mod redis;
+
 pub(crate) fn register_plugins() -> Result<(), Error> {
     inputs::register_plugins()?;
     processors::register_plugins()?;
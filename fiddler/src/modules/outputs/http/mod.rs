@@ -108,7 +108,10 @@ fn default_batch_format() -> String {
 }
 
 /// Build a configured reqwest client.
-fn build_client(timeout_secs: u64, tls_config: Option<&ClientTlsConfig>) -> Result<Client, Error> {
+pub(crate) fn build_client(
+    timeout_secs: u64,
+    tls_config: Option<&ClientTlsConfig>,
+) -> Result<Client, Error> {
     let mut builder = Client::builder()
         .timeout(Duration::from_secs(timeout_secs))
         .pool_max_idle_per_host(2)
@@ -124,7 +127,7 @@ fn build_client(timeout_secs: u64, tls_config: Option<&ClientTlsConfig>) -> Resu
 }
 
 /// Build a request with headers and auth.
-fn build_request(
+pub(crate) fn build_request(
     client: &Client,
     method: &Method,
     url: &str,
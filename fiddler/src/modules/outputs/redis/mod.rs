@@ -46,33 +46,17 @@
 use crate::config::register_plugin;
 use crate::config::ItemType;
 use crate::config::{ConfigSpec, ExecutionType};
+use crate::modules::redis::{connection_manager, is_auth_error};
 use crate::{BatchingPolicy, Closer, Error, Message, MessageBatch, Output, OutputBatch};
 use async_trait::async_trait;
 use fiddler_macros::fiddler_registration_func;
 use redis::aio::ConnectionManager;
-use redis::{AsyncCommands, Client, ErrorKind};
+use redis::AsyncCommands;
 use serde::Deserialize;
 use serde_yaml::Value;
 use std::time::Duration;
 use tracing::{debug, warn};
 
-/// Check if a Redis error is an authentication failure.
-fn is_auth_error(e: &redis::RedisError) -> bool {
-    matches!(e.kind(), ErrorKind::AuthenticationFailed)
-        || e.to_string().to_lowercase().contains("noauth")
-        || e.to_string().to_lowercase().contains("wrongpass")
-        || e.to_string().to_lowercase().contains("invalid password")
-}
-
-/// Convert a Redis error to a fiddler Error, with special handling for auth failures.
-fn redis_error_to_fiddler_error(e: redis::RedisError, context: &str) -> Error {
-    if is_auth_error(&e) {
-        Error::ConfigFailedValidation(format!("Redis authentication failed: {}", e))
-    } else {
-        Error::ExecutionError(format!("{}: {}", context, e))
-    }
-}
-
 const DEFAULT_MODE: &str = "list";
 const DEFAULT_LIST_COMMAND: &str = "rpush";
 
@@ -128,12 +112,7 @@ impl RedisListOutput {
 
         let use_lpush = config.list_command.to_lowercase() == "lpush";
 
-        let client = Client::open(config.url.as_str())
-            .map_err(|e| redis_error_to_fiddler_error(e, "Invalid Redis URL"))?;
-
-        let conn = ConnectionManager::new(client)
-            .await
-            .map_err(|e| redis_error_to_fiddler_error(e, "Failed to connect to Redis"))?;
+        let conn = connection_manager(&config.url).await?;
 
         let batch_size = config.batch.as_ref().map_or(500, |b| b.effective_size());
         let interval = config
@@ -222,12 +201,7 @@ impl RedisPubSubOutput {
             Error::ConfigFailedValidation("channel required for pubsub mode".into())
         })?;
 
-        let client = Client::open(config.url.as_str())
-            .map_err(|e| redis_error_to_fiddler_error(e, "Invalid Redis URL"))?;
-
-        let conn = ConnectionManager::new(client)
-            .await
-            .map_err(|e| redis_error_to_fiddler_error(e, "Failed to connect to Redis"))?;
+        let conn = connection_manager(&config.url).await?;
 
         debug!(channel = %channel, "Redis pub/sub output initialized");
 
@@ -278,12 +252,7 @@ impl RedisStreamOutput {
             Error::ConfigFailedValidation("stream required for stream mode".into())
         })?;
 
-        let client = Client::open(config.url.as_str())
-            .map_err(|e| redis_error_to_fiddler_error(e, "Invalid Redis URL"))?;
-
-        let conn = ConnectionManager::new(client)
-            .await
-            .map_err(|e| redis_error_to_fiddler_error(e, "Failed to connect to Redis"))?;
+        let conn = connection_manager(&config.url).await?;
 
         let batch_size = config.batch.as_ref().map_or(500, |b| b.effective_size());
         let interval = config
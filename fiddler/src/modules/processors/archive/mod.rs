@@ -0,0 +1,702 @@
+//! Archive and unarchive processors.
+//!
+//! `unarchive` expands a tar, gzip compressed tar or zip payload into one
+//! message per regular file member, with the member path in the
+//! `archive_path` metadata key.  Directories and other entry types are skipped.
+//! Members are read with size limits, so an archive that expands beyond
+//! `max_member_bytes` or `max_total_bytes` fails instead of exhausting memory.
+//!
+//! `archive` holds messages back and combines them into a single payload once
+//! the batch size, byte or duration limit is reached, and on shutdown.  Tar and
+//! zip member names are taken from `archive_path` metadata when present, so
+//! unarchived members keep their names when archived again.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - unarchive:
+//!       format: auto                # Optional: tar, tar_gz, zip or auto (default: auto)
+//!       max_member_bytes: 67108864  # Optional: maximum size of one member (default: 64MB)
+//!       max_total_bytes: 268435456  # Optional: maximum size of all members (default: 256MB)
+//!   - archive:
+//!       format: ndjson              # Required: tar, zip, ndjson or json_array
+//!       batch:                      # Optional: when to emit an archive
+//!         size: 500
+//!         duration: "10s"
+//!         max_batch_bytes: 10485760
+//! ```
+
+use std::collections::HashSet;
+use std::io::{Cursor, Read, Write};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config::register_plugin;
+use crate::config::ItemType;
+use crate::config::{ConfigSpec, ExecutionType};
+use crate::Message;
+use crate::MessageBatch;
+use crate::{BatchingPolicy, Closer, Error, Processor};
+use async_trait::async_trait;
+use fiddler_macros::fiddler_registration_func;
+use flate2::read::MultiGzDecoder;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use serde_yaml::Value;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// Metadata key holding the path of an archive member
+const PATH_METADATA: &str = "archive_path";
+/// Metadata key holding the number of messages combined into an archive
+const COUNT_METADATA: &str = "archive_count";
+
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum UnarchiveFormat {
+    Tar,
+    TarGz,
+    Zip,
+    #[default]
+    Auto,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum ArchiveFormat {
+    Tar,
+    Zip,
+    Ndjson,
+    JsonArray,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UnarchiveConfig {
+    #[serde(default)]
+    format: UnarchiveFormat,
+    #[serde(default = "default_max_member_bytes")]
+    max_member_bytes: u64,
+    #[serde(default = "default_max_total_bytes")]
+    max_total_bytes: u64,
+}
+
+fn default_max_member_bytes() -> u64 {
+    67_108_864
+}
+
+fn default_max_total_bytes() -> u64 {
+    268_435_456
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ArchiveConfig {
+    format: ArchiveFormat,
+    #[serde(default)]
+    batch: BatchingPolicy,
+}
+
+fn processing_error(e: impl std::fmt::Display) -> Error {
+    Error::ProcessingError(format!("{e}"))
+}
+
+fn message_failed(e: impl std::fmt::Display) -> Error {
+    Error::MessageFailed(format!("{e}"))
+}
+
+/// Identify the archive format from the leading bytes of `data`
+fn detect(data: &[u8]) -> Option<UnarchiveFormat> {
+    if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+        Some(UnarchiveFormat::Zip)
+    } else if data.starts_with(&[0x1f, 0x8b]) {
+        Some(UnarchiveFormat::TarGz)
+    } else if data.get(257..262) == Some(b"ustar") {
+        Some(UnarchiveFormat::Tar)
+    } else {
+        None
+    }
+}
+
+/// Bounds on the expanded size of an archive, guarding against archive bombs
+#[derive(Clone, Copy)]
+struct Limits {
+    member: u64,
+    total: u64,
+}
+
+/// Read one member, failing once it exceeds a limit rather than buffering it whole
+fn read_member(
+    reader: impl Read,
+    path: &str,
+    limits: Limits,
+    total: &mut u64,
+) -> Result<Vec<u8>, Error> {
+    let remaining = limits.total - *total;
+    let limit = limits.member.min(remaining);
+    let mut content = Vec::new();
+    reader
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut content)
+        .map_err(message_failed)?;
+
+    let size = content.len() as u64;
+    if size > limits.member {
+        return Err(Error::MessageFailed(format!(
+            "archive member '{path}' exceeds max_member_bytes of {}",
+            limits.member
+        )));
+    }
+    if size > remaining {
+        return Err(Error::MessageFailed(format!(
+            "archive exceeds max_total_bytes of {}",
+            limits.total
+        )));
+    }
+    *total += size;
+    Ok(content)
+}
+
+fn read_tar<R: Read>(reader: R, limits: Limits) -> Result<Vec<(String, Vec<u8>)>, Error> {
+    let mut archive = tar::Archive::new(reader);
+    let mut members = Vec::new();
+    let mut total = 0;
+    for entry in archive.entries().map_err(message_failed)? {
+        let mut entry = entry.map_err(message_failed)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry
+            .path()
+            .map_err(message_failed)?
+            .to_string_lossy()
+            .into_owned();
+        let content = read_member(&mut entry, &path, limits, &mut total)?;
+        members.push((path, content));
+    }
+    Ok(members)
+}
+
+fn read_zip(data: &[u8], limits: Limits) -> Result<Vec<(String, Vec<u8>)>, Error> {
+    let mut archive = ZipArchive::new(Cursor::new(data)).map_err(message_failed)?;
+    let mut members = Vec::new();
+    let mut total = 0;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(message_failed)?;
+        if !file.is_file() {
+            continue;
+        }
+        let path = file.name().to_string();
+        let content = read_member(&mut file, &path, limits, &mut total)?;
+        members.push((path, content));
+    }
+    Ok(members)
+}
+
+pub struct Unarchive {
+    format: UnarchiveFormat,
+    limits: Limits,
+}
+
+#[async_trait]
+impl Processor for Unarchive {
+    async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
+        let format = match self.format {
+            UnarchiveFormat::Auto => detect(&message.bytes)
+                .ok_or_else(|| Error::MessageFailed("unable to detect archive format".into()))?,
+            format => format,
+        };
+
+        let members = match format {
+            UnarchiveFormat::Tar => read_tar(&message.bytes[..], self.limits)?,
+            UnarchiveFormat::TarGz => {
+                read_tar(MultiGzDecoder::new(&message.bytes[..]), self.limits)?
+            }
+            UnarchiveFormat::Zip => read_zip(&message.bytes, self.limits)?,
+            UnarchiveFormat::Auto => unreachable!("format is detected above"),
+        };
+
+        Ok(members
+            .into_iter()
+            .map(|(path, bytes)| {
+                let mut metadata = message.metadata.clone();
+                metadata.insert(PATH_METADATA.into(), Value::String(path));
+                Message {
+                    bytes,
+                    metadata,
+                    ..Default::default()
+                }
+            })
+            .collect())
+    }
+}
+
+impl Closer for Unarchive {}
+
+#[derive(Default)]
+struct Pending {
+    messages: Vec<Message>,
+    bytes: usize,
+    started: Option<Instant>,
+}
+
+pub struct Archive {
+    format: ArchiveFormat,
+    batch: BatchingPolicy,
+    pending: Mutex<Pending>,
+}
+
+/// Member names for tar and zip archives, made unique within the archive
+fn member_names(messages: &[Message]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    messages
+        .iter()
+        .enumerate()
+        .map(|(i, m)| {
+            let name = match m.metadata.get(PATH_METADATA) {
+                Some(Value::String(path)) if !path.is_empty() => path.clone(),
+                _ => format!("message_{:06}", i + 1),
+            };
+            if seen.insert(name.clone()) {
+                name
+            } else {
+                let unique = format!("{name}.{}", i + 1);
+                seen.insert(unique.clone());
+                unique
+            }
+        })
+        .collect()
+}
+
+fn write_tar(messages: &[Message]) -> Result<Vec<u8>, Error> {
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let mut builder = tar::Builder::new(Vec::new());
+    for (message, name) in messages.iter().zip(member_names(messages)) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(message.bytes.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        builder
+            .append_data(&mut header, name, &message.bytes[..])
+            .map_err(processing_error)?;
+    }
+    builder.into_inner().map_err(processing_error)
+}
+
+fn write_zip(messages: &[Message]) -> Result<Vec<u8>, Error> {
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    for (message, name) in messages.iter().zip(member_names(messages)) {
+        writer.start_file(name, options).map_err(processing_error)?;
+        writer.write_all(&message.bytes).map_err(processing_error)?;
+    }
+    Ok(writer.finish().map_err(processing_error)?.into_inner())
+}
+
+impl Archive {
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Pending>, Error> {
+        self.pending.lock().map_err(processing_error)
+    }
+
+    fn build(&self, messages: Vec<Message>) -> Result<Message, Error> {
+        let count = messages.len();
+        let bytes = match self.format {
+            ArchiveFormat::Tar => write_tar(&messages)?,
+            ArchiveFormat::Zip => write_zip(&messages)?,
+            ArchiveFormat::Ndjson => {
+                let mut output = Vec::new();
+                for message in messages {
+                    let line = message.bytes.strip_suffix(b"\n").unwrap_or(&message.bytes);
+                    output.extend_from_slice(line);
+                    output.push(b'\n');
+                }
+                output
+            }
+            ArchiveFormat::JsonArray => {
+                let values = messages
+                    .iter()
+                    .map(|m| serde_json::from_slice(&m.bytes))
+                    .collect::<Result<Vec<JsonValue>, _>>()
+                    .map_err(processing_error)?;
+                serde_json::to_vec(&values).map_err(processing_error)?
+            }
+        };
+
+        let mut message = Message {
+            bytes,
+            ..Default::default()
+        };
+        message
+            .metadata
+            .insert(COUNT_METADATA.into(), Value::from(count as u64));
+        Ok(message)
+    }
+
+    fn take(pending: &mut Pending) -> Vec<Message> {
+        pending.bytes = 0;
+        pending.started = None;
+        std::mem::take(&mut pending.messages)
+    }
+}
+
+#[async_trait]
+impl Processor for Archive {
+    async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
+        // Reject bodies that cannot be combined before they are held back
+        if self.format == ArchiveFormat::JsonArray {
+            serde_json::from_slice::<JsonValue>(&message.bytes).map_err(message_failed)?;
+        }
+
+        let completed = {
+            let mut pending = self.lock()?;
+            pending.started.get_or_insert_with(Instant::now);
+            pending.bytes += message.bytes.len();
+            pending.messages.push(message);
+
+            if pending.messages.len() >= self.batch.effective_size()
+                || pending.bytes >= self.batch.effective_max_batch_bytes()
+            {
+                Self::take(&mut pending)
+            } else {
+                return Ok(Vec::new());
+            }
+        };
+
+        Ok(vec![self.build(completed)?])
+    }
+
+    async fn flush(&self, shutdown: bool) -> Result<MessageBatch, Error> {
+        let completed = {
+            let mut pending = self.lock()?;
+            let expired = pending
+                .started
+                .is_some_and(|s| shutdown || s.elapsed() >= self.batch.effective_duration());
+            if !expired {
+                return Ok(Vec::new());
+            }
+            Self::take(&mut pending)
+        };
+
+        Ok(vec![self.build(completed)?])
+    }
+}
+
+impl Closer for Archive {}
+
+#[fiddler_registration_func]
+fn create_unarchive(conf: Value) -> Result<ExecutionType, Error> {
+    let c: UnarchiveConfig = serde_yaml::from_value(conf)?;
+
+    if c.max_member_bytes == 0 || c.max_total_bytes == 0 {
+        return Err(Error::ConfigFailedValidation(
+            "max_member_bytes and max_total_bytes must be greater than 0".into(),
+        ));
+    }
+
+    Ok(ExecutionType::Processor(Box::new(Unarchive {
+        format: c.format,
+        limits: Limits {
+            member: c.max_member_bytes,
+            total: c.max_total_bytes,
+        },
+    })))
+}
+
+#[fiddler_registration_func]
+fn create_archive(conf: Value) -> Result<ExecutionType, Error> {
+    let c: ArchiveConfig = serde_yaml::from_value(conf)?;
+
+    if c.batch.size == Some(0) {
+        return Err(Error::ConfigFailedValidation(
+            "batch size must be greater than 0".into(),
+        ));
+    }
+
+    Ok(ExecutionType::Processor(Box::new(Archive {
+        format: c.format,
+        batch: c.batch,
+        pending: Mutex::new(Pending::default()),
+    })))
+}
+
+pub(super) fn register_archive() -> Result<(), Error> {
+    let unarchive_config = "type: object
+properties:
+  format:
+    type: string
+    enum: [\"tar\", \"tar_gz\", \"zip\", \"auto\"]
+  max_member_bytes:
+    type: integer
+    minimum: 1
+  max_total_bytes:
+    type: integer
+    minimum: 1
+additionalProperties: false";
+    let archive_config = "type: object
+required:
+  - format
+properties:
+  format:
+    type: string
+    enum: [\"tar\", \"zip\", \"ndjson\", \"json_array\"]
+  batch:
+    type: object
+    properties:
+      size:
+        type: integer
+        description: \"Messages per archive (default: 500)\"
+      duration:
+        type: string
+        description: \"Maximum time a message is held back (default: 10s)\"
+      max_batch_bytes:
+        type: integer
+        description: \"Maximum cumulative byte size per archive (default: 10MB)\"
+additionalProperties: false";
+
+    register_plugin(
+        "unarchive".into(),
+        ItemType::Processor,
+        ConfigSpec::from_schema(unarchive_config)?,
+        create_unarchive,
+    )?;
+    register_plugin(
+        "archive".into(),
+        ItemType::Processor,
+        ConfigSpec::from_schema(archive_config)?,
+        create_archive,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::Callback;
+    use flate2::{write::GzEncoder, Compression};
+    use std::time::Duration;
+
+    #[test]
+    fn register_plugin() {
+        register_archive().unwrap()
+    }
+
+    async fn processor(creator: Callback, conf: &str) -> Box<dyn Processor + Send + Sync> {
+        let value: Value = serde_yaml::from_str(conf).unwrap();
+        match creator(value).await.unwrap() {
+            ExecutionType::Processor(p) => p,
+            _ => panic!("expected processor"),
+        }
+    }
+
+    fn message(body: &str, path: Option<&str>) -> Message {
+        let mut message = Message {
+            bytes: body.as_bytes().to_vec(),
+            ..Default::default()
+        };
+        if let Some(path) = path {
+            message
+                .metadata
+                .insert(PATH_METADATA.into(), Value::String(path.into()));
+        }
+        message
+    }
+
+    fn members(batch: &[Message]) -> Vec<(String, String)> {
+        batch
+            .iter()
+            .map(|m| {
+                (
+                    m.metadata
+                        .get(PATH_METADATA)
+                        .unwrap()
+                        .as_str()
+                        .unwrap()
+                        .into(),
+                    String::from_utf8(m.bytes.clone()).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let unarchive = processor(create_unarchive, "{}").await;
+        for format in ["tar", "zip"] {
+            let archive = processor(
+                create_archive,
+                &format!("format: {format}\nbatch: {{size: 3}}"),
+            )
+            .await;
+            assert!(archive
+                .process(message("first", Some("logs/a.log")))
+                .await
+                .unwrap()
+                .is_empty());
+            assert!(archive
+                .process(message("second", Some("logs/a.log")))
+                .await
+                .unwrap()
+                .is_empty());
+            let output = archive.process(message("third", None)).await.unwrap();
+            assert_eq!(output.len(), 1);
+            assert_eq!(
+                output[0].metadata.get(COUNT_METADATA),
+                Some(&Value::from(3_u64))
+            );
+
+            let expanded = unarchive.process(output[0].clone()).await.unwrap();
+            assert_eq!(
+                members(&expanded),
+                vec![
+                    ("logs/a.log".into(), "first".into()),
+                    ("logs/a.log.2".into(), "second".into()),
+                    ("message_000003".into(), "third".into()),
+                ],
+                "{format}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn unarchive_tar_gz() {
+        let archive = processor(create_archive, "format: tar").await;
+        archive
+            .process(message("one", Some("a.txt")))
+            .await
+            .unwrap();
+        archive
+            .process(message("two", Some("b/c.txt")))
+            .await
+            .unwrap();
+        let tar = archive.flush(true).await.unwrap().remove(0);
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&tar.bytes).unwrap();
+        let mut input = message("", None);
+        input.bytes = encoder.finish().unwrap();
+        input
+            .metadata
+            .insert("source".into(), Value::String("s3".into()));
+
+        let unarchive = processor(create_unarchive, "format: tar_gz").await;
+        let expanded = unarchive.process(input).await.unwrap();
+        assert_eq!(
+            members(&expanded),
+            vec![
+                ("a.txt".into(), "one".into()),
+                ("b/c.txt".into(), "two".into())
+            ]
+        );
+        assert_eq!(
+            expanded[1].metadata.get("source"),
+            Some(&Value::String("s3".into()))
+        );
+
+        let result = unarchive.process(message("plain", None)).await;
+        assert!(matches!(result, Err(Error::MessageFailed(_))));
+        let auto = processor(create_unarchive, "{}").await;
+        let result = auto.process(message("plain", None)).await;
+        assert!(matches!(result, Err(Error::MessageFailed(_))));
+    }
+
+    #[tokio::test]
+    async fn unarchive_limits() {
+        for format in ["tar", "zip"] {
+            let archive = processor(create_archive, &format!("format: {format}")).await;
+            archive
+                .process(message("12345", Some("a.txt")))
+                .await
+                .unwrap();
+            archive
+                .process(message("67890", Some("b.txt")))
+                .await
+                .unwrap();
+            let packed = archive.flush(true).await.unwrap().remove(0);
+
+            for (conf, expanded) in [
+                ("max_member_bytes: 5\nmax_total_bytes: 10", true),
+                ("max_member_bytes: 4", false),
+                ("max_total_bytes: 9", false),
+            ] {
+                let unarchive = processor(create_unarchive, conf).await;
+                match unarchive.process(packed.clone()).await {
+                    Ok(members) => assert!(expanded && members.len() == 2, "{format} {conf}"),
+                    Err(e) => {
+                        assert!(!expanded, "{format} {conf}: {e}");
+                        assert!(matches!(e, Error::MessageFailed(_)), "{format} {conf}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn archive_json() {
+        let archive = processor(create_archive, "format: ndjson").await;
+        archive.process(message("{\"a\":1}\n", None)).await.unwrap();
+        archive.process(message("{\"a\":2}", None)).await.unwrap();
+        let output = archive.flush(true).await.unwrap();
+        assert_eq!(output[0].bytes, b"{\"a\":1}\n{\"a\":2}\n".to_vec());
+        assert!(archive.flush(true).await.unwrap().is_empty());
+
+        let archive = processor(create_archive, "format: json_array").await;
+        archive.process(message("{\"a\":1}", None)).await.unwrap();
+        let result = archive.process(message("not json", None)).await;
+        assert!(matches!(result, Err(Error::MessageFailed(_))));
+        archive.process(message("[2]", None)).await.unwrap();
+        let output = archive.flush(true).await.unwrap();
+        assert_eq!(output[0].bytes, b"[{\"a\":1},[2]]".to_vec());
+    }
+
+    #[tokio::test]
+    async fn archive_limits() {
+        let archive = processor(
+            create_archive,
+            "format: ndjson\nbatch: {max_batch_bytes: 8, duration: 50ms}",
+        )
+        .await;
+        assert!(archive
+            .process(message("1234", None))
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            archive.process(message("5678", None)).await.unwrap().len(),
+            1
+        );
+
+        assert!(archive
+            .process(message("a", None))
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(archive.flush(false).await.unwrap().is_empty());
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let output = archive.flush(false).await.unwrap();
+        assert_eq!(output[0].bytes, b"a\n".to_vec());
+    }
+
+    #[tokio::test]
+    async fn invalid_config() {
+        for conf in [
+            "{}",
+            "format: rar",
+            "format: tar\nbatch: {size: 0}",
+            "format: tar\nlevel: 1",
+        ] {
+            let value: Value = serde_yaml::from_str(conf).unwrap();
+            assert!(create_archive(value).await.is_err(), "{conf}");
+        }
+        for conf in [
+            "format: ndjson",
+            "max_member_bytes: 0",
+            "max_total_bytes: 0",
+        ] {
+            let value: Value = serde_yaml::from_str(conf).unwrap();
+            assert!(create_unarchive(value).await.is_err(), "{conf}");
+        }
+    }
+}
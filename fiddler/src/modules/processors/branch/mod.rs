@@ -0,0 +1,461 @@
+//! Branch processor for running nested processors on a copy of a message.
+//!
+//! `request_map` builds the body handed to the nested `processors` from the
+//! original message; without it the whole message is copied.  Once the
+//! nested processors complete, `result_map` grafts fields of the result back
+//! into the original message.  Without `result_map` the original passes
+//! through unchanged, which suits processors run for their side effects.
+//!
+//! When the nested processors emit several messages, `result_map` is
+//! evaluated against a JSON array of their bodies.  When they emit none, the
+//! original message passes through unchanged.  Errors raised by the nested
+//! processors fail the original message.  Messages held back by the nested
+//! processors are flushed for their side effects and then discarded, as they
+//! have no original message to be merged into.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - branch:
+//!       request_map:                 # Optional: body sent to the nested processors
+//!         - source: "client.ip"      # JMESPath expression against the original
+//!           target: "ip"             # Field within the request
+//!       processors:                  # Required: processors run on the request
+//!         - http:
+//!             url: "https://geo.example.com/lookup"
+//!       result_map:                  # Optional: fields merged back into the original
+//!         - source: "country"        # JMESPath expression against the result
+//!           target: "client.country" # Field within the original
+//! ```
+
+use super::path::{parse_path, set_path, variable, Expression};
+use super::{build_processors, flush_chain, run_chain};
+use crate::config::register_plugin;
+use crate::config::{ConfigSpec, ExecutionType};
+use crate::config::{Item, ItemType};
+use crate::Message;
+use crate::MessageBatch;
+use crate::{Closer, Error, Processor};
+use async_trait::async_trait;
+use fiddler_macros::fiddler_registration_func;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use serde_yaml::Value;
+use tracing::debug;
+
+#[derive(Deserialize)]
+struct Mapping {
+    source: String,
+    target: String,
+}
+
+#[derive(Deserialize)]
+struct BranchConfig {
+    request_map: Option<Vec<Mapping>>,
+    processors: Vec<Item>,
+    result_map: Option<Vec<Mapping>>,
+}
+
+/// Mapping with its source compiled and target path parsed
+struct ParsedMapping {
+    source: Expression,
+    target: Vec<String>,
+}
+
+pub struct Branch {
+    request_map: Option<Vec<ParsedMapping>>,
+    processors: Vec<Box<dyn Processor + Send + Sync>>,
+    result_map: Option<Vec<ParsedMapping>>,
+}
+
+/// Evaluate each mapping against `source` and write the results into `target`
+fn apply_mappings(
+    mappings: &[ParsedMapping],
+    source: &[u8],
+    target: &mut JsonValue,
+) -> Result<(), Error> {
+    let data = variable(source)?;
+    for m in mappings {
+        set_path(target, &m.target, m.source.search(&data)?)?;
+    }
+    Ok(())
+}
+
+impl Branch {
+    fn request(&self, message: &Message) -> Result<Message, Error> {
+        let Some(mappings) = &self.request_map else {
+            return Ok(message.clone());
+        };
+
+        let mut request = JsonValue::Object(serde_json::Map::new());
+        apply_mappings(mappings, &message.bytes, &mut request)?;
+
+        Ok(Message {
+            bytes: serde_json::to_vec(&request)
+                .map_err(|e| Error::ProcessingError(format!("{e}")))?,
+            metadata: message.metadata.clone(),
+            ..Default::default()
+        })
+    }
+}
+
+#[async_trait]
+impl Processor for Branch {
+    async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
+        let request = self.request(&message)?;
+        let mut results = run_chain(&self.processors, vec![request]).await?;
+
+        let Some(mappings) = &self.result_map else {
+            return Ok(vec![message]);
+        };
+        if results.is_empty() {
+            return Ok(vec![message]);
+        }
+
+        let result = if results.len() == 1 {
+            results.remove(0).bytes
+        } else {
+            let values = results
+                .iter()
+                .map(|m| serde_json::from_slice(&m.bytes))
+                .collect::<Result<Vec<JsonValue>, _>>()
+                .map_err(|e| Error::MessageFailed(format!("{e}")))?;
+            serde_json::to_vec(&values).map_err(|e| Error::ProcessingError(format!("{e}")))?
+        };
+
+        let mut original: JsonValue = serde_json::from_slice(&message.bytes)
+            .map_err(|e| Error::MessageFailed(format!("{e}")))?;
+        apply_mappings(mappings, &result, &mut original)?;
+
+        Ok(vec![Message {
+            bytes: serde_json::to_vec(&original)
+                .map_err(|e| Error::ProcessingError(format!("{e}")))?,
+            ..message
+        }])
+    }
+
+    async fn flush(&self, shutdown: bool) -> Result<MessageBatch, Error> {
+        // Flushed results have no original message to be merged into
+        let discarded = flush_chain(&self.processors, shutdown).await?;
+        if !discarded.is_empty() {
+            debug!(
+                count = discarded.len(),
+                "discarding messages flushed by branch processors"
+            );
+        }
+        Ok(Vec::new())
+    }
+}
+
+#[async_trait]
+impl Closer for Branch {
+    async fn close(&mut self) -> Result<(), Error> {
+        for p in &mut self.processors {
+            p.close().await?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_mappings(mappings: Option<Vec<Mapping>>) -> Result<Option<Vec<ParsedMapping>>, Error> {
+    mappings
+        .map(|mappings| {
+            mappings
+                .into_iter()
+                .map(|m| {
+                    Ok(ParsedMapping {
+                        source: Expression::compile(&m.source)?,
+                        target: parse_path(&m.target)?,
+                    })
+                })
+                .collect()
+        })
+        .transpose()
+}
+
+#[fiddler_registration_func]
+fn create_branch(conf: Value) -> Result<ExecutionType, Error> {
+    let c: BranchConfig = serde_yaml::from_value(conf)?;
+
+    if c.processors.is_empty() {
+        return Err(Error::ConfigFailedValidation(
+            "at least one processor is required".into(),
+        ));
+    }
+
+    let request_map = parse_mappings(c.request_map)?;
+    let result_map = parse_mappings(c.result_map)?;
+
+    let processors = build_processors(c.processors).await?;
+
+    Ok(ExecutionType::Processor(Box::new(Branch {
+        request_map,
+        processors,
+        result_map,
+    })))
+}
+
+pub(super) fn register_branch() -> Result<(), Error> {
+    let config = "type: object
+properties:
+  request_map:
+    type: array
+    items:
+      type: object
+      properties:
+        source:
+          type: string
+        target:
+          type: string
+      required:
+        - source
+        - target
+  processors:
+    type: array
+    items:
+      type: object
+  result_map:
+    type: array
+    items:
+      type: object
+      properties:
+        source:
+          type: string
+        target:
+          type: string
+      required:
+        - source
+        - target
+required:
+  - processors";
+    let conf_spec = ConfigSpec::from_schema(config)?;
+
+    register_plugin(
+        "branch".into(),
+        ItemType::Processor,
+        conf_spec,
+        create_branch,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use std::sync::Once;
+
+    static REGISTER: Once = Once::new();
+
+    /// Replaces the body with `{"greeting": {"to": <name>}, "keys": [...]}`
+    struct Greeter;
+
+    #[async_trait]
+    impl Processor for Greeter {
+        async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
+            let body: JsonValue = serde_json::from_slice(&message.bytes).unwrap();
+            let keys: Vec<&String> = body.as_object().unwrap().keys().collect();
+            let result = json!({"greeting": {"to": body["name"]}, "keys": keys});
+            Ok(vec![Message {
+                bytes: serde_json::to_vec(&result).unwrap(),
+                ..message
+            }])
+        }
+    }
+
+    impl Closer for Greeter {}
+
+    /// Emits one message per element of the `items` array
+    struct Splitter;
+
+    #[async_trait]
+    impl Processor for Splitter {
+        async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
+            let body: JsonValue = serde_json::from_slice(&message.bytes).unwrap();
+            match body["items"].as_array() {
+                Some(items) => Ok(items
+                    .iter()
+                    .map(|i| Message {
+                        bytes: serde_json::to_vec(i).unwrap(),
+                        ..Default::default()
+                    })
+                    .collect()),
+                None => Err(Error::ProcessingError("items is not an array".into())),
+            }
+        }
+    }
+
+    impl Closer for Splitter {}
+
+    /// Replaces the body with plain text
+    struct Plain;
+
+    #[async_trait]
+    impl Processor for Plain {
+        async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
+            Ok(vec![Message {
+                bytes: b"plain".to_vec(),
+                ..message
+            }])
+        }
+    }
+
+    impl Closer for Plain {}
+
+    #[fiddler_registration_func]
+    fn create_greeter(_conf: Value) -> Result<ExecutionType, Error> {
+        Ok(ExecutionType::Processor(Box::new(Greeter)))
+    }
+
+    fn branch(
+        request_map: Option<Vec<Mapping>>,
+        processor: Box<dyn Processor + Send + Sync>,
+        result_map: Option<Vec<Mapping>>,
+    ) -> Branch {
+        Branch {
+            request_map: parse_mappings(request_map).unwrap(),
+            processors: vec![processor],
+            result_map: parse_mappings(result_map).unwrap(),
+        }
+    }
+
+    fn mapping(source: &str, target: &str) -> Mapping {
+        Mapping {
+            source: source.into(),
+            target: target.into(),
+        }
+    }
+
+    fn message(body: JsonValue) -> Message {
+        Message {
+            bytes: serde_json::to_vec(&body).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    fn body(message: &Message) -> JsonValue {
+        serde_json::from_slice(&message.bytes).unwrap()
+    }
+
+    #[test]
+    fn register_plugin() {
+        register_branch().unwrap()
+    }
+
+    #[tokio::test]
+    async fn request_and_result_maps() {
+        let p = branch(
+            Some(vec![mapping("user.name", "name")]),
+            Box::new(Greeter),
+            Some(vec![
+                mapping("greeting", "user.greeting"),
+                mapping("keys", "request_keys"),
+            ]),
+        );
+
+        let mut input = message(json!({"user": {"name": "ada"}, "id": 7}));
+        input.metadata.insert("source".into(), Value::from("test"));
+        let output = p.process(input).await.unwrap();
+        assert_eq!(output.len(), 1);
+        assert_eq!(
+            body(&output[0]),
+            json!({
+                "user": {"name": "ada", "greeting": {"to": "ada"}},
+                "id": 7,
+                "request_keys": ["name"]
+            })
+        );
+        assert_eq!(output[0].metadata.get("source"), Some(&Value::from("test")));
+    }
+
+    #[tokio::test]
+    async fn multiple_and_no_results() {
+        let p = branch(
+            None,
+            Box::new(Splitter),
+            Some(vec![mapping("[*].v", "values")]),
+        );
+
+        let output = p
+            .process(message(json!({"items": [{"v": 1}, {"v": 2}]})))
+            .await
+            .unwrap();
+        assert_eq!(
+            body(&output[0]),
+            json!({"items": [{"v": 1}, {"v": 2}], "values": [1, 2]})
+        );
+
+        let output = p.process(message(json!({"items": []}))).await.unwrap();
+        assert_eq!(body(&output[0]), json!({"items": []}));
+        assert!(p.process(message(json!({"items": 1}))).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn bad_data_fails_message() {
+        let p = branch(
+            Some(vec![mapping("items", "items")]),
+            Box::new(Splitter),
+            Some(vec![mapping("[*]", "values")]),
+        );
+        let not_json = Message {
+            bytes: b"not json".to_vec(),
+            ..Default::default()
+        };
+        let result = p.process(not_json).await;
+        assert!(matches!(result, Err(Error::MessageFailed(_))), "{result:?}");
+
+        // results that are not JSON cannot be mapped back
+        let p = branch(None, Box::new(Plain), Some(vec![mapping("a", "a")]));
+        let result = p.process(message(json!({"a": 1}))).await;
+        assert!(matches!(result, Err(Error::MessageFailed(_))), "{result:?}");
+    }
+
+    #[tokio::test]
+    async fn without_result_map() {
+        let p = branch(None, Box::new(Splitter), None);
+        let output = p.process(message(json!({"items": [1, 2]}))).await.unwrap();
+        assert_eq!(output, vec![message(json!({"items": [1, 2]}))]);
+    }
+
+    #[tokio::test]
+    async fn nested_processors_from_config() {
+        REGISTER.call_once(|| {
+            crate::config::register_plugin(
+                "branch_test_greeter".into(),
+                ItemType::Processor,
+                ConfigSpec::from_schema("type: object").unwrap(),
+                create_greeter,
+            )
+            .unwrap()
+        });
+
+        let value: Value = serde_yaml::from_str(
+            "request_map: [{source: name, target: name}]
+processors:
+  - label: greet
+    branch_test_greeter: {}
+result_map: [{source: greeting.to, target: to}]",
+        )
+        .unwrap();
+        let p = match create_branch(value).await.unwrap() {
+            ExecutionType::Processor(p) => p,
+            _ => panic!("expected processor"),
+        };
+        let output = p.process(message(json!({"name": "ada"}))).await.unwrap();
+        assert_eq!(body(&output[0]), json!({"name": "ada", "to": "ada"}));
+    }
+
+    #[tokio::test]
+    async fn invalid_config() {
+        for conf in [
+            "processors: []",
+            "result_map: []",
+            "processors: [{not_a_processor: {}}]",
+            "processors: [{noop: {}}]\nrequest_map: [{source: 'a[[', target: a}]",
+            "processors: [{noop: {}}]\nresult_map: [{source: a, target: 'a..b'}]",
+        ] {
+            let value: Value = serde_yaml::from_str(conf).unwrap();
+            assert!(create_branch(value).await.is_err(), "{conf}");
+        }
+    }
+}
@@ -1,4 +1,24 @@
-use std::io::Read;
+//! Compress and decompress processors.
+//!
+//! `decompress` also accepts `algorithm: auto`, which detects the codec from
+//! the leading magic bytes and passes unrecognized messages through unchanged.
+//! The zlib header is short enough to match plain text, so messages that look
+//! like zlib but fail to inflate are also passed through.  Raw deflate has no
+//! signature and is never detected.  Concatenated gzip, zstd, bzip2 and xz
+//! members are decompressed in full.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - compress:
+//!       algorithm: zstd  # Optional: gzip, deflate, zlib, zstd, lz4, snappy, bzip2 or xz (default: gzip)
+//!       level: 9         # Optional: codec specific compression level
+//!   - decompress:
+//!       algorithm: auto  # Optional: any of the above, or auto (default: gzip)
+//! ```
+
+use std::io::{Read, Write};
 
 use crate::config::register_plugin;
 use crate::config::ItemType;
@@ -13,19 +33,68 @@ use serde::Deserialize;
 use serde_yaml::Value;
 
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 struct CompressConfig {
     algorithm: Option<Algorithm>,
+    level: Option<i32>,
 }
 
-#[derive(Clone, Default, Deserialize)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
 pub enum Algorithm {
     #[default]
+    #[serde(alias = "Gzip")]
     Gzip,
-    Zip,
+    /// Raw deflate stream, formerly named `Zip`
+    #[serde(alias = "Zip", alias = "zip")]
+    Deflate,
+    #[serde(alias = "Zlib")]
     Zlib,
+    Zstd,
+    Lz4,
+    Snappy,
+    Bzip2,
+    Xz,
+    /// Detect the codec when decompressing
+    Auto,
+}
+
+impl Algorithm {
+    /// Range of accepted levels and the level used when none is configured
+    fn levels(&self) -> Option<(i32, i32, i32)> {
+        match self {
+            Algorithm::Gzip | Algorithm::Deflate | Algorithm::Zlib => Some((0, 9, 9)),
+            Algorithm::Zstd => Some((1, 22, 3)),
+            Algorithm::Bzip2 => Some((1, 9, 9)),
+            Algorithm::Xz => Some((0, 9, 6)),
+            Algorithm::Lz4 | Algorithm::Snappy | Algorithm::Auto => None,
+        }
+    }
+
+    /// Identify the codec from the leading bytes of `data`
+    fn detect(data: &[u8]) -> Option<Self> {
+        const SNAPPY_STREAM: &[u8] = b"\xff\x06\x00\x00sNaPpY";
+        match data {
+            [0x1f, 0x8b, ..] => Some(Algorithm::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Algorithm::Zstd),
+            [0x04, 0x22, 0x4d, 0x18, ..] => Some(Algorithm::Lz4),
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Some(Algorithm::Xz),
+            [b'B', b'Z', b'h', b'1'..=b'9', ..] => Some(Algorithm::Bzip2),
+            _ if data.starts_with(SNAPPY_STREAM) => Some(Algorithm::Snappy),
+            // zlib: deflate method with a header checksum that is a multiple of 31
+            [cmf, flg, ..]
+                if cmf & 0x0f == 8
+                    && cmf >> 4 <= 7
+                    && (u16::from(*cmf) << 8 | u16::from(*flg)).is_multiple_of(31) =>
+            {
+                Some(Algorithm::Zlib)
+            }
+            _ => None,
+        }
+    }
 }
 
-#[derive(Clone, Default, Deserialize)]
+#[derive(Clone, Copy, Default, Deserialize)]
 pub enum Operation {
     Compress,
     #[default]
@@ -36,74 +105,86 @@ pub enum Operation {
 pub struct Compress {
     algorithm: Algorithm,
     method: Operation,
+    level: Option<i32>,
+}
+
+fn read_all<R: Read>(mut reader: R) -> Result<Vec<u8>, Error> {
+    let mut output = Vec::new();
+    reader
+        .read_to_end(&mut output)
+        .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+    Ok(output)
+}
+
+fn write_all<W: Write>(mut writer: W, data: &[u8]) -> Result<W, Error> {
+    writer
+        .write_all(data)
+        .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+    Ok(writer)
+}
+
+fn compress(algorithm: Algorithm, level: i32, data: &[u8]) -> Result<Vec<u8>, Error> {
+    let flate_level = Compression::new(level.unsigned_abs());
+    match algorithm {
+        Algorithm::Gzip => read_all(read::GzEncoder::new(data, flate_level)),
+        Algorithm::Deflate => read_all(read::DeflateEncoder::new(data, flate_level)),
+        Algorithm::Zlib => read_all(read::ZlibEncoder::new(data, flate_level)),
+        Algorithm::Zstd => {
+            zstd::encode_all(data, level).map_err(|e| Error::ProcessingError(format!("{e}")))
+        }
+        Algorithm::Lz4 => write_all(lz4_flex::frame::FrameEncoder::new(Vec::new()), data)?
+            .finish()
+            .map_err(|e| Error::ProcessingError(format!("{e}"))),
+        Algorithm::Snappy => write_all(snap::write::FrameEncoder::new(Vec::new()), data)?
+            .into_inner()
+            .map_err(|e| Error::ProcessingError(format!("{e}"))),
+        Algorithm::Bzip2 => read_all(bzip2::read::BzEncoder::new(
+            data,
+            bzip2::Compression::new(level.unsigned_abs()),
+        )),
+        Algorithm::Xz => read_all(xz2::read::XzEncoder::new(data, level.unsigned_abs())),
+        Algorithm::Auto => Err(Error::ProcessingError(
+            "auto is only supported when decompressing".into(),
+        )),
+    }
+}
+
+fn decompress(algorithm: Algorithm, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    match algorithm {
+        Algorithm::Gzip => read_all(read::MultiGzDecoder::new(&data[..])),
+        Algorithm::Deflate => read_all(read::DeflateDecoder::new(&data[..])),
+        Algorithm::Zlib => read_all(read::ZlibDecoder::new(&data[..])),
+        Algorithm::Zstd => {
+            zstd::decode_all(&data[..]).map_err(|e| Error::ProcessingError(format!("{e}")))
+        }
+        Algorithm::Lz4 => read_all(lz4_flex::frame::FrameDecoder::new(&data[..])),
+        Algorithm::Snappy => read_all(snap::read::FrameDecoder::new(&data[..])),
+        Algorithm::Bzip2 => read_all(bzip2::read::MultiBzDecoder::new(&data[..])),
+        Algorithm::Xz => read_all(xz2::read::XzDecoder::new_multi_decoder(&data[..])),
+        Algorithm::Auto => match Algorithm::detect(&data) {
+            // The two byte zlib header also matches plain text, such as "80",
+            // so data that fails to inflate is passed through unchanged
+            Some(Algorithm::Zlib) => read_all(read::ZlibDecoder::new(&data[..])).or(Ok(data)),
+            Some(detected) => decompress(detected, data),
+            None => Ok(data),
+        },
+    }
 }
 
 #[async_trait]
 impl Processor for Compress {
     async fn process(&self, mut message: Message) -> Result<MessageBatch, Error> {
-        match self.method {
-            Operation::Compress => match self.algorithm {
-                Algorithm::Gzip => {
-                    let mut output = Vec::new();
-                    let mut deflater =
-                        read::GzEncoder::new(&message.bytes[..], Compression::best());
-                    deflater
-                        .read_to_end(&mut output)
-                        .map_err(|e| Error::ProcessingError(format!("{e}")))?;
-                    message.bytes = output;
-                    Ok(vec![message])
-                }
-                Algorithm::Zip => {
-                    let mut output = Vec::new();
-                    let mut deflater =
-                        read::DeflateEncoder::new(&message.bytes[..], Compression::best());
-                    deflater
-                        .read_to_end(&mut output)
-                        .map_err(|e| Error::ProcessingError(format!("{e}")))?;
-                    message.bytes = output;
-                    Ok(vec![message])
-                }
-                Algorithm::Zlib => {
-                    let mut output = Vec::new();
-                    let mut deflater =
-                        read::ZlibEncoder::new(&message.bytes[..], Compression::best());
-                    deflater
-                        .read_to_end(&mut output)
-                        .map_err(|e| Error::ProcessingError(format!("{e}")))?;
-                    message.bytes = output;
-                    Ok(vec![message])
-                }
-            },
-            Operation::Decompress => match self.algorithm {
-                Algorithm::Gzip => {
-                    let mut output = Vec::new();
-                    let mut deflater = read::GzDecoder::new(&message.bytes[..]);
-                    deflater
-                        .read_to_end(&mut output)
-                        .map_err(|e| Error::ProcessingError(format!("{e}")))?;
-                    message.bytes = output;
-                    Ok(vec![message])
-                }
-                Algorithm::Zip => {
-                    let mut output = Vec::new();
-                    let mut deflater = read::DeflateDecoder::new(&message.bytes[..]);
-                    deflater
-                        .read_to_end(&mut output)
-                        .map_err(|e| Error::ProcessingError(format!("{e}")))?;
-                    message.bytes = output;
-                    Ok(vec![message])
-                }
-                Algorithm::Zlib => {
-                    let mut output = Vec::new();
-                    let mut deflater = read::ZlibDecoder::new(&message.bytes[..]);
-                    deflater
-                        .read_to_end(&mut output)
-                        .map_err(|e| Error::ProcessingError(format!("{e}")))?;
-                    message.bytes = output;
-                    Ok(vec![message])
-                }
-            },
-        }
+        message.bytes = match self.method {
+            Operation::Compress => {
+                let level = self
+                    .level
+                    .or(self.algorithm.levels().map(|(_, _, default)| default))
+                    .unwrap_or_default();
+                compress(self.algorithm, level, &message.bytes)?
+            }
+            Operation::Decompress => decompress(self.algorithm, message.bytes)?,
+        };
+        Ok(vec![message])
     }
 }
 
@@ -111,33 +192,63 @@ impl Closer for Compress {}
 
 #[fiddler_registration_func]
 fn create_compress(conf: Value) -> Result<ExecutionType, Error> {
-    let mut proc = Compress::default();
     let c: CompressConfig = serde_yaml::from_value(conf)?;
-    if let Some(a) = c.algorithm {
-        proc.algorithm = a;
-    };
-    proc.method = Operation::Compress;
+    let algorithm = c.algorithm.unwrap_or_default();
+
+    if algorithm == Algorithm::Auto {
+        return Err(Error::ConfigFailedValidation(
+            "auto is only supported by decompress".into(),
+        ));
+    }
+
+    if let Some(level) = c.level {
+        match algorithm.levels() {
+            Some((min, max, _)) if (min..=max).contains(&level) => {}
+            Some((min, max, _)) => {
+                return Err(Error::ConfigFailedValidation(format!(
+                    "level must be between {min} and {max} for {algorithm:?}"
+                )))
+            }
+            None => {
+                return Err(Error::ConfigFailedValidation(format!(
+                    "{algorithm:?} does not support a compression level"
+                )))
+            }
+        }
+    }
 
-    Ok(ExecutionType::Processor(Box::new(proc)))
+    Ok(ExecutionType::Processor(Box::new(Compress {
+        algorithm,
+        method: Operation::Compress,
+        level: c.level,
+    })))
 }
 
 #[fiddler_registration_func]
 fn create_decompress(conf: Value) -> Result<ExecutionType, Error> {
-    let mut proc = Compress::default();
     let c: CompressConfig = serde_yaml::from_value(conf)?;
-    if let Some(a) = c.algorithm {
-        proc.algorithm = a;
-    };
-    proc.method = Operation::Decompress;
+    if c.level.is_some() {
+        return Err(Error::ConfigFailedValidation(
+            "level is only supported by compress".into(),
+        ));
+    }
 
-    Ok(ExecutionType::Processor(Box::new(proc)))
+    Ok(ExecutionType::Processor(Box::new(Compress {
+        algorithm: c.algorithm.unwrap_or_default(),
+        method: Operation::Decompress,
+        level: None,
+    })))
 }
 
 pub(super) fn register_compress() -> Result<(), Error> {
     let config = "type: object
 properties:
-  algorithm: 
-    type: string";
+  algorithm:
+    type: string
+    enum: [\"gzip\", \"deflate\", \"zlib\", \"zstd\", \"lz4\", \"snappy\", \"bzip2\", \"xz\", \"auto\", \"Gzip\", \"Zip\", \"zip\", \"Zlib\"]
+  level:
+    type: integer
+additionalProperties: false";
     let conf_spec = ConfigSpec::from_schema(config)?;
 
     register_plugin(
@@ -176,8 +287,9 @@ mod test {
             ..Default::default()
         };
         let processor = Compress {
-            algorithm: Algorithm::Zip,
+            algorithm: Algorithm::Deflate,
             method: Operation::Compress,
+            level: None,
         };
         let output = processor.process(msg).await.unwrap();
         let expected = BASE64_STANDARD.decode(DEFLATED_HELLO).unwrap();
@@ -197,8 +309,9 @@ mod test {
             ..Default::default()
         };
         let processor = Compress {
-            algorithm: Algorithm::Zip,
+            algorithm: Algorithm::Deflate,
             method: Operation::Decompress,
+            level: None,
         };
         let output = processor.process(msg).await.unwrap();
         let expected_str = "Hello World";
@@ -221,6 +334,7 @@ mod test {
         let processor = Compress {
             algorithm: Algorithm::Gzip,
             method: Operation::Compress,
+            level: None,
         };
         let output = processor.process(msg).await.unwrap();
         let expected = BASE64_STANDARD.decode(GZIPPED_HELLO).unwrap();
@@ -242,6 +356,7 @@ mod test {
         let processor = Compress {
             algorithm: Algorithm::Gzip,
             method: Operation::Decompress,
+            level: None,
         };
         let output = processor.process(msg).await.unwrap();
         let expected_str = "Hello World";
@@ -264,6 +379,7 @@ mod test {
         let processor = Compress {
             algorithm: Algorithm::Zlib,
             method: Operation::Compress,
+            level: None,
         };
         let output = processor.process(msg).await.unwrap();
         let expected = BASE64_STANDARD.decode(ZLIB_HELLO).unwrap();
@@ -285,6 +401,7 @@ mod test {
         let processor = Compress {
             algorithm: Algorithm::Zlib,
             method: Operation::Decompress,
+            level: None,
         };
         let output = processor.process(msg).await.unwrap();
         let expected_str = "Hello World";
@@ -296,4 +413,89 @@ mod test {
             },]
         )
     }
+
+    #[test]
+    fn round_trip() {
+        let input = "Hello World ".repeat(100).into_bytes();
+        for algorithm in [
+            Algorithm::Gzip,
+            Algorithm::Deflate,
+            Algorithm::Zlib,
+            Algorithm::Zstd,
+            Algorithm::Lz4,
+            Algorithm::Snappy,
+            Algorithm::Bzip2,
+            Algorithm::Xz,
+        ] {
+            let level = algorithm
+                .levels()
+                .map(|(_, max, _)| max)
+                .unwrap_or_default();
+            let compressed = compress(algorithm, level, &input).unwrap();
+            assert!(compressed.len() < input.len(), "{algorithm:?}");
+            assert_eq!(
+                decompress(algorithm, compressed.clone()).unwrap(),
+                input,
+                "{algorithm:?}"
+            );
+
+            if algorithm != Algorithm::Deflate {
+                assert_eq!(Algorithm::detect(&compressed), Some(algorithm));
+                assert_eq!(decompress(Algorithm::Auto, compressed).unwrap(), input);
+            }
+        }
+
+        assert_eq!(Algorithm::detect(b"Hello World"), None);
+        assert_eq!(
+            decompress(Algorithm::Auto, b"Hello World".to_vec()).unwrap(),
+            b"Hello World"
+        );
+
+        // Plain text with a valid zlib header checksum
+        assert_eq!(Algorithm::detect(b"80 errors"), Some(Algorithm::Zlib));
+        assert_eq!(
+            decompress(Algorithm::Auto, b"80 errors".to_vec()).unwrap(),
+            b"80 errors"
+        );
+        assert!(decompress(Algorithm::Zlib, b"80 errors".to_vec()).is_err());
+    }
+
+    #[test]
+    fn multi_member() {
+        for algorithm in [
+            Algorithm::Gzip,
+            Algorithm::Zstd,
+            Algorithm::Bzip2,
+            Algorithm::Xz,
+        ] {
+            let level = algorithm.levels().unwrap().2;
+            let mut data = compress(algorithm, level, b"Hello ").unwrap();
+            data.extend(compress(algorithm, level, b"World").unwrap());
+            assert_eq!(
+                decompress(Algorithm::Auto, data).unwrap(),
+                b"Hello World",
+                "{algorithm:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn config() {
+        for (conf, valid) in [
+            ("algorithm: Zip", true),
+            ("algorithm: zstd\nlevel: 19", true),
+            ("algorithm: zstd\nlevel: 23", false),
+            ("algorithm: snappy\nlevel: 1", false),
+            ("algorithm: auto", false),
+            ("algorithm: brotli", false),
+        ] {
+            let value: Value = serde_yaml::from_str(conf).unwrap();
+            assert_eq!(create_compress(value).await.is_ok(), valid, "{conf}");
+        }
+
+        let value: Value = serde_yaml::from_str("algorithm: auto").unwrap();
+        assert!(create_decompress(value).await.is_ok());
+        let value: Value = serde_yaml::from_str("level: 1").unwrap();
+        assert!(create_decompress(value).await.is_err());
+    }
 }
@@ -0,0 +1,518 @@
+//! CSV and TSV parsing and serialization processors.
+//!
+//! `csv_decode` turns a delimited payload into one JSON object per row, and
+//! `csv_encode` turns JSON objects back into delimited rows.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - csv_decode:
+//!       header: true          # Optional: first row holds column names (default: true)
+//!       columns: [a, b, c]    # Optional: explicit column names
+//!       delimiter: ","        # Optional: single character (default: ",")
+//!       quote: "\""           # Optional: single character (default: "\"")
+//!       escape: "\\"          # Optional: escape character (default: doubled quotes)
+//!       infer_types: true     # Optional: convert numbers, booleans and empty values (default: false)
+//!   - csv_encode:
+//!       columns: [a, b, c]    # Optional: column order (default: sorted union of object keys)
+//!       header: false         # Optional: emit a header row (default: false)
+//!       delimiter: "\t"
+//! ```
+
+use crate::config::register_plugin;
+use crate::config::ItemType;
+use crate::config::{ConfigSpec, ExecutionType};
+use crate::Message;
+use crate::MessageBatch;
+use crate::{Closer, Error, Processor};
+use async_trait::async_trait;
+use fiddler_macros::fiddler_registration_func;
+use serde::Deserialize;
+use serde_json::{Map, Value as JsonValue};
+use serde_yaml::Value;
+use std::collections::BTreeSet;
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_delimiter() -> String {
+    ",".into()
+}
+
+fn default_quote() -> String {
+    "\"".into()
+}
+
+#[derive(Deserialize)]
+struct CsvDecodeConfig {
+    #[serde(default = "default_true")]
+    header: bool,
+    columns: Option<Vec<String>>,
+    #[serde(default = "default_delimiter")]
+    delimiter: String,
+    #[serde(default = "default_quote")]
+    quote: String,
+    escape: Option<String>,
+    #[serde(default)]
+    infer_types: bool,
+}
+
+#[derive(Deserialize)]
+struct CsvEncodeConfig {
+    columns: Option<Vec<String>>,
+    #[serde(default)]
+    header: bool,
+    #[serde(default = "default_delimiter")]
+    delimiter: String,
+    #[serde(default = "default_quote")]
+    quote: String,
+}
+
+/// Convert a single-character configuration value into the byte used by the csv crate.
+fn single_byte(value: &str, field: &str) -> Result<u8, Error> {
+    match value.as_bytes() {
+        [b] => Ok(*b),
+        _ => Err(Error::ConfigFailedValidation(format!(
+            "{field} must be a single ASCII character, got '{value}'"
+        ))),
+    }
+}
+
+pub struct CsvDecode {
+    header: bool,
+    columns: Option<Vec<String>>,
+    delimiter: u8,
+    quote: u8,
+    escape: Option<u8>,
+    infer_types: bool,
+}
+
+impl Default for CsvDecode {
+    fn default() -> Self {
+        Self {
+            header: true,
+            columns: None,
+            delimiter: b',',
+            quote: b'"',
+            escape: None,
+            infer_types: false,
+        }
+    }
+}
+
+/// Convert a raw field into the most specific JSON type it represents.
+fn infer_value(field: &str) -> JsonValue {
+    if field.is_empty() {
+        return JsonValue::Null;
+    }
+    if field.eq_ignore_ascii_case("true") {
+        return JsonValue::Bool(true);
+    }
+    if field.eq_ignore_ascii_case("false") {
+        return JsonValue::Bool(false);
+    }
+    if let Ok(i) = field.parse::<i64>() {
+        return JsonValue::from(i);
+    }
+    if let Ok(f) = field.parse::<f64>() {
+        if f.is_finite() {
+            return JsonValue::from(f);
+        }
+    }
+    JsonValue::String(field.to_string())
+}
+
+#[async_trait]
+impl Processor for CsvDecode {
+    async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(self.header)
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .escape(self.escape)
+            .double_quote(self.escape.is_none())
+            .flexible(true)
+            .from_reader(&message.bytes[..]);
+
+        let columns: Vec<String> = match &self.columns {
+            Some(c) => c.clone(),
+            None if self.header => reader
+                .headers()
+                .map_err(|e| Error::MessageFailed(format!("{e}")))?
+                .iter()
+                .map(|h| h.to_string())
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let mut output = Vec::new();
+        for record in reader.records() {
+            let record = record.map_err(|e| Error::MessageFailed(format!("{e}")))?;
+            let mut row = Map::new();
+            for (i, field) in record.iter().enumerate() {
+                let key = match columns.get(i) {
+                    Some(c) => c.clone(),
+                    None => format!("column_{i}"),
+                };
+                let value = if self.infer_types {
+                    infer_value(field)
+                } else {
+                    JsonValue::String(field.to_string())
+                };
+                row.insert(key, value);
+            }
+
+            let bytes = serde_json::to_vec(&JsonValue::Object(row))
+                .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+            output.push(Message {
+                bytes,
+                metadata: message.metadata.clone(),
+                ..Default::default()
+            });
+        }
+
+        Ok(output)
+    }
+}
+
+impl Closer for CsvDecode {}
+
+pub struct CsvEncode {
+    columns: Option<Vec<String>>,
+    header: bool,
+    delimiter: u8,
+    quote: u8,
+}
+
+impl Default for CsvEncode {
+    fn default() -> Self {
+        Self {
+            columns: None,
+            header: false,
+            delimiter: b',',
+            quote: b'"',
+        }
+    }
+}
+
+/// Render a JSON value as a single CSV field.
+fn field_value(value: Option<&JsonValue>) -> Result<String, Error> {
+    match value {
+        None | Some(JsonValue::Null) => Ok(String::new()),
+        Some(JsonValue::String(s)) => Ok(s.clone()),
+        Some(JsonValue::Bool(b)) => Ok(b.to_string()),
+        Some(JsonValue::Number(n)) => Ok(n.to_string()),
+        Some(v) => serde_json::to_string(v).map_err(|e| Error::ProcessingError(format!("{e}"))),
+    }
+}
+
+#[async_trait]
+impl Processor for CsvEncode {
+    async fn process(&self, mut message: Message) -> Result<MessageBatch, Error> {
+        let parsed: JsonValue = serde_json::from_slice(&message.bytes)
+            .map_err(|e| Error::MessageFailed(format!("{e}")))?;
+
+        let rows: Vec<Map<String, JsonValue>> = match parsed {
+            JsonValue::Object(o) => vec![o],
+            JsonValue::Array(items) => items
+                .into_iter()
+                .map(|i| match i {
+                    JsonValue::Object(o) => Ok(o),
+                    _ => Err(Error::MessageFailed(
+                        "csv_encode requires an array of JSON objects".into(),
+                    )),
+                })
+                .collect::<Result<_, _>>()?,
+            _ => {
+                return Err(Error::MessageFailed(
+                    "csv_encode requires a JSON object or array of objects".into(),
+                ))
+            }
+        };
+
+        let columns: Vec<String> = match &self.columns {
+            Some(c) => c.clone(),
+            None => rows
+                .iter()
+                .flat_map(|r| r.keys().cloned())
+                .collect::<BTreeSet<String>>()
+                .into_iter()
+                .collect(),
+        };
+
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .from_writer(Vec::new());
+
+        if self.header {
+            writer
+                .write_record(&columns)
+                .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+        }
+
+        for row in &rows {
+            let fields = columns
+                .iter()
+                .map(|c| field_value(row.get(c)))
+                .collect::<Result<Vec<_>, _>>()?;
+            writer
+                .write_record(&fields)
+                .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+        }
+
+        let mut bytes = writer
+            .into_inner()
+            .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+        if bytes.last() == Some(&b'\n') {
+            bytes.pop();
+        }
+
+        message.bytes = bytes;
+        Ok(vec![message])
+    }
+}
+
+impl Closer for CsvEncode {}
+
+#[fiddler_registration_func]
+fn create_csv_decode(conf: Value) -> Result<ExecutionType, Error> {
+    let c: CsvDecodeConfig = serde_yaml::from_value(conf)?;
+    let escape = match &c.escape {
+        Some(e) => Some(single_byte(e, "escape")?),
+        None => None,
+    };
+
+    Ok(ExecutionType::Processor(Box::new(CsvDecode {
+        header: c.header,
+        columns: c.columns,
+        delimiter: single_byte(&c.delimiter, "delimiter")?,
+        quote: single_byte(&c.quote, "quote")?,
+        escape,
+        infer_types: c.infer_types,
+    })))
+}
+
+#[fiddler_registration_func]
+fn create_csv_encode(conf: Value) -> Result<ExecutionType, Error> {
+    let c: CsvEncodeConfig = serde_yaml::from_value(conf)?;
+
+    Ok(ExecutionType::Processor(Box::new(CsvEncode {
+        columns: c.columns,
+        header: c.header,
+        delimiter: single_byte(&c.delimiter, "delimiter")?,
+        quote: single_byte(&c.quote, "quote")?,
+    })))
+}
+
+pub(super) fn register_csv() -> Result<(), Error> {
+    let decode_config = "type: object
+properties:
+  header:
+    type: boolean
+  columns:
+    type: array
+    items:
+      type: string
+  delimiter:
+    type: string
+  quote:
+    type: string
+  escape:
+    type: string
+  infer_types:
+    type: boolean";
+    let decode_spec = ConfigSpec::from_schema(decode_config)?;
+
+    let encode_config = "type: object
+properties:
+  header:
+    type: boolean
+  columns:
+    type: array
+    items:
+      type: string
+  delimiter:
+    type: string
+  quote:
+    type: string";
+    let encode_spec = ConfigSpec::from_schema(encode_config)?;
+
+    register_plugin(
+        "csv_decode".into(),
+        ItemType::Processor,
+        decode_spec,
+        create_csv_decode,
+    )?;
+    register_plugin(
+        "csv_encode".into(),
+        ItemType::Processor,
+        encode_spec,
+        create_csv_encode,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn register_plugin() {
+        register_csv().unwrap()
+    }
+
+    fn parse(messages: &[Message]) -> Vec<JsonValue> {
+        messages
+            .iter()
+            .map(|m| serde_json::from_slice(&m.bytes).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn decode_with_header() {
+        let msg = Message {
+            bytes: b"name,age\nalice,30\nbob,25\n".to_vec(),
+            ..Default::default()
+        };
+        let output = CsvDecode::default().process(msg).await.unwrap();
+        assert_eq!(
+            parse(&output),
+            vec![
+                json!({"name": "alice", "age": "30"}),
+                json!({"name": "bob", "age": "25"})
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn decode_explicit_columns_without_header() {
+        let processor = CsvDecode {
+            header: false,
+            columns: Some(vec!["host".into(), "count".into()]),
+            ..Default::default()
+        };
+        let msg = Message {
+            bytes: b"web-1,10\nweb-2,20,extra".to_vec(),
+            ..Default::default()
+        };
+        let output = processor.process(msg).await.unwrap();
+        assert_eq!(
+            parse(&output),
+            vec![
+                json!({"host": "web-1", "count": "10"}),
+                json!({"host": "web-2", "count": "20", "column_2": "extra"})
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn decode_tsv_with_type_inference() {
+        let processor = CsvDecode {
+            delimiter: b'\t',
+            infer_types: true,
+            ..Default::default()
+        };
+        let msg = Message {
+            bytes: b"id\tratio\tactive\tnote\n7\t0.5\tTRUE\t\n".to_vec(),
+            ..Default::default()
+        };
+        let output = processor.process(msg).await.unwrap();
+        assert_eq!(
+            parse(&output),
+            vec![json!({"id": 7, "ratio": 0.5, "active": true, "note": null})]
+        );
+    }
+
+    #[tokio::test]
+    async fn decode_quoted_and_escaped_fields() {
+        let processor = CsvDecode {
+            header: false,
+            escape: Some(b'\\'),
+            ..Default::default()
+        };
+        let msg = Message {
+            bytes: br#""a, b","say \"hi\"""#.to_vec(),
+            ..Default::default()
+        };
+        let output = processor.process(msg).await.unwrap();
+        assert_eq!(
+            parse(&output),
+            vec![json!({"column_0": "a, b", "column_1": "say \"hi\""})]
+        );
+    }
+
+    #[tokio::test]
+    async fn decode_preserves_metadata() {
+        let mut metadata = std::collections::HashMap::new();
+        metadata.insert("source".to_string(), Value::String("s3".into()));
+        let msg = Message {
+            bytes: b"a\n1".to_vec(),
+            metadata: metadata.clone(),
+            ..Default::default()
+        };
+        let output = CsvDecode::default().process(msg).await.unwrap();
+        assert_eq!(output[0].metadata, metadata);
+    }
+
+    #[tokio::test]
+    async fn decode_invalid_utf8_fails_message() {
+        let msg = Message {
+            bytes: b"name,age\n\xff,30".to_vec(),
+            ..Default::default()
+        };
+        let result = CsvDecode::default().process(msg).await;
+        assert!(matches!(result, Err(Error::MessageFailed(_))));
+    }
+
+    #[tokio::test]
+    async fn encode_sorted_columns() {
+        let msg = Message {
+            bytes: br#"{"name": "alice, jr", "age": 30, "tags": ["a"], "none": null}"#.to_vec(),
+            ..Default::default()
+        };
+        let output = CsvEncode::default().process(msg).await.unwrap();
+        assert_eq!(
+            String::from_utf8(output[0].bytes.clone()).unwrap(),
+            r#"30,"alice, jr",,"[""a""]""#
+        );
+    }
+
+    #[tokio::test]
+    async fn encode_array_with_header_and_columns() {
+        let processor = CsvEncode {
+            columns: Some(vec!["name".into(), "age".into()]),
+            header: true,
+            delimiter: b'\t',
+            ..Default::default()
+        };
+        let msg = Message {
+            bytes: br#"[{"name": "alice", "age": 30}, {"name": "bob"}]"#.to_vec(),
+            ..Default::default()
+        };
+        let output = processor.process(msg).await.unwrap();
+        assert_eq!(
+            String::from_utf8(output[0].bytes.clone()).unwrap(),
+            "name\tage\nalice\t30\nbob\t"
+        );
+    }
+
+    #[tokio::test]
+    async fn encode_rejects_scalars() {
+        let msg = Message {
+            bytes: b"42".to_vec(),
+            ..Default::default()
+        };
+        let result = CsvEncode::default().process(msg).await;
+        assert!(matches!(result, Err(Error::MessageFailed(_))));
+    }
+
+    #[tokio::test]
+    async fn invalid_delimiter_rejected() {
+        let conf: Value = serde_yaml::from_str("delimiter: '||'").unwrap();
+        let result = create_csv_decode(conf).await;
+        assert!(matches!(result, Err(Error::ConfigFailedValidation(_))));
+    }
+}
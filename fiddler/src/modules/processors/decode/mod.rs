@@ -1,65 +1,202 @@
+//! Encode and decode processors for text-safe binary encodings.
+//!
+//! Both processors act on the whole message, or on a string field of a JSON
+//! message when `field` is set.  Decoded field values must be valid UTF-8.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - decode:
+//!       algorithm: base64_url_nopad  # Optional: default base64
+//!       field: "payload"             # Optional: field to decode instead of the whole message
+//!       target: "decoded"            # Optional: field for the result (default: field)
+//! ```
+
 use crate::config::register_plugin;
 use crate::config::ItemType;
 use crate::config::{ConfigSpec, ExecutionType};
+use crate::modules::processors::path::{get_path, parse_path, set_path, PERCENT_ENCODE_SET};
 use crate::Message;
 use crate::MessageBatch;
 use crate::{Closer, Error, Processor};
 use async_trait::async_trait;
-use base64::{prelude::BASE64_STANDARD, Engine};
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD};
+use base64::Engine;
+use data_encoding::{BASE32, BASE32_NOPAD, HEXLOWER, HEXLOWER_PERMISSIVE};
 use fiddler_macros::fiddler_registration_func;
+use percent_encoding::{percent_decode, percent_encode};
 use serde::Deserialize;
+use serde_json::Value as JsonValue;
 use serde_yaml::Value;
 
 #[derive(Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct DecoderConfig {
-    algorithm: Option<Algoritym>,
+    algorithm: Option<Algorithm>,
+    field: Option<String>,
+    target: Option<String>,
 }
 
-#[derive(Clone, Default, Deserialize)]
-pub enum Algoritym {
+#[derive(Clone, Copy, Default, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Algorithm {
     #[default]
+    #[serde(alias = "Base64")]
     Base64,
+    Base64Nopad,
+    Base64Url,
+    Base64UrlNopad,
+    Base32,
+    Base32Nopad,
+    Hex,
+    Percent,
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+enum Operation {
+    Encode,
+    #[default]
+    Decode,
+}
+
+impl Algorithm {
+    fn encode(&self, input: &[u8]) -> String {
+        match self {
+            Algorithm::Base64 => STANDARD.encode(input),
+            Algorithm::Base64Nopad => STANDARD_NO_PAD.encode(input),
+            Algorithm::Base64Url => URL_SAFE.encode(input),
+            Algorithm::Base64UrlNopad => URL_SAFE_NO_PAD.encode(input),
+            Algorithm::Base32 => BASE32.encode(input),
+            Algorithm::Base32Nopad => BASE32_NOPAD.encode(input),
+            Algorithm::Hex => HEXLOWER.encode(input),
+            Algorithm::Percent => percent_encode(input, PERCENT_ENCODE_SET).to_string(),
+        }
+    }
+
+    fn decode(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+        let input = input.trim_ascii();
+        let result = match self {
+            Algorithm::Base64 => STANDARD.decode(input).map_err(|e| format!("{e}")),
+            Algorithm::Base64Nopad => STANDARD_NO_PAD.decode(input).map_err(|e| format!("{e}")),
+            Algorithm::Base64Url => URL_SAFE.decode(input).map_err(|e| format!("{e}")),
+            Algorithm::Base64UrlNopad => URL_SAFE_NO_PAD.decode(input).map_err(|e| format!("{e}")),
+            Algorithm::Base32 => BASE32.decode(input).map_err(|e| format!("{e}")),
+            Algorithm::Base32Nopad => BASE32_NOPAD.decode(input).map_err(|e| format!("{e}")),
+            Algorithm::Hex => HEXLOWER_PERMISSIVE
+                .decode(input)
+                .map_err(|e| format!("{e}")),
+            Algorithm::Percent => Ok(percent_decode(input).collect()),
+        };
+        result.map_err(Error::MessageFailed)
+    }
 }
 
 #[derive(Clone, Default)]
 pub struct Decoder {
-    algorithm: Algoritym,
+    algorithm: Algorithm,
+    operation: Operation,
+    field: Option<Vec<String>>,
+    target: Option<Vec<String>>,
+}
+
+impl Decoder {
+    fn apply(&self, input: &[u8]) -> Result<Vec<u8>, Error> {
+        match self.operation {
+            Operation::Encode => Ok(self.algorithm.encode(input).into_bytes()),
+            Operation::Decode => self.algorithm.decode(input),
+        }
+    }
+
+    fn apply_field(&self, field: &[String], bytes: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut doc: JsonValue =
+            serde_json::from_slice(bytes).map_err(|e| Error::MessageFailed(format!("{e}")))?;
+
+        let input = match get_path(&doc, field) {
+            Some(JsonValue::String(s)) => s.clone().into_bytes(),
+            // Encoding a non-string value encodes its JSON form
+            Some(other) if self.operation == Operation::Encode => other.to_string().into_bytes(),
+            Some(_) => {
+                return Err(Error::MessageFailed(format!(
+                    "field '{}' is not a string",
+                    field.join(".")
+                )))
+            }
+            None => {
+                return Err(Error::MessageFailed(format!(
+                    "field '{}' not found",
+                    field.join(".")
+                )))
+            }
+        };
+
+        let output = String::from_utf8(self.apply(&input)?)
+            .map_err(|e| Error::MessageFailed(format!("{e}")))?;
+        let target = self.target.as_deref().unwrap_or(field);
+        set_path(&mut doc, target, JsonValue::String(output))?;
+
+        serde_json::to_vec(&doc).map_err(|e| Error::MessageFailed(format!("{e}")))
+    }
 }
 
 #[async_trait]
 impl Processor for Decoder {
     async fn process(&self, mut message: Message) -> Result<MessageBatch, Error> {
-        match self.algorithm {
-            Algoritym::Base64 => {
-                let content = String::from_utf8(message.bytes)
-                    .map_err(|e| Error::ProcessingError(format!("{e}")))?;
-                let result = BASE64_STANDARD
-                    .decode(content)
-                    .map_err(|e| Error::ProcessingError(format!("{e}")))?;
-
-                message.bytes = result;
-                Ok(vec![message])
-            }
-        }
+        message.bytes = match &self.field {
+            Some(field) => self.apply_field(field, &message.bytes)?,
+            None => self.apply(&message.bytes)?,
+        };
+        Ok(vec![message])
     }
 }
 
 impl Closer for Decoder {}
 
+fn build(conf: Value, operation: Operation) -> Result<ExecutionType, Error> {
+    let c: DecoderConfig = serde_yaml::from_value(conf)?;
+    if c.target.is_some() && c.field.is_none() {
+        return Err(Error::ConfigFailedValidation(
+            "target requires field to be set".into(),
+        ));
+    }
+
+    Ok(ExecutionType::Processor(Box::new(Decoder {
+        algorithm: c.algorithm.unwrap_or_default(),
+        operation,
+        field: c.field.as_deref().map(parse_path).transpose()?,
+        target: c.target.as_deref().map(parse_path).transpose()?,
+    })))
+}
+
+#[fiddler_registration_func]
+fn create_encode(conf: Value) -> Result<ExecutionType, Error> {
+    build(conf, Operation::Encode)
+}
+
 #[fiddler_registration_func]
 fn create_decode(conf: Value) -> Result<ExecutionType, Error> {
-    let mut proc = Decoder::default();
-    let c: DecoderConfig = serde_yaml::from_value(conf)?;
-    if let Some(a) = c.algorithm {
-        proc.algorithm = a;
-    };
-    Ok(ExecutionType::Processor(Box::new(proc)))
+    build(conf, Operation::Decode)
 }
 
 pub(super) fn register_decode() -> Result<(), Error> {
-    let config = "type: object";
+    let config = "type: object
+properties:
+  algorithm:
+    type: string
+    enum: [\"base64\", \"Base64\", \"base64_nopad\", \"base64_url\", \"base64_url_nopad\", \"base32\", \"base32_nopad\", \"hex\", \"percent\"]
+  field:
+    type: string
+  target:
+    type: string
+additionalProperties: false";
     let conf_spec = ConfigSpec::from_schema(config)?;
 
+    register_plugin(
+        "encode".into(),
+        ItemType::Processor,
+        conf_spec.clone(),
+        create_encode,
+    )?;
     register_plugin(
         "decode".into(),
         ItemType::Processor,
@@ -94,4 +231,104 @@ mod test {
             },]
         )
     }
+
+    #[test]
+    fn round_trip() {
+        let input = b"Hello World?/~\xff";
+        for (algorithm, encoded) in [
+            (Algorithm::Base64, "SGVsbG8gV29ybGQ/L37/"),
+            (Algorithm::Base64Nopad, "SGVsbG8gV29ybGQ/L37/"),
+            (Algorithm::Base64Url, "SGVsbG8gV29ybGQ_L37_"),
+            (Algorithm::Base64UrlNopad, "SGVsbG8gV29ybGQ_L37_"),
+            (Algorithm::Base32, "JBSWY3DPEBLW64TMMQ7S67X7"),
+            (Algorithm::Base32Nopad, "JBSWY3DPEBLW64TMMQ7S67X7"),
+            (Algorithm::Hex, "48656c6c6f20576f726c643f2f7eff"),
+            (Algorithm::Percent, "Hello%20World%3F%2F~%FF"),
+        ] {
+            assert_eq!(algorithm.encode(input), encoded, "{algorithm:?}");
+            assert_eq!(
+                algorithm.decode(encoded.as_bytes()).unwrap(),
+                input,
+                "{algorithm:?}"
+            );
+        }
+
+        assert_eq!(Algorithm::Base64.encode(b"a"), "YQ==");
+        assert_eq!(Algorithm::Base64Nopad.encode(b"a"), "YQ");
+        assert_eq!(Algorithm::Base32.encode(b"a"), "ME======");
+        assert_eq!(Algorithm::Base32Nopad.encode(b"a"), "ME");
+        assert_eq!(Algorithm::Hex.decode(b"4A4b\n").unwrap(), b"JK");
+        assert!(matches!(
+            Algorithm::Base64.decode(b"YQ"),
+            Err(Error::MessageFailed(_))
+        ));
+        assert!(Algorithm::Hex.decode(b"zz").is_err());
+    }
+
+    #[tokio::test]
+    async fn field_encode_and_decode() {
+        let value: Value = serde_yaml::from_str("algorithm: hex\nfield: data.raw").unwrap();
+        let encoder = match create_encode(value.clone()).await.unwrap() {
+            ExecutionType::Processor(p) => p,
+            _ => panic!("expected processor"),
+        };
+        let decoder = match create_decode(value).await.unwrap() {
+            ExecutionType::Processor(p) => p,
+            _ => panic!("expected processor"),
+        };
+
+        let msg = Message {
+            bytes: br#"{"data":{"raw":"hi"},"other":1}"#.to_vec(),
+            ..Default::default()
+        };
+        let encoded = encoder.process(msg).await.unwrap().remove(0);
+        assert_eq!(
+            encoded.bytes,
+            br#"{"data":{"raw":"6869"},"other":1}"#.to_vec()
+        );
+
+        let decoded = decoder.process(encoded).await.unwrap().remove(0);
+        assert_eq!(
+            decoded.bytes,
+            br#"{"data":{"raw":"hi"},"other":1}"#.to_vec()
+        );
+
+        let msg = Message {
+            bytes: br#"{"data":{"raw":"ff"}}"#.to_vec(),
+            ..Default::default()
+        };
+        assert!(decoder.process(msg).await.is_err());
+
+        for bytes in [
+            &br#"{"data":{}}"#[..],
+            br#"{"data":{"raw":1}}"#,
+            b"not json",
+        ] {
+            let msg = Message {
+                bytes: bytes.to_vec(),
+                ..Default::default()
+            };
+            let result = decoder.process(msg).await;
+            assert!(matches!(result, Err(Error::MessageFailed(_))), "{result:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn legacy_algorithm_name() {
+        let value: Value = serde_yaml::from_str("algorithm: Base64").unwrap();
+        assert!(create_decode(value).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn invalid_config() {
+        for conf in [
+            "algorithm: base58",
+            "algoritm: hex",
+            "target: out",
+            "field: 'a..b'",
+        ] {
+            let value: Value = serde_yaml::from_str(conf).unwrap();
+            assert!(create_decode(value).await.is_err(), "{conf}");
+        }
+    }
 }
@@ -0,0 +1,342 @@
+//! Dedupe processor for dropping messages that were already seen.
+//!
+//! The deduplication key is the result of a JMESPath expression or, when no
+//! expression is configured, the SHA-256 hash of the message body.  Seen keys
+//! are kept in a bounded LRU cache and optionally in Redis, so duplicates are
+//! detected across multiple fiddler instances.  Duplicates are reported as
+//! filtered.  A message whose key is missing, or that cannot be recorded in
+//! Redis, fails on its own so the input can redeliver it.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - dedupe:
+//!       key: "event_id"                     # Optional: JMESPath expression (default: content hash)
+//!       ttl: 10m                            # Optional: how long a key is remembered
+//!       cache_size: 100000                  # Optional: maximum keys held in memory
+//!       redis:                              # Optional: shared store, requires the redis feature
+//!         url: "redis://localhost:6379/0"
+//!         prefix: "fiddler:dedupe:"
+//! ```
+
+use super::path::{variable, Expression};
+use crate::config::register_plugin;
+use crate::config::ItemType;
+use crate::config::{ConfigSpec, ExecutionType};
+#[cfg(feature = "redis")]
+use crate::modules::redis::connection_manager;
+use crate::Message;
+use crate::MessageBatch;
+use crate::{Closer, Error, Processor};
+use async_trait::async_trait;
+use fiddler_macros::fiddler_registration_func;
+use lru::LruCache;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use serde_yaml::Value;
+use sha2::{Digest, Sha256};
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+fn default_cache_size() -> usize {
+    100_000
+}
+
+fn default_prefix() -> String {
+    "fiddler:dedupe:".into()
+}
+
+/// Redis store shared between fiddler instances
+#[derive(Deserialize, Clone)]
+#[cfg_attr(not(feature = "redis"), allow(dead_code))]
+struct RedisStoreConfig {
+    url: String,
+    #[serde(default = "default_prefix")]
+    prefix: String,
+}
+
+#[derive(Deserialize)]
+struct DedupeConfig {
+    key: Option<String>,
+    #[serde(default, deserialize_with = "crate::deserialize_optional_duration")]
+    ttl: Option<Duration>,
+    #[serde(default = "default_cache_size")]
+    cache_size: usize,
+    redis: Option<RedisStoreConfig>,
+}
+
+#[cfg(feature = "redis")]
+struct RedisStore {
+    conn: redis::aio::ConnectionManager,
+    prefix: String,
+}
+
+#[cfg(feature = "redis")]
+impl RedisStore {
+    async fn new(config: RedisStoreConfig) -> Result<Self, Error> {
+        let conn = connection_manager(&config.url).await?;
+        Ok(RedisStore {
+            conn,
+            prefix: config.prefix,
+        })
+    }
+
+    /// Record the key, returning `true` if it was not present yet
+    async fn insert(&self, key: &str, ttl: Option<Duration>) -> Result<bool, Error> {
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(format!("{}{key}", self.prefix)).arg(1).arg("NX");
+        if let Some(ttl) = ttl {
+            cmd.arg("PX").arg(ttl.as_millis().max(1) as u64);
+        }
+        let mut conn = self.conn.clone();
+        let result: Option<String> = cmd
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| Error::MessageFailed(format!("Redis dedupe lookup failed: {e}")))?;
+        Ok(result.is_some())
+    }
+}
+
+pub struct Dedupe {
+    key: Option<Expression>,
+    ttl: Option<Duration>,
+    cache: Mutex<LruCache<String, Instant>>,
+    #[cfg(feature = "redis")]
+    store: Option<RedisStore>,
+}
+
+impl Dedupe {
+    fn dedupe_key(&self, message: &Message) -> Result<String, Error> {
+        let Some(expression) = &self.key else {
+            return Ok(format!("{:x}", Sha256::digest(&message.bytes)));
+        };
+
+        match expression.search(&variable(&message.bytes)?)? {
+            JsonValue::Null => Err(Error::MessageFailed(format!(
+                "dedupe key '{expression}' returned null"
+            ))),
+            JsonValue::String(s) => Ok(s),
+            other => Ok(other.to_string()),
+        }
+    }
+
+    /// Record the key in the local cache, returning `true` if it was not seen within the TTL
+    fn insert_local(&self, key: &str) -> Result<bool, Error> {
+        let mut cache = self
+            .cache
+            .lock()
+            .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+
+        let seen = cache
+            .peek(key)
+            .is_some_and(|inserted| self.ttl.is_none_or(|ttl| inserted.elapsed() < ttl));
+        if seen {
+            return Ok(false);
+        }
+        cache.put(key.to_string(), Instant::now());
+        Ok(true)
+    }
+
+    /// Forget a key recorded by [`Dedupe::insert_local`]
+    #[cfg(feature = "redis")]
+    fn remove_local(&self, key: &str) -> Result<(), Error> {
+        self.cache
+            .lock()
+            .map_err(|e| Error::ProcessingError(format!("{e}")))?
+            .pop(key);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Processor for Dedupe {
+    async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
+        let key = self.dedupe_key(&message)?;
+
+        if !self.insert_local(&key)? {
+            debug!(key = key, "dropping duplicate message");
+            return Ok(Vec::new());
+        }
+
+        #[cfg(feature = "redis")]
+        if let Some(store) = &self.store {
+            // A failed write must not leave the key cached, or the message would
+            // be dropped as a duplicate when the input redelivers it
+            let inserted = match store.insert(&key, self.ttl).await {
+                Ok(inserted) => inserted,
+                Err(e) => {
+                    self.remove_local(&key)?;
+                    return Err(e);
+                }
+            };
+            if !inserted {
+                debug!(
+                    key = key,
+                    "dropping duplicate message seen by another instance"
+                );
+                return Ok(Vec::new());
+            }
+        }
+
+        Ok(vec![message])
+    }
+}
+
+impl Closer for Dedupe {}
+
+#[fiddler_registration_func]
+fn create_dedupe(conf: Value) -> Result<ExecutionType, Error> {
+    let c: DedupeConfig = serde_yaml::from_value(conf)?;
+    let key = c.key.as_deref().map(Expression::compile).transpose()?;
+
+    let cache_size = NonZeroUsize::new(c.cache_size)
+        .ok_or_else(|| Error::ConfigFailedValidation("cache_size must be greater than 0".into()))?;
+
+    #[cfg(feature = "redis")]
+    let store = match c.redis {
+        Some(r) => Some(RedisStore::new(r).await?),
+        None => None,
+    };
+
+    #[cfg(not(feature = "redis"))]
+    if c.redis.is_some() {
+        return Err(Error::ConfigFailedValidation(
+            "the redis store requires fiddler to be built with the redis feature".into(),
+        ));
+    }
+
+    Ok(ExecutionType::Processor(Box::new(Dedupe {
+        key,
+        ttl: c.ttl,
+        cache: Mutex::new(LruCache::new(cache_size)),
+        #[cfg(feature = "redis")]
+        store,
+    })))
+}
+
+pub(super) fn register_dedupe() -> Result<(), Error> {
+    let config = "type: object
+properties:
+  key:
+    type: string
+  ttl:
+    type: string
+  cache_size:
+    type: integer
+    minimum: 1
+  redis:
+    type: object
+    properties:
+      url:
+        type: string
+      prefix:
+        type: string
+    required:
+      - url";
+    let conf_spec = ConfigSpec::from_schema(config)?;
+
+    register_plugin(
+        "dedupe".into(),
+        ItemType::Processor,
+        conf_spec,
+        create_dedupe,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn register_plugin() {
+        register_dedupe().unwrap()
+    }
+
+    fn processor(key: Option<&str>, ttl: Option<Duration>, cache_size: usize) -> Dedupe {
+        Dedupe {
+            key: key.map(|k| Expression::compile(k).unwrap()),
+            ttl,
+            cache: Mutex::new(LruCache::new(NonZeroUsize::new(cache_size).unwrap())),
+            #[cfg(feature = "redis")]
+            store: None,
+        }
+    }
+
+    fn message(body: &str) -> Message {
+        Message {
+            bytes: body.as_bytes().to_vec(),
+            ..Default::default()
+        }
+    }
+
+    async fn passed(p: &Dedupe, body: &str) -> bool {
+        !p.process(message(body)).await.unwrap().is_empty()
+    }
+
+    #[tokio::test]
+    async fn content_hash() {
+        let p = processor(None, None, 10);
+        assert!(passed(&p, "hello").await);
+        assert!(!passed(&p, "hello").await);
+        assert!(passed(&p, "world").await);
+    }
+
+    #[tokio::test]
+    async fn jmespath_key() {
+        let p = processor(Some("id"), None, 10);
+        assert!(passed(&p, r#"{"id": "a", "attempt": 1}"#).await);
+        assert!(!passed(&p, r#"{"id": "a", "attempt": 2}"#).await);
+        assert!(passed(&p, r#"{"id": "b", "attempt": 1}"#).await);
+        assert!(passed(&p, r#"{"id": 1}"#).await);
+        assert!(!passed(&p, r#"{"id": 1}"#).await);
+    }
+
+    #[tokio::test]
+    async fn missing_key_fails() {
+        let p = processor(Some("id"), None, 10);
+        for body in [r#"{"other": 1}"#, "not json"] {
+            let result = p.process(message(body)).await;
+            assert!(matches!(result, Err(Error::MessageFailed(_))), "{body}");
+        }
+    }
+
+    #[tokio::test]
+    async fn ttl_expiry() {
+        let p = processor(None, Some(Duration::from_millis(20)), 10);
+        assert!(passed(&p, "hello").await);
+        assert!(!passed(&p, "hello").await);
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(passed(&p, "hello").await);
+    }
+
+    #[tokio::test]
+    async fn lru_eviction() {
+        let p = processor(None, None, 2);
+        assert!(passed(&p, "a").await);
+        assert!(passed(&p, "b").await);
+        assert!(passed(&p, "c").await);
+        // "a" was evicted to make room for "c"
+        assert!(passed(&p, "a").await);
+        assert!(!passed(&p, "c").await);
+    }
+
+    #[cfg(feature = "redis")]
+    #[tokio::test]
+    async fn rolled_back_key_passes_again() {
+        let p = processor(None, None, 10);
+        assert!(p.insert_local("a").unwrap());
+        p.remove_local("a").unwrap();
+        assert!(passed(&p, "a").await);
+    }
+
+    #[tokio::test]
+    async fn invalid_config() {
+        for conf in ["key: '[['", "cache_size: 0"] {
+            let value: Value = serde_yaml::from_str(conf).unwrap();
+            assert!(create_dedupe(value).await.is_err(), "{conf}");
+        }
+    }
+}
@@ -0,0 +1,546 @@
+//! Enrich processor for joining messages against a local lookup table.
+//!
+//! The table is loaded from a CSV, JSON or NDJSON file into memory and indexed
+//! by `key_field`.  For every message the `key` JMESPath expression selects the
+//! value to join on and the matching row is merged into the message body or
+//! metadata.  The file is reloaded when its modification time changes.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - enrich:
+//!       path: "/etc/fiddler/assets.csv"  # Required: lookup table file
+//!       format: csv                      # Optional: csv, json or ndjson (default: from extension)
+//!       key_field: "ip"                  # Required: table field holding the join key
+//!       key: "source.ip"                 # Required: JMESPath expression selecting the message key
+//!       destination: body                # Optional: body or metadata (default: body)
+//!       target: "asset"                  # Optional: path or metadata key receiving the row
+//!       default:                         # Optional: row used when no match is found
+//!         owner: unknown
+//!       reload_interval: 10s             # Optional: how often the file is checked for changes
+//! ```
+
+use super::path::{parse_path, set_path, variable, Expression};
+use crate::config::register_plugin;
+use crate::config::ItemType;
+use crate::config::{ConfigSpec, ExecutionType};
+use crate::Message;
+use crate::MessageBatch;
+use crate::{Closer, Error, Processor};
+use async_trait::async_trait;
+use fiddler_macros::fiddler_registration_func;
+use serde::Deserialize;
+use serde_json::{Map, Value as JsonValue};
+use serde_yaml::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, warn};
+
+fn default_reload_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
+/// File format of the lookup table
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TableFormat {
+    /// Comma separated values with a header row
+    Csv,
+    /// An array of objects, or an object mapping keys to rows
+    Json,
+    /// One JSON object per line
+    Ndjson,
+}
+
+/// Where matched rows are written
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Destination {
+    #[default]
+    Body,
+    Metadata,
+}
+
+#[derive(Deserialize)]
+struct EnrichConfig {
+    path: String,
+    format: Option<TableFormat>,
+    key_field: String,
+    key: String,
+    #[serde(default)]
+    destination: Destination,
+    target: Option<String>,
+    default: Option<JsonValue>,
+    #[serde(
+        default = "default_reload_interval",
+        deserialize_with = "crate::deserialize_duration"
+    )]
+    reload_interval: Duration,
+}
+
+struct Table {
+    rows: HashMap<String, Map<String, JsonValue>>,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+pub struct Enrich {
+    path: String,
+    format: TableFormat,
+    key_field: String,
+    key: Expression,
+    destination: Destination,
+    target: Option<String>,
+    default: Option<Map<String, JsonValue>>,
+    reload_interval: Duration,
+    table: RwLock<Table>,
+}
+
+/// Render a join key, using the raw value of strings
+fn key_string(value: &JsonValue) -> Option<String> {
+    match value {
+        JsonValue::Null => None,
+        JsonValue::String(s) => Some(s.clone()),
+        other => Some(other.to_string()),
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn index_rows(
+    rows: impl IntoIterator<Item = JsonValue>,
+    key_field: &str,
+) -> Result<HashMap<String, Map<String, JsonValue>>, Error> {
+    let mut index = HashMap::new();
+    for row in rows {
+        let JsonValue::Object(row) = row else {
+            return Err(Error::ConfigFailedValidation(
+                "lookup table rows must be objects".into(),
+            ));
+        };
+        if let Some(key) = row.get(key_field).and_then(key_string) {
+            let _ = index.insert(key, row);
+        }
+    }
+    Ok(index)
+}
+
+fn load_table(
+    path: &str,
+    format: TableFormat,
+    key_field: &str,
+) -> Result<HashMap<String, Map<String, JsonValue>>, Error> {
+    let contents =
+        std::fs::read(path).map_err(|e| Error::ConfigFailedValidation(format!("{path}: {e}")))?;
+    let invalid = |e: String| Error::ConfigFailedValidation(format!("{path}: {e}"));
+
+    match format {
+        TableFormat::Csv => {
+            let mut reader = csv::Reader::from_reader(contents.as_slice());
+            let headers = reader
+                .headers()
+                .map_err(|e| invalid(e.to_string()))?
+                .clone();
+            let mut rows = Vec::new();
+            for record in reader.records() {
+                let record = record.map_err(|e| invalid(e.to_string()))?;
+                let row: Map<String, JsonValue> = headers
+                    .iter()
+                    .zip(record.iter())
+                    .map(|(h, v)| (h.to_string(), JsonValue::String(v.to_string())))
+                    .collect();
+                rows.push(JsonValue::Object(row));
+            }
+            index_rows(rows, key_field)
+        }
+        TableFormat::Json => {
+            let value: JsonValue =
+                serde_json::from_slice(&contents).map_err(|e| invalid(e.to_string()))?;
+            match value {
+                JsonValue::Array(rows) => index_rows(rows, key_field),
+                JsonValue::Object(map) => map
+                    .into_iter()
+                    .map(|(k, row)| match row {
+                        JsonValue::Object(row) => Ok((k, row)),
+                        _ => Err(invalid("lookup table rows must be objects".into())),
+                    })
+                    .collect(),
+                _ => Err(invalid(
+                    "expected an array of objects or an object of rows".into(),
+                )),
+            }
+        }
+        TableFormat::Ndjson => {
+            let rows = contents
+                .split(|b| *b == b'\n')
+                .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+                .map(|line| serde_json::from_slice(line).map_err(|e| invalid(e.to_string())))
+                .collect::<Result<Vec<JsonValue>, Error>>()?;
+            index_rows(rows, key_field)
+        }
+    }
+}
+
+impl Enrich {
+    /// Reload the table when the file changed, keeping the previous table on failure
+    fn refresh(&self) -> Result<(), Error> {
+        {
+            let table = self
+                .table
+                .read()
+                .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+            if table.checked.elapsed() < self.reload_interval {
+                return Ok(());
+            }
+        }
+
+        let previous = {
+            let mut table = self
+                .table
+                .write()
+                .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+            if table.checked.elapsed() < self.reload_interval {
+                return Ok(());
+            }
+            table.checked = Instant::now();
+            table.modified
+        };
+
+        // The file is read without holding the lock so lookups are not blocked
+        let current = modified(&self.path);
+        if current.is_none() || current == previous {
+            return Ok(());
+        }
+
+        match load_table(&self.path, self.format, &self.key_field) {
+            Ok(rows) => {
+                debug!(path = self.path, rows = rows.len(), "reloaded lookup table");
+                let mut table = self
+                    .table
+                    .write()
+                    .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+                table.rows = rows;
+                table.modified = current;
+            }
+            Err(e) => warn!(path = self.path, error = %e, "failed to reload lookup table"),
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, message: &Message) -> Result<Option<Map<String, JsonValue>>, Error> {
+        let value = self.key.search(&variable(&message.bytes)?)?;
+
+        let table = self
+            .table
+            .read()
+            .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+        let row = key_string(&value).and_then(|k| table.rows.get(&k).cloned());
+        Ok(row.or_else(|| self.default.clone()))
+    }
+}
+
+#[async_trait]
+impl Processor for Enrich {
+    async fn process(&self, mut message: Message) -> Result<MessageBatch, Error> {
+        self.refresh()?;
+
+        let Some(row) = self.lookup(&message)? else {
+            return Ok(vec![message]);
+        };
+
+        match self.destination {
+            Destination::Body => {
+                let mut body: JsonValue = serde_json::from_slice(&message.bytes)
+                    .map_err(|e| Error::MessageFailed(format!("{e}")))?;
+                match &self.target {
+                    Some(target) => {
+                        set_path(&mut body, &parse_path(target)?, JsonValue::Object(row))?
+                    }
+                    None => {
+                        let obj = body.as_object_mut().ok_or_else(|| {
+                            Error::MessageFailed(
+                                "enrich requires the message to be a JSON object".into(),
+                            )
+                        })?;
+                        obj.extend(row);
+                    }
+                }
+                message.bytes = serde_json::to_vec(&body)
+                    .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+            }
+            Destination::Metadata => {
+                let to_yaml = |v: &JsonValue| {
+                    serde_yaml::to_value(v).map_err(|e| Error::ProcessingError(format!("{e}")))
+                };
+                match &self.target {
+                    Some(target) => {
+                        let value = to_yaml(&JsonValue::Object(row))?;
+                        message.metadata.insert(target.clone(), value);
+                    }
+                    None => {
+                        for (k, v) in row {
+                            message.metadata.insert(k, to_yaml(&v)?);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(vec![message])
+    }
+}
+
+impl Closer for Enrich {}
+
+#[fiddler_registration_func]
+fn create_enrich(conf: Value) -> Result<ExecutionType, Error> {
+    let c: EnrichConfig = serde_yaml::from_value(conf)?;
+
+    let format = match c.format {
+        Some(f) => f,
+        None => match Path::new(&c.path).extension().and_then(|e| e.to_str()) {
+            Some("csv") => TableFormat::Csv,
+            Some("json") => TableFormat::Json,
+            Some("ndjson") | Some("jsonl") => TableFormat::Ndjson,
+            _ => {
+                return Err(Error::ConfigFailedValidation(format!(
+                    "unable to infer the format of '{}', set format explicitly",
+                    c.path
+                )))
+            }
+        },
+    };
+
+    let key = Expression::compile(&c.key)?;
+
+    if let (Destination::Body, Some(target)) = (c.destination, &c.target) {
+        let _ = parse_path(target)?;
+    }
+
+    let default = match c.default {
+        Some(JsonValue::Object(row)) => Some(row),
+        Some(_) => {
+            return Err(Error::ConfigFailedValidation(
+                "default must be an object".into(),
+            ))
+        }
+        None => None,
+    };
+
+    let rows = load_table(&c.path, format, &c.key_field)?;
+
+    Ok(ExecutionType::Processor(Box::new(Enrich {
+        table: RwLock::new(Table {
+            rows,
+            modified: modified(&c.path),
+            checked: Instant::now(),
+        }),
+        path: c.path,
+        format,
+        key_field: c.key_field,
+        key,
+        destination: c.destination,
+        target: c.target,
+        default,
+        reload_interval: c.reload_interval,
+    })))
+}
+
+pub(super) fn register_enrich() -> Result<(), Error> {
+    let config = "type: object
+properties:
+  path:
+    type: string
+  format:
+    type: string
+    enum: [csv, json, ndjson]
+  key_field:
+    type: string
+  key:
+    type: string
+  destination:
+    type: string
+    enum: [body, metadata]
+  target:
+    type: string
+  default:
+    type: object
+  reload_interval:
+    type: string
+required:
+  - path
+  - key_field
+  - key";
+    let conf_spec = ConfigSpec::from_schema(config)?;
+
+    register_plugin(
+        "enrich".into(),
+        ItemType::Processor,
+        conf_spec,
+        create_enrich,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::modules::processors::test_util::{body, message, processor, text_message};
+    use serde_json::json;
+    use std::io::Write;
+
+    #[test]
+    fn register_plugin() {
+        register_enrich().unwrap()
+    }
+
+    fn table_file(name: &str, contents: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("fiddler_enrich_{}_{name}", uuid::Uuid::new_v4()));
+        let mut f = std::fs::File::create(&path).unwrap();
+        f.write_all(contents.as_bytes()).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    async fn enrich(p: &(dyn Processor + Send + Sync), value: JsonValue) -> Message {
+        let mut output = p.process(message(value)).await.unwrap();
+        assert_eq!(output.len(), 1);
+        output.remove(0)
+    }
+
+    const CSV: &str = "ip,owner,service\n10.0.0.1,alice,web\n10.0.0.2,bob,db\n";
+
+    #[tokio::test]
+    async fn csv_merge_into_target() {
+        let path = table_file("assets.csv", CSV);
+        let p = processor(
+            create_enrich,
+            &format!("path: {path}\nkey_field: ip\nkey: source.ip\ntarget: asset"),
+        )
+        .await;
+        let output = enrich(p.as_ref(), json!({"source": {"ip": "10.0.0.2"}})).await;
+        assert_eq!(
+            body(&output),
+            json!({"source": {"ip": "10.0.0.2"}, "asset": {"ip": "10.0.0.2", "owner": "bob", "service": "db"}})
+        );
+    }
+
+    #[tokio::test]
+    async fn miss_without_default_passes_through() {
+        let path = table_file("assets.csv", CSV);
+        let p = processor(
+            create_enrich,
+            &format!("path: {path}\nkey_field: ip\nkey: ip"),
+        )
+        .await;
+        let output = enrich(p.as_ref(), json!({"ip": "10.9.9.9"})).await;
+        assert_eq!(body(&output), json!({"ip": "10.9.9.9"}));
+    }
+
+    #[tokio::test]
+    async fn miss_uses_default() {
+        let path = table_file("assets.csv", CSV);
+        let p = processor(
+            create_enrich,
+            &format!("path: {path}\nkey_field: ip\nkey: ip\ndefault:\n  owner: unknown"),
+        )
+        .await;
+        let output = enrich(p.as_ref(), json!({"ip": "10.9.9.9"})).await;
+        assert_eq!(body(&output), json!({"ip": "10.9.9.9", "owner": "unknown"}));
+    }
+
+    #[tokio::test]
+    async fn json_object_table_into_metadata() {
+        let path = table_file(
+            "services.json",
+            r#"{"web": {"team": "frontend", "tier": 1}}"#,
+        );
+        let p = processor(
+            create_enrich,
+            &format!("path: {path}\nkey_field: name\nkey: service\ndestination: metadata"),
+        )
+        .await;
+        let output = enrich(p.as_ref(), json!({"service": "web"})).await;
+        assert_eq!(body(&output), json!({"service": "web"}));
+        assert_eq!(
+            output.metadata.get("team"),
+            Some(&Value::String("frontend".into()))
+        );
+        assert_eq!(output.metadata.get("tier"), Some(&Value::Number(1.into())));
+    }
+
+    #[tokio::test]
+    async fn ndjson_numeric_key() {
+        let path = table_file(
+            "users.ndjson",
+            "{\"id\": 1, \"name\": \"alice\"}\n\n{\"id\": 2, \"name\": \"bob\"}\n",
+        );
+        let p = processor(
+            create_enrich,
+            &format!("path: {path}\nkey_field: id\nkey: user_id\ntarget: user"),
+        )
+        .await;
+        let output = enrich(p.as_ref(), json!({"user_id": 2})).await;
+        assert_eq!(body(&output)["user"]["name"], "bob");
+    }
+
+    #[tokio::test]
+    async fn reload_on_change() {
+        let path = table_file("assets.csv", CSV);
+        let p = processor(
+            create_enrich,
+            &format!("path: {path}\nkey_field: ip\nkey: ip\nreload_interval: 0s"),
+        )
+        .await;
+        assert_eq!(
+            body(&enrich(p.as_ref(), json!({"ip": "10.0.0.1"})).await)["owner"],
+            "alice"
+        );
+
+        std::fs::write(&path, "ip,owner\n10.0.0.1,carol\n").unwrap();
+        let later = SystemTime::now() + Duration::from_secs(5);
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+
+        assert_eq!(
+            body(&enrich(p.as_ref(), json!({"ip": "10.0.0.1"})).await)["owner"],
+            "carol"
+        );
+    }
+
+    #[tokio::test]
+    async fn bad_body_fails_message() {
+        let path = table_file("assets.csv", CSV);
+        let p = processor(
+            create_enrich,
+            &format!("path: {path}\nkey_field: ip\nkey: ip\ndefault:\n  owner: unknown"),
+        )
+        .await;
+        for m in [text_message("not json"), message(json!(["10.0.0.1"]))] {
+            let result = p.process(m).await;
+            assert!(matches!(result, Err(Error::MessageFailed(_))), "{result:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_config() {
+        let path = table_file("assets.csv", CSV);
+        for conf in [
+            format!("path: {path}.missing\nkey_field: ip\nkey: ip"),
+            format!("path: {path}\nkey_field: ip\nkey: '[['"),
+            format!("path: {path}\nkey_field: ip\nkey: ip\ndefault: 1"),
+            "path: table.txt\nkey_field: ip\nkey: ip".to_string(),
+        ] {
+            let value: Value = serde_yaml::from_str(&conf).unwrap();
+            assert!(create_enrich(value).await.is_err(), "{conf}");
+        }
+    }
+}
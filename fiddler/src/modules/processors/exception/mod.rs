@@ -1,6 +1,35 @@
+//! Try processor for handling failures of a single processor.
+//!
+//! When `processor` fails, the original message is annotated with the error
+//! details and sent through the `catch` processors instead.  The `finally`
+//! processors run on whatever comes out of either path.  With `reraise` the
+//! output of catch and finally still continues down the pipeline, but the
+//! message is reported as failed to the input, so its error handling, such as
+//! redelivery or dead-lettering, still applies.  Messages held back by nested
+//! processors, such as `archive`, are flushed through `finally` as well.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - try:
+//!       processor:                 # Required: processor to attempt
+//!         label: parse_event
+//!         transform:
+//!           mappings:
+//!             - source: "event"
+//!               target: "event"
+//!       catch:                     # Optional: processors run when it fails
+//!         - noop: {}
+//!       finally:                   # Optional: processors run on every outcome
+//!         - noop: {}
+//!       reraise: false             # Optional: fail the message after catch (default: false)
+//! ```
+
+use super::{build_processor, build_processors, flush_chain, run_chain};
 use crate::config::register_plugin;
-use crate::config::{parse_configuration_item, Item, ItemType};
 use crate::config::{ConfigSpec, ExecutionType};
+use crate::config::{Item, ItemType};
 use crate::Message;
 use crate::MessageBatch;
 use crate::{Closer, Error, Processor};
@@ -10,35 +39,85 @@ use serde::Deserialize;
 use serde_yaml::Value;
 use tracing::debug;
 
+/// Metadata key holding the error text
+const ERROR_MESSAGE_METADATA: &str = "error_message";
+/// Metadata key holding the kind of error
+const ERROR_KIND_METADATA: &str = "error_kind";
+/// Metadata key holding the label of the failing processor
+const ERROR_LABEL_METADATA: &str = "error_label";
+
 #[derive(Deserialize)]
 struct TryConfig {
     processor: Value,
     catch: Option<Vec<Item>>,
+    finally: Option<Vec<Item>>,
+    #[serde(default)]
+    reraise: bool,
 }
 pub struct Try {
     processor: Box<dyn Processor + Send + Sync>,
+    label: String,
     catch: Vec<Box<dyn Processor + Send + Sync>>,
+    finally: Vec<Box<dyn Processor + Send + Sync>>,
+    reraise: bool,
+}
+
+/// Short name describing the kind of error
+fn error_kind(e: &Error) -> &'static str {
+    match e {
+        Error::ProcessingError(_) => "processing_error",
+        Error::ConditionalCheckfailed => "conditional_check_failed",
+        Error::MessageFailed(_) | Error::MessageFailedWithOutput(..) => "message_failed",
+        Error::ExecutionError(_) => "execution_error",
+        Error::Validation(_) => "validation",
+        Error::UnRetryable(_) => "unretryable",
+        Error::NotYetImplemented => "not_yet_implemented",
+        _ => "other",
+    }
 }
 
 #[async_trait]
 impl Processor for Try {
-    async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
-        match self.processor.process(message.clone()).await {
-            Ok(m) => Ok(m),
-            Err(e) => {
-                debug!("caught error {e}");
-                let mut messages = vec![message];
-                for p in &self.catch {
-                    let mut new_messages = Vec::new();
-                    for m in messages.drain(..) {
-                        let processed = p.process(m).await?;
-                        new_messages.extend(processed);
-                    }
-                    messages = new_messages;
-                }
-                Ok(messages)
+    async fn process(&self, mut message: Message) -> Result<MessageBatch, Error> {
+        let e = match self.processor.process(message.clone()).await {
+            Ok(m) => return run_chain(&self.finally, m).await,
+            Err(e) => e,
+        };
+
+        debug!(label = self.label, "caught error {e}");
+        message
+            .metadata
+            .insert(ERROR_MESSAGE_METADATA.into(), Value::String(format!("{e}")));
+        message.metadata.insert(
+            ERROR_KIND_METADATA.into(),
+            Value::String(error_kind(&e).into()),
+        );
+        message.metadata.insert(
+            ERROR_LABEL_METADATA.into(),
+            Value::String(self.label.clone()),
+        );
+
+        let messages = match run_chain(&self.catch, vec![message.clone()]).await {
+            Ok(m) => run_chain(&self.finally, m).await?,
+            Err(catch_error) => {
+                run_chain(&self.finally, vec![message]).await?;
+                return Err(catch_error);
             }
+        };
+
+        if self.reraise {
+            return Err(Error::MessageFailedWithOutput(format!("{e}"), messages));
         }
+        Ok(messages)
+    }
+
+    async fn flush(&self, shutdown: bool) -> Result<MessageBatch, Error> {
+        // Messages released by the processor or catch still go through finally
+        let mut released = self.processor.flush(shutdown).await?;
+        released.extend(flush_chain(&self.catch, shutdown).await?);
+        let mut messages = run_chain(&self.finally, released).await?;
+        messages.extend(flush_chain(&self.finally, shutdown).await?);
+        Ok(messages)
     }
 }
 
@@ -46,7 +125,7 @@ impl Processor for Try {
 impl Closer for Try {
     async fn close(&mut self) -> Result<(), Error> {
         self.processor.close().await?;
-        for p in &mut self.catch {
+        for p in self.catch.iter_mut().chain(self.finally.iter_mut()) {
             p.close().await?;
         }
         Ok(())
@@ -58,34 +137,19 @@ fn create_try(conf: Value) -> Result<ExecutionType, Error> {
     let try_conf: TryConfig = serde_yaml::from_value(conf.clone())?;
     let proc: Item = serde_yaml::from_value(try_conf.processor)?;
 
-    let proc_registered_item = parse_configuration_item(ItemType::Processor, &proc.extra).await?;
-    let p = match ((proc_registered_item.creator)(proc_registered_item.config)).await? {
-        ExecutionType::Processor(rp) => rp,
-        _ => {
-            return Err(Error::ConfigFailedValidation(
-                "invalid execution type provided".into(),
-            ))
-        }
-    };
-
-    let catch: Vec<Box<dyn Processor + Send + Sync>> = match try_conf.catch {
-        Some(processors) => {
-            let mut steps = Vec::new();
-            for p in processors {
-                let ri = parse_configuration_item(ItemType::Processor, &p.extra).await?;
-                let proc = ((ri.creator)(ri.config.clone())).await?;
-                if let ExecutionType::Processor(rp) = proc {
-                    steps.push(rp);
-                };
-            }
-            steps
-        }
-        None => Vec::new(),
-    };
+    let p = build_processor(&proc).await?;
+    // Fall back to the processor name when no label is given
+    let label = proc
+        .label
+        .or_else(|| proc.extra.keys().next().cloned())
+        .unwrap_or_default();
 
     Ok(ExecutionType::Processor(Box::new(Try {
         processor: p,
-        catch,
+        label,
+        catch: build_processors(try_conf.catch.unwrap_or_default()).await?,
+        finally: build_processors(try_conf.finally.unwrap_or_default()).await?,
+        reraise: try_conf.reraise,
     })))
 }
 
@@ -96,6 +160,14 @@ properties:
     type: object
   catch:
     type: array
+    items:
+      type: object
+  finally:
+    type: array
+    items:
+      type: object
+  reraise:
+    type: boolean
 required:
   - processor";
 
@@ -107,9 +179,213 @@ required:
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::config::ParsedRegisteredItem;
+    use crate::modules::processors::run_processor;
+    use crate::runtime::{InternalMessage, MessageStatus};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Once};
+
+    static REGISTER: Once = Once::new();
+
+    /// Fails on bodies equal to its name, otherwise tags the message with it
+    #[derive(Default)]
+    struct Step {
+        name: &'static str,
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Processor for Step {
+        async fn process(&self, mut message: Message) -> Result<MessageBatch, Error> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if message.bytes == self.name.as_bytes() {
+                return Err(Error::ProcessingError(format!("{} failed", self.name)));
+            }
+            message.metadata.insert(self.name.into(), Value::Bool(true));
+            Ok(vec![message])
+        }
+    }
+
+    impl Closer for Step {}
+
+    #[fiddler_registration_func]
+    fn create_step(_conf: Value) -> Result<ExecutionType, Error> {
+        Ok(ExecutionType::Processor(Box::new(Step {
+            name: "step",
+            ..Default::default()
+        })))
+    }
+
+    fn step(name: &'static str) -> (Box<dyn Processor + Send + Sync>, Arc<AtomicUsize>) {
+        let s = Step {
+            name,
+            ..Default::default()
+        };
+        let calls = s.calls.clone();
+        (Box::new(s), calls)
+    }
+
+    fn processor(reraise: bool) -> (Try, Arc<AtomicUsize>, Arc<AtomicUsize>) {
+        let (catch, catch_calls) = step("catch");
+        let (finally, finally_calls) = step("finally");
+        let p = Try {
+            processor: step("main").0,
+            label: "parse".into(),
+            catch: vec![catch],
+            finally: vec![finally],
+            reraise,
+        };
+        (p, catch_calls, finally_calls)
+    }
+
+    fn message(body: &str) -> Message {
+        Message {
+            bytes: body.as_bytes().to_vec(),
+            ..Default::default()
+        }
+    }
 
     #[test]
     fn register_plugin() {
         register_try().unwrap()
     }
+
+    #[tokio::test]
+    async fn success_runs_finally() {
+        let (p, catch_calls, _) = processor(false);
+        let output = p.process(message("ok")).await.unwrap();
+        assert_eq!(output.len(), 1);
+        assert!(output[0].metadata.contains_key("main"));
+        assert!(output[0].metadata.contains_key("finally"));
+        assert!(!output[0].metadata.contains_key(ERROR_MESSAGE_METADATA));
+        assert_eq!(catch_calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn catch_sees_error_details() {
+        let (p, _, finally_calls) = processor(false);
+        let output = p.process(message("main")).await.unwrap();
+        assert_eq!(output.len(), 1);
+        let metadata = &output[0].metadata;
+        assert_eq!(
+            metadata.get(ERROR_MESSAGE_METADATA),
+            Some(&Value::String("Processor failure: main failed".into()))
+        );
+        assert_eq!(
+            metadata.get(ERROR_KIND_METADATA),
+            Some(&Value::String("processing_error".into()))
+        );
+        assert_eq!(
+            metadata.get(ERROR_LABEL_METADATA),
+            Some(&Value::String("parse".into()))
+        );
+        assert!(metadata.contains_key("catch"));
+        assert!(metadata.contains_key("finally"));
+        assert_eq!(finally_calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn reraise_after_catch() {
+        let (p, catch_calls, finally_calls) = processor(true);
+        match p.process(message("main")).await {
+            Err(Error::MessageFailedWithOutput(e, output)) => {
+                assert_eq!(e, "Processor failure: main failed");
+                assert_eq!(output.len(), 1);
+                assert!(output[0].metadata.contains_key("catch"));
+                assert!(output[0].metadata.contains_key("finally"));
+            }
+            other => panic!("unexpected result {other:?}"),
+        }
+        assert_eq!(catch_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(finally_calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[fiddler_registration_func]
+    fn create_reraising_try(_conf: Value) -> Result<ExecutionType, Error> {
+        Ok(ExecutionType::Processor(Box::new(processor(true).0)))
+    }
+
+    #[tokio::test]
+    async fn reraise_fails_message_in_pipeline() {
+        let (input_tx, input_rx) = flume::bounded(10);
+        let (output_tx, output_rx) = flume::bounded(10);
+        let (state_tx, state_rx) = flume::bounded(10);
+        let (handle_tx, _handle_rx) = flume::bounded(10);
+        let item = ParsedRegisteredItem {
+            creator: create_reraising_try,
+            config: Value::Null,
+        };
+        let task = tokio::spawn(run_processor(
+            item, output_tx, input_rx, state_tx, handle_tx,
+        ));
+
+        for (message_id, body) in [("failed", "main"), ("ok", "ok")] {
+            input_tx
+                .send_async(InternalMessage {
+                    message: message(body),
+                    message_id: message_id.into(),
+                    status: MessageStatus::New,
+                })
+                .await
+                .unwrap();
+        }
+        drop(input_tx);
+        // The processor keeps running after the failure and shuts down cleanly
+        task.await.unwrap().unwrap();
+
+        let output: Vec<InternalMessage> = output_rx.drain().collect();
+        assert_eq!(output.len(), 2);
+        assert_eq!(output[0].message_id, "failed");
+        assert!(output[0].message.metadata.contains_key("catch"));
+        assert_eq!(output[1].message_id, "ok");
+
+        // The catch output is tracked as an extra instance next to the failure
+        let states: Vec<(String, String)> = state_rx
+            .drain()
+            .map(|s| (s.message_id, s.status.to_string()))
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                ("failed".into(), "New".into()),
+                ("failed".into(), "ProcessError".into())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn from_config() {
+        REGISTER.call_once(|| {
+            crate::config::register_plugin(
+                "try_test_step".into(),
+                ItemType::Processor,
+                ConfigSpec::from_schema("type: object").unwrap(),
+                create_step,
+            )
+            .unwrap()
+        });
+
+        let value: Value = serde_yaml::from_str(
+            "processor: {try_test_step: {}}
+catch: [{try_test_step: {}}]
+finally: [{try_test_step: {}}]
+reraise: true",
+        )
+        .unwrap();
+        let p = match create_try(value).await.unwrap() {
+            ExecutionType::Processor(p) => p,
+            _ => panic!("expected processor"),
+        };
+        assert!(p.process(message("ok")).await.is_ok());
+        assert!(matches!(
+            p.process(message("step")).await,
+            Err(Error::ProcessingError(_))
+        ));
+
+        let value: Value = serde_yaml::from_str(
+            "processor: {label: first, try_test_step: {}}\nfinally: [{noexist: {}}]",
+        )
+        .unwrap();
+        assert!(create_try(value).await.is_err());
+    }
 }
@@ -0,0 +1,392 @@
+//! For each and parallel processors for applying processors to array elements.
+//!
+//! Each element of the array at `path` is sent through the nested
+//! `processors` as its own message, carrying a copy of the parent metadata.
+//! The results replace the element in place: an element whose processors emit
+//! no messages is removed, and several messages are all inserted in order.
+//! `for_each` processes elements one at a time, while `parallel` processes up
+//! to `max_in_flight` elements concurrently.  Results are reassembled in the
+//! original order either way.  A missing array leaves the message unchanged,
+//! and any error fails the whole message.  Elements held back by the nested
+//! processors no longer belong to an array, so they are flushed as messages of
+//! their own.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - parallel:
+//!       path: "items"          # Required: field holding the array
+//!       processors:            # Required: processors run on each element
+//!         - http:
+//!             url: "https://api.example.com/items"
+//!       max_in_flight: 8       # Optional: concurrent elements, parallel only (default: 10)
+//! ```
+
+use super::path::{get_path_mut, parse_path};
+use super::{build_processors, flush_chain, run_chain};
+use crate::config::register_plugin;
+use crate::config::{ConfigSpec, ExecutionType};
+use crate::config::{Item, ItemType};
+use crate::Message;
+use crate::MessageBatch;
+use crate::{Closer, Error, Processor};
+use async_trait::async_trait;
+use fiddler_macros::fiddler_registration_func;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use serde_yaml::Value;
+use std::collections::HashMap;
+
+fn default_max_in_flight() -> usize {
+    10
+}
+
+#[derive(Deserialize)]
+struct ForEachConfig {
+    path: String,
+    processors: Vec<Item>,
+}
+
+#[derive(Deserialize)]
+struct ParallelConfig {
+    path: String,
+    processors: Vec<Item>,
+    #[serde(default = "default_max_in_flight")]
+    max_in_flight: usize,
+}
+
+pub struct ForEach {
+    path: Vec<String>,
+    processors: Vec<Box<dyn Processor + Send + Sync>>,
+    max_in_flight: usize,
+}
+
+impl ForEach {
+    /// Run the nested processors on a single element
+    async fn run(
+        &self,
+        element: JsonValue,
+        metadata: &HashMap<String, Value>,
+    ) -> Result<Vec<JsonValue>, Error> {
+        let messages = vec![Message {
+            bytes: serde_json::to_vec(&element)
+                .map_err(|e| Error::ProcessingError(format!("{e}")))?,
+            metadata: metadata.clone(),
+            ..Default::default()
+        }];
+        let messages = run_chain(&self.processors, messages).await?;
+
+        messages
+            .iter()
+            .map(|m| {
+                serde_json::from_slice(&m.bytes).map_err(|e| Error::MessageFailed(format!("{e}")))
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Processor for ForEach {
+    async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
+        let mut doc: JsonValue = serde_json::from_slice(&message.bytes)
+            .map_err(|e| Error::MessageFailed(format!("{e}")))?;
+
+        let elements = match get_path_mut(&mut doc, &self.path) {
+            Some(JsonValue::Array(elements)) => std::mem::take(elements),
+            Some(JsonValue::Null) | None => return Ok(vec![message]),
+            Some(other) => {
+                return Err(Error::MessageFailed(format!(
+                    "'{}' is not an array, got {other}",
+                    self.path.join(".")
+                )))
+            }
+        };
+
+        let results: Vec<Vec<JsonValue>> = stream::iter(elements)
+            .map(|element| self.run(element, &message.metadata))
+            .buffered(self.max_in_flight)
+            .try_collect()
+            .await?;
+
+        if let Some(array) = get_path_mut(&mut doc, &self.path) {
+            *array = JsonValue::Array(results.into_iter().flatten().collect());
+        }
+
+        Ok(vec![Message {
+            bytes: serde_json::to_vec(&doc).map_err(|e| Error::ProcessingError(format!("{e}")))?,
+            ..message
+        }])
+    }
+
+    async fn flush(&self, shutdown: bool) -> Result<MessageBatch, Error> {
+        flush_chain(&self.processors, shutdown).await
+    }
+}
+
+#[async_trait]
+impl Closer for ForEach {
+    async fn close(&mut self) -> Result<(), Error> {
+        for p in &mut self.processors {
+            p.close().await?;
+        }
+        Ok(())
+    }
+}
+
+async fn build(path: &str, items: Vec<Item>, max_in_flight: usize) -> Result<ExecutionType, Error> {
+    if items.is_empty() {
+        return Err(Error::ConfigFailedValidation(
+            "at least one processor is required".into(),
+        ));
+    }
+    if max_in_flight == 0 {
+        return Err(Error::ConfigFailedValidation(
+            "max_in_flight must be greater than 0".into(),
+        ));
+    }
+
+    let path = parse_path(path)?;
+    let processors = build_processors(items).await?;
+
+    Ok(ExecutionType::Processor(Box::new(ForEach {
+        path,
+        processors,
+        max_in_flight,
+    })))
+}
+
+#[fiddler_registration_func]
+fn create_for_each(conf: Value) -> Result<ExecutionType, Error> {
+    let c: ForEachConfig = serde_yaml::from_value(conf)?;
+    build(&c.path, c.processors, 1).await
+}
+
+#[fiddler_registration_func]
+fn create_parallel(conf: Value) -> Result<ExecutionType, Error> {
+    let c: ParallelConfig = serde_yaml::from_value(conf)?;
+    build(&c.path, c.processors, c.max_in_flight).await
+}
+
+pub(super) fn register_for_each() -> Result<(), Error> {
+    let for_each_config = "type: object
+properties:
+  path:
+    type: string
+  processors:
+    type: array
+    items:
+      type: object
+required:
+  - path
+  - processors";
+    let parallel_config = "type: object
+properties:
+  path:
+    type: string
+  processors:
+    type: array
+    items:
+      type: object
+  max_in_flight:
+    type: integer
+    minimum: 1
+required:
+  - path
+  - processors";
+
+    register_plugin(
+        "for_each".into(),
+        ItemType::Processor,
+        ConfigSpec::from_schema(for_each_config)?,
+        create_for_each,
+    )?;
+    register_plugin(
+        "parallel".into(),
+        ItemType::Processor,
+        ConfigSpec::from_schema(parallel_config)?,
+        create_parallel,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Once};
+    use std::time::Duration;
+
+    static REGISTER: Once = Once::new();
+
+    /// Doubles numbers, drops zeros and repeats strings twice, tracking concurrency
+    #[derive(Default)]
+    struct Doubler {
+        active: Arc<AtomicUsize>,
+        peak: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Processor for Doubler {
+        async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
+            let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(active, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            self.active.fetch_sub(1, Ordering::SeqCst);
+
+            let value: JsonValue = serde_json::from_slice(&message.bytes).unwrap();
+            let outputs = match value {
+                JsonValue::Number(n) if n.as_i64() == Some(0) => vec![],
+                JsonValue::Number(n) => vec![json!(n.as_i64().unwrap() * 2)],
+                JsonValue::String(s) => vec![json!(s), json!(s)],
+                _ => return Err(Error::ProcessingError("unsupported element".into())),
+            };
+            Ok(outputs
+                .into_iter()
+                .map(|v| Message {
+                    bytes: serde_json::to_vec(&v).unwrap(),
+                    ..message.clone()
+                })
+                .collect())
+        }
+    }
+
+    impl Closer for Doubler {}
+
+    #[fiddler_registration_func]
+    fn create_doubler(_conf: Value) -> Result<ExecutionType, Error> {
+        Ok(ExecutionType::Processor(Box::new(Doubler::default())))
+    }
+
+    fn for_each(max_in_flight: usize) -> (ForEach, Arc<AtomicUsize>) {
+        let doubler = Doubler::default();
+        let peak = doubler.peak.clone();
+        let p = ForEach {
+            path: vec!["data".into(), "items".into()],
+            processors: vec![Box::new(doubler)],
+            max_in_flight,
+        };
+        (p, peak)
+    }
+
+    fn message(body: JsonValue) -> Message {
+        Message {
+            bytes: serde_json::to_vec(&body).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    fn body(message: &Message) -> JsonValue {
+        serde_json::from_slice(&message.bytes).unwrap()
+    }
+
+    #[test]
+    fn register_plugin() {
+        register_for_each().unwrap()
+    }
+
+    #[tokio::test]
+    async fn reassembles_in_order() {
+        let (p, peak) = for_each(1);
+        let output = p
+            .process(message(json!({"id": 1, "data": {"items": [1, 0, "a", 3]}})))
+            .await
+            .unwrap();
+        assert_eq!(
+            body(&output[0]),
+            json!({"id": 1, "data": {"items": [2, "a", "a", 6]}})
+        );
+        assert_eq!(peak.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn parallel_limit() {
+        let (p, peak) = for_each(3);
+        let items: Vec<i64> = (1..=10).collect();
+        let output = p
+            .process(message(json!({"data": {"items": items}})))
+            .await
+            .unwrap();
+        let expected: Vec<i64> = (1..=10).map(|i| i * 2).collect();
+        assert_eq!(body(&output[0]), json!({"data": {"items": expected}}));
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn missing_and_invalid() {
+        let (p, _) = for_each(2);
+        let input = message(json!({"data": {}}));
+        assert_eq!(p.process(input.clone()).await.unwrap(), vec![input]);
+        for input in [
+            message(json!({"data": {"items": 1}})),
+            Message {
+                bytes: b"not json".to_vec(),
+                ..Default::default()
+            },
+        ] {
+            let result = p.process(input).await;
+            assert!(matches!(result, Err(Error::MessageFailed(_))), "{result:?}");
+        }
+        assert!(p
+            .process(message(json!({"data": {"items": [1, true]}})))
+            .await
+            .is_err());
+    }
+
+    fn register_doubler() {
+        REGISTER.call_once(|| {
+            crate::config::register_plugin(
+                "for_each_test_doubler".into(),
+                ItemType::Processor,
+                ConfigSpec::from_schema("type: object").unwrap(),
+                create_doubler,
+            )
+            .unwrap()
+        });
+    }
+
+    async fn assert_doubles(created: ExecutionType) {
+        let p = match created {
+            ExecutionType::Processor(p) => p,
+            _ => panic!("expected processor"),
+        };
+        let output = p.process(message(json!({"items": [1, 2]}))).await.unwrap();
+        assert_eq!(body(&output[0]), json!({"items": [2, 4]}));
+    }
+
+    #[tokio::test]
+    async fn for_each_from_config() {
+        register_doubler();
+        let value: Value =
+            serde_yaml::from_str("path: items\nprocessors: [{for_each_test_doubler: {}}]").unwrap();
+        assert_doubles(create_for_each(value).await.unwrap()).await;
+
+        for conf in [
+            "path: items\nprocessors: []",
+            "path: 'a..b'\nprocessors: [{for_each_test_doubler: {}}]",
+        ] {
+            let value: Value = serde_yaml::from_str(conf).unwrap();
+            assert!(create_for_each(value).await.is_err(), "{conf}");
+        }
+    }
+
+    #[tokio::test]
+    async fn parallel_from_config() {
+        register_doubler();
+        let value: Value = serde_yaml::from_str(
+            "path: items\nprocessors: [{for_each_test_doubler: {}}]\nmax_in_flight: 4",
+        )
+        .unwrap();
+        assert_doubles(create_parallel(value).await.unwrap()).await;
+
+        for conf in [
+            "path: items\nprocessors: []",
+            "path: 'a..b'\nprocessors: [{for_each_test_doubler: {}}]",
+            "path: items\nprocessors: [{for_each_test_doubler: {}}]\nmax_in_flight: 0",
+        ] {
+            let value: Value = serde_yaml::from_str(conf).unwrap();
+            assert!(create_parallel(value).await.is_err(), "{conf}");
+        }
+    }
+}
@@ -0,0 +1,824 @@
+//! GeoIP processor for adding location and network context to IP addresses.
+//!
+//! The IP address is read from a field of the JSON message or from message
+//! metadata, such as the `syslog_source_ip` added by the syslog input, and
+//! looked up in local MaxMind-format City and ASN databases.  Private,
+//! loopback and other non-routable addresses are skipped, and an address that
+//! cannot be parsed passes the message through with a `geoip_error` metadata
+//! key.  Results are written to the JSON body, or to metadata when the address
+//! comes from metadata and the body is not a JSON object.  Database files are
+//! checked for changes periodically and reloaded, keeping the previous database
+//! if the new file cannot be read.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - geoip:
+//!       city_db: "/var/lib/geoip/GeoLite2-City.mmdb"  # Optional: City database
+//!       asn_db: "/var/lib/geoip/GeoLite2-ASN.mmdb"    # Optional: ASN database
+//!       field: "source.ip"                 # Optional: field holding the IP address
+//!       metadata_key: "syslog_source_ip"   # Optional: metadata key holding the IP (default when no field)
+//!       target: "geo"                      # Optional: field to write results to (default: geo)
+//!       language: "en"                     # Optional: language for names (default: en)
+//!       skip_private: true                 # Optional: skip non-routable addresses (default: true)
+//!       reload_interval: 1m                # Optional: how often files are checked for changes
+//! ```
+
+use crate::config::register_plugin;
+use crate::config::ItemType;
+use crate::config::{ConfigSpec, ExecutionType};
+use crate::modules::processors::path::{get_path, parse_path, set_path};
+use crate::Message;
+use crate::MessageBatch;
+use crate::{Closer, Error, Processor};
+use async_trait::async_trait;
+use fiddler_macros::fiddler_registration_func;
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+use serde::Deserialize;
+use serde_json::{Map, Value as JsonValue};
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::RwLock;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, warn};
+
+const DEFAULT_METADATA_KEY: &str = "syslog_source_ip";
+/// Metadata key set when the address cannot be parsed
+const ERROR_METADATA: &str = "geoip_error";
+
+fn default_target() -> String {
+    "geo".into()
+}
+
+fn default_language() -> String {
+    "en".into()
+}
+
+fn default_skip_private() -> bool {
+    true
+}
+
+fn default_reload_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+#[derive(Deserialize)]
+struct GeoIpConfig {
+    city_db: Option<String>,
+    asn_db: Option<String>,
+    field: Option<String>,
+    metadata_key: Option<String>,
+    #[serde(default = "default_target")]
+    target: String,
+    #[serde(default = "default_language")]
+    language: String,
+    #[serde(default = "default_skip_private")]
+    skip_private: bool,
+    #[serde(
+        default = "default_reload_interval",
+        deserialize_with = "crate::deserialize_duration"
+    )]
+    reload_interval: Duration,
+}
+
+/// Where the IP address is read from
+enum Source {
+    Field(Vec<String>),
+    Metadata(String),
+}
+
+struct DatabaseState {
+    reader: Reader<Vec<u8>>,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+/// MaxMind database reloaded when the file changes
+struct Database {
+    path: String,
+    reload_interval: Duration,
+    state: RwLock<DatabaseState>,
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl Database {
+    fn open(path: &str, reload_interval: Duration) -> Result<Self, Error> {
+        let modified = modified(path);
+        let reader = Reader::open_readfile(path).map_err(|e| {
+            Error::ConfigFailedValidation(format!("unable to open GeoIP database {path}: {e}"))
+        })?;
+        Ok(Database {
+            path: path.to_string(),
+            reload_interval,
+            state: RwLock::new(DatabaseState {
+                reader,
+                modified,
+                checked: Instant::now(),
+            }),
+        })
+    }
+
+    /// Reload the database when the file changed, keeping the previous one on failure
+    fn refresh(&self) -> Result<(), Error> {
+        {
+            let state = self
+                .state
+                .read()
+                .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+            if state.checked.elapsed() < self.reload_interval {
+                return Ok(());
+            }
+        }
+
+        let previous = {
+            let mut state = self
+                .state
+                .write()
+                .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+            if state.checked.elapsed() < self.reload_interval {
+                return Ok(());
+            }
+            state.checked = Instant::now();
+            state.modified
+        };
+
+        // The file is read without holding the lock so lookups are not blocked
+        let current = modified(&self.path);
+        if current.is_none() || current == previous {
+            return Ok(());
+        }
+
+        match Reader::open_readfile(&self.path) {
+            Ok(reader) => {
+                debug!(path = self.path, "reloaded GeoIP database");
+                let mut state = self
+                    .state
+                    .write()
+                    .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+                state.reader = reader;
+                state.modified = current;
+            }
+            Err(e) => warn!(path = self.path, error = %e, "failed to reload GeoIP database"),
+        }
+        Ok(())
+    }
+
+    /// Run `f` against the current database, reloading it first if needed
+    fn with_reader<R>(&self, f: impl FnOnce(&Reader<Vec<u8>>) -> R) -> Result<R, Error> {
+        self.refresh()?;
+        let state = self
+            .state
+            .read()
+            .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+        Ok(f(&state.reader))
+    }
+}
+
+/// Returns `true` for addresses that are not publicly routable
+fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_private_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => is_private_v4(v4),
+            None => is_private_v6(ip),
+        },
+    }
+}
+
+fn is_private_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // shared address space (RFC 6598)
+        || (a == 100 && (64..128).contains(&b))
+        // reserved for future use
+        || a >= 240
+}
+
+fn is_private_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // unique local fc00::/7
+        || (first & 0xfe00) == 0xfc00
+        // link local fe80::/10
+        || (first & 0xffc0) == 0xfe80
+        // documentation 2001:db8::/32
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8)
+}
+
+fn name(names: &Option<BTreeMap<&str, &str>>, language: &str) -> Option<JsonValue> {
+    names
+        .as_ref()
+        .and_then(|n| n.get(language))
+        .map(|n| JsonValue::String(n.to_string()))
+}
+
+fn insert(fields: &mut Map<String, JsonValue>, key: &str, value: Option<JsonValue>) {
+    if let Some(value) = value {
+        fields.insert(key.into(), value);
+    }
+}
+
+/// Convert a City record into output fields
+fn city_fields(city: &geoip2::City, language: &str, fields: &mut Map<String, JsonValue>) {
+    if let Some(country) = &city.country {
+        insert(
+            fields,
+            "country_code",
+            country.iso_code.map(JsonValue::from),
+        );
+        insert(fields, "country_name", name(&country.names, language));
+    }
+    if let Some(continent) = &city.continent {
+        insert(
+            fields,
+            "continent_code",
+            continent.code.map(JsonValue::from),
+        );
+        insert(fields, "continent_name", name(&continent.names, language));
+    }
+    if let Some(subdivision) = city.subdivisions.as_ref().and_then(|s| s.first()) {
+        insert(
+            fields,
+            "region_code",
+            subdivision.iso_code.map(JsonValue::from),
+        );
+        insert(fields, "region_name", name(&subdivision.names, language));
+    }
+    if let Some(c) = &city.city {
+        insert(fields, "city_name", name(&c.names, language));
+    }
+    if let Some(postal) = &city.postal {
+        insert(fields, "postal_code", postal.code.map(JsonValue::from));
+    }
+    if let Some(location) = &city.location {
+        if let (Some(lat), Some(lon)) = (location.latitude, location.longitude) {
+            fields.insert(
+                "location".into(),
+                serde_json::json!({"lat": lat, "lon": lon}),
+            );
+        }
+        insert(fields, "timezone", location.time_zone.map(JsonValue::from));
+    }
+}
+
+/// Convert an ASN record into output fields
+fn asn_fields(asn: &geoip2::Asn, fields: &mut Map<String, JsonValue>) {
+    insert(
+        fields,
+        "asn",
+        asn.autonomous_system_number.map(JsonValue::from),
+    );
+    insert(
+        fields,
+        "as_org",
+        asn.autonomous_system_organization.map(JsonValue::from),
+    );
+}
+
+/// Treat a missing record as an empty result
+fn found<T>(result: Result<T, MaxMindDBError>) -> Result<Option<T>, Error> {
+    match result {
+        Ok(record) => Ok(Some(record)),
+        Err(MaxMindDBError::AddressNotFoundError(_)) => Ok(None),
+        Err(e) => Err(Error::ProcessingError(format!("GeoIP lookup failed: {e}"))),
+    }
+}
+
+pub struct GeoIp {
+    city: Option<Database>,
+    asn: Option<Database>,
+    source: Source,
+    target: Vec<String>,
+    /// Metadata key receiving the results when the body is not a JSON object
+    target_key: String,
+    language: String,
+    skip_private: bool,
+}
+
+impl GeoIp {
+    fn lookup(&self, ip: IpAddr) -> Result<Map<String, JsonValue>, Error> {
+        let mut fields = Map::new();
+
+        if let Some(db) = &self.city {
+            db.with_reader(|reader| {
+                found(reader.lookup::<geoip2::City>(ip))
+                    .map(|city| city.map(|c| city_fields(&c, &self.language, &mut fields)))
+            })??;
+        }
+        if let Some(db) = &self.asn {
+            db.with_reader(|reader| {
+                found(reader.lookup::<geoip2::Asn>(ip))
+                    .map(|asn| asn.map(|a| asn_fields(&a, &mut fields)))
+            })??;
+        }
+
+        Ok(fields)
+    }
+}
+
+#[async_trait]
+impl Processor for GeoIp {
+    async fn process(&self, mut message: Message) -> Result<MessageBatch, Error> {
+        // Only a field source needs the body up front, metadata sources may carry plain text
+        let (doc, address) = match &self.source {
+            Source::Field(path) => {
+                let doc: JsonValue = serde_json::from_slice(&message.bytes)
+                    .map_err(|e| Error::MessageFailed(format!("{e}")))?;
+                let address = get_path(&doc, path)
+                    .and_then(|v| v.as_str())
+                    .map(String::from);
+                (Some(doc), address)
+            }
+            Source::Metadata(key) => {
+                let address = message
+                    .metadata
+                    .get(key)
+                    .and_then(|v| v.as_str())
+                    .map(String::from);
+                (None, address)
+            }
+        };
+        let Some(address) = address else {
+            return Ok(vec![message]);
+        };
+
+        let ip: IpAddr = match address.trim().parse() {
+            Ok(ip) => ip,
+            Err(e) => {
+                let error = format!("invalid IP address '{address}': {e}");
+                debug!(error = error, "skipping GeoIP lookup");
+                message
+                    .metadata
+                    .insert(ERROR_METADATA.into(), Value::String(error));
+                return Ok(vec![message]);
+            }
+        };
+        if self.skip_private && is_private(ip) {
+            return Ok(vec![message]);
+        }
+
+        let fields = self.lookup(ip)?;
+        if fields.is_empty() {
+            return Ok(vec![message]);
+        }
+
+        let mut doc = match doc {
+            Some(doc) => doc,
+            None => match serde_json::from_slice(&message.bytes) {
+                Ok(doc @ JsonValue::Object(_)) => doc,
+                _ => {
+                    let value = serde_yaml::to_value(JsonValue::Object(fields))
+                        .map_err(|e| Error::MessageFailed(format!("{e}")))?;
+                    message.metadata.insert(self.target_key.clone(), value);
+                    return Ok(vec![message]);
+                }
+            },
+        };
+
+        set_path(&mut doc, &self.target, JsonValue::Object(fields))?;
+        message.bytes =
+            serde_json::to_vec(&doc).map_err(|e| Error::ProcessingError(format!("{e}")))?;
+        Ok(vec![message])
+    }
+}
+
+impl Closer for GeoIp {}
+
+#[fiddler_registration_func]
+fn create_geoip(conf: Value) -> Result<ExecutionType, Error> {
+    let c: GeoIpConfig = serde_yaml::from_value(conf)?;
+
+    if c.city_db.is_none() && c.asn_db.is_none() {
+        return Err(Error::ConfigFailedValidation(
+            "at least one of city_db or asn_db is required".into(),
+        ));
+    }
+
+    let source = match (c.field, c.metadata_key) {
+        (Some(_), Some(_)) => {
+            return Err(Error::ConfigFailedValidation(
+                "only one of field or metadata_key may be set".into(),
+            ))
+        }
+        (Some(field), None) => Source::Field(parse_path(&field)?),
+        (None, key) => Source::Metadata(key.unwrap_or_else(|| DEFAULT_METADATA_KEY.into())),
+    };
+
+    Ok(ExecutionType::Processor(Box::new(GeoIp {
+        city: c
+            .city_db
+            .map(|p| Database::open(&p, c.reload_interval))
+            .transpose()?,
+        asn: c
+            .asn_db
+            .map(|p| Database::open(&p, c.reload_interval))
+            .transpose()?,
+        source,
+        target: parse_path(&c.target)?,
+        target_key: c.target,
+        language: c.language,
+        skip_private: c.skip_private,
+    })))
+}
+
+pub(super) fn register_geoip() -> Result<(), Error> {
+    let config = "type: object
+properties:
+  city_db:
+    type: string
+  asn_db:
+    type: string
+  field:
+    type: string
+  metadata_key:
+    type: string
+  target:
+    type: string
+  language:
+    type: string
+  skip_private:
+    type: boolean
+  reload_interval:
+    type: string";
+    let conf_spec = ConfigSpec::from_schema(config)?;
+
+    register_plugin("geoip".into(), ItemType::Processor, conf_spec, create_geoip)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::modules::processors::test_util::{body, processor, text_message};
+
+    #[test]
+    fn register_plugin() {
+        register_geoip().unwrap()
+    }
+
+    /// Minimal MaxMind DB encoder, enough to build small test databases
+    mod mmdb {
+        pub enum Data {
+            Str(&'static str),
+            U32(u32),
+            Double(f64),
+            Map(Vec<(&'static str, Data)>),
+            Array(Vec<Data>),
+        }
+
+        fn control(kind: u8, size: usize, out: &mut Vec<u8>) {
+            let (first, extended) = if kind <= 7 {
+                (kind << 5, None)
+            } else {
+                (0, Some(kind - 7))
+            };
+            let first = first | (size.min(29) as u8);
+            out.push(first);
+            out.extend(extended);
+            if size >= 29 {
+                out.push((size - 29) as u8);
+            }
+        }
+
+        fn unsigned(kind: u8, value: u64, out: &mut Vec<u8>) {
+            let bytes: Vec<u8> = value
+                .to_be_bytes()
+                .into_iter()
+                .skip_while(|b| *b == 0)
+                .collect();
+            control(kind, bytes.len(), out);
+            out.extend(bytes);
+        }
+
+        pub fn encode(data: &Data, out: &mut Vec<u8>) {
+            match data {
+                Data::Str(s) => {
+                    control(2, s.len(), out);
+                    out.extend(s.as_bytes());
+                }
+                Data::U32(v) => unsigned(6, *v as u64, out),
+                Data::Double(v) => {
+                    control(3, 8, out);
+                    out.extend(v.to_be_bytes());
+                }
+                Data::Map(pairs) => {
+                    control(7, pairs.len(), out);
+                    for (key, value) in pairs {
+                        encode(&Data::Str(key), out);
+                        encode(value, out);
+                    }
+                }
+                Data::Array(items) => {
+                    control(11, items.len(), out);
+                    for item in items {
+                        encode(item, out);
+                    }
+                }
+            }
+        }
+
+        /// IPv4 database whose single node maps 0.0.0.0/1 to `record`;
+        /// addresses in 128.0.0.0/1 are not found.
+        pub fn database(record: &Data) -> Vec<u8> {
+            let node_count: u32 = 1;
+            let mut out = Vec::new();
+            out.extend(&(node_count + 16).to_be_bytes()[1..]);
+            out.extend(&node_count.to_be_bytes()[1..]);
+            out.extend([0u8; 16]);
+            encode(record, &mut out);
+
+            out.extend(b"\xab\xcd\xefMaxMind.com");
+            let mut meta = Vec::new();
+            control(7, 9, &mut meta);
+            for (key, kind, value) in [
+                ("binary_format_major_version", 5, 2u64),
+                ("binary_format_minor_version", 5, 0),
+                ("build_epoch", 9, 0),
+                ("ip_version", 5, 4),
+                ("node_count", 6, node_count as u64),
+                ("record_size", 5, 24),
+            ] {
+                encode(&Data::Str(key), &mut meta);
+                unsigned(kind, value, &mut meta);
+            }
+            encode(&Data::Str("database_type"), &mut meta);
+            encode(&Data::Str("Test"), &mut meta);
+            encode(&Data::Str("description"), &mut meta);
+            encode(&Data::Map(vec![]), &mut meta);
+            encode(&Data::Str("languages"), &mut meta);
+            encode(&Data::Array(vec![Data::Str("en")]), &mut meta);
+            out.extend(meta);
+            out
+        }
+    }
+
+    use mmdb::Data;
+
+    fn names(en: &'static str) -> Data {
+        Data::Map(vec![("en", Data::Str(en))])
+    }
+
+    fn city_record(city: &'static str) -> Data {
+        Data::Map(vec![
+            ("city", Data::Map(vec![("names", names(city))])),
+            (
+                "continent",
+                Data::Map(vec![
+                    ("code", Data::Str("NA")),
+                    ("names", names("North America")),
+                ]),
+            ),
+            (
+                "country",
+                Data::Map(vec![
+                    ("iso_code", Data::Str("US")),
+                    ("names", names("United States")),
+                ]),
+            ),
+            (
+                "location",
+                Data::Map(vec![
+                    ("latitude", Data::Double(37.5)),
+                    ("longitude", Data::Double(-122.25)),
+                    ("time_zone", Data::Str("America/Los_Angeles")),
+                ]),
+            ),
+            ("postal", Data::Map(vec![("code", Data::Str("94035"))])),
+            (
+                "subdivisions",
+                Data::Array(vec![Data::Map(vec![
+                    ("iso_code", Data::Str("CA")),
+                    ("names", names("California")),
+                ])]),
+            ),
+        ])
+    }
+
+    fn asn_record() -> Data {
+        Data::Map(vec![
+            ("autonomous_system_number", Data::U32(15169)),
+            ("autonomous_system_organization", Data::Str("GOOGLE")),
+        ])
+    }
+
+    fn write_db(record: &Data) -> String {
+        let path =
+            std::env::temp_dir().join(format!("fiddler_geoip_{}.mmdb", uuid::Uuid::new_v4()));
+        std::fs::write(&path, mmdb::database(record)).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    #[tokio::test]
+    async fn city_and_asn_from_field() {
+        let city = write_db(&city_record("Mountain View"));
+        let asn = write_db(&asn_record());
+        let p = processor(
+            create_geoip,
+            &format!("city_db: {city}\nasn_db: {asn}\nfield: source.ip"),
+        )
+        .await;
+
+        let result = p
+            .process(text_message(r#"{"source": {"ip": "8.8.8.8"}}"#))
+            .await
+            .unwrap();
+        assert_eq!(
+            body(&result[0])["geo"],
+            serde_json::json!({
+                "country_code": "US",
+                "country_name": "United States",
+                "continent_code": "NA",
+                "continent_name": "North America",
+                "region_code": "CA",
+                "region_name": "California",
+                "city_name": "Mountain View",
+                "postal_code": "94035",
+                "location": {"lat": 37.5, "lon": -122.25},
+                "timezone": "America/Los_Angeles",
+                "asn": 15169,
+                "as_org": "GOOGLE",
+            })
+        );
+
+        std::fs::remove_file(city).unwrap();
+        std::fs::remove_file(asn).unwrap();
+    }
+
+    #[tokio::test]
+    async fn metadata_source_and_skipped_addresses() {
+        let asn = write_db(&asn_record());
+        let p = processor(create_geoip, &format!("asn_db: {asn}\ntarget: network")).await;
+
+        for (ip, enriched) in [
+            ("8.8.4.4", true),
+            ("10.1.2.3", false),
+            ("192.168.0.1", false),
+            ("127.0.0.1", false),
+            ("100.64.0.1", false),
+            ("::ffff:172.16.0.1", false),
+            ("fd00::1", false),
+            ("200.1.1.1", false),
+        ] {
+            let mut msg = text_message("{}");
+            msg.metadata
+                .insert(DEFAULT_METADATA_KEY.into(), Value::String(ip.into()));
+            let result = p.process(msg).await.unwrap();
+            assert_eq!(body(&result[0]).get("network").is_some(), enriched, "{ip}");
+        }
+
+        let unchanged = p.process(text_message(r#"{"a":1}"#)).await.unwrap();
+        assert_eq!(unchanged[0].bytes, br#"{"a":1}"#.to_vec());
+
+        let mut invalid = text_message("{}");
+        invalid
+            .metadata
+            .insert(DEFAULT_METADATA_KEY.into(), Value::String("nope".into()));
+        let result = p.process(invalid).await.unwrap();
+        assert_eq!(result[0].bytes, b"{}".to_vec());
+        assert_eq!(
+            result[0].metadata.get(ERROR_METADATA),
+            Some(&Value::String(
+                "invalid IP address 'nope': invalid IP address syntax".into()
+            ))
+        );
+
+        std::fs::remove_file(asn).unwrap();
+    }
+
+    #[tokio::test]
+    async fn plain_text_body_from_metadata() {
+        let asn = write_db(&asn_record());
+        let p = processor(create_geoip, &format!("asn_db: {asn}\ntarget: network")).await;
+
+        let mut msg = text_message("<34>Oct 11 22:14:15 host su: 'su root' failed");
+        msg.metadata
+            .insert(DEFAULT_METADATA_KEY.into(), Value::String("8.8.4.4".into()));
+        let result = p.process(msg).await.unwrap();
+        assert_eq!(
+            result[0].bytes,
+            b"<34>Oct 11 22:14:15 host su: 'su root' failed".to_vec()
+        );
+        let network: JsonValue =
+            serde_yaml::from_value(result[0].metadata["network"].clone()).unwrap();
+        assert_eq!(
+            network,
+            serde_json::json!({"asn": 15169, "as_org": "GOOGLE"})
+        );
+
+        // a field source still requires a JSON body
+        let p = processor(create_geoip, &format!("asn_db: {asn}\nfield: ip")).await;
+        let result = p.process(text_message("not json")).await;
+        assert!(matches!(result, Err(Error::MessageFailed(_))));
+
+        std::fs::remove_file(asn).unwrap();
+    }
+
+    #[tokio::test]
+    async fn private_addresses_can_be_looked_up() {
+        let asn = write_db(&asn_record());
+        let p = processor(
+            create_geoip,
+            &format!("asn_db: {asn}\nfield: ip\nskip_private: false"),
+        )
+        .await;
+
+        let result = p
+            .process(text_message(r#"{"ip": "10.0.0.1"}"#))
+            .await
+            .unwrap();
+        assert_eq!(body(&result[0])["geo"]["asn"], 15169);
+
+        std::fs::remove_file(asn).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reload_on_change() {
+        let path = write_db(&city_record("Mountain View"));
+        let p = processor(
+            create_geoip,
+            &format!("city_db: {path}\nfield: ip\nreload_interval: 0s"),
+        )
+        .await;
+
+        let result = p
+            .process(text_message(r#"{"ip": "8.8.8.8"}"#))
+            .await
+            .unwrap();
+        assert_eq!(body(&result[0])["geo"]["city_name"], "Mountain View");
+
+        // move the modification time forward for coarse-grained filesystems
+        std::fs::write(&path, mmdb::database(&city_record("Sunnyvale"))).unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+
+        let result = p
+            .process(text_message(r#"{"ip": "8.8.8.8"}"#))
+            .await
+            .unwrap();
+        assert_eq!(body(&result[0])["geo"]["city_name"], "Sunnyvale");
+
+        // a corrupt file keeps the previous database
+        std::fs::write(&path, b"corrupt").unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        let result = p
+            .process(text_message(r#"{"ip": "8.8.8.8"}"#))
+            .await
+            .unwrap();
+        assert_eq!(body(&result[0])["geo"]["city_name"], "Sunnyvale");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn private_ranges() {
+        for ip in [
+            "10.0.0.1",
+            "172.31.255.255",
+            "169.254.1.1",
+            "0.0.0.0",
+            "240.0.0.1",
+            "::1",
+            "fe80::1",
+            "2001:db8::1",
+        ] {
+            assert!(is_private(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "8.8.8.8",
+            "100.128.0.1",
+            "2606:4700::1111",
+            "::ffff:1.1.1.1",
+        ] {
+            assert!(!is_private(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_config() {
+        let asn = write_db(&asn_record());
+        for conf in [
+            "field: ip".to_string(),
+            "asn_db: /does/not/exist.mmdb".to_string(),
+            format!("asn_db: {asn}\nfield: ip\nmetadata_key: ip"),
+            format!("asn_db: {asn}\ntarget: 'a..b'"),
+        ] {
+            let value: Value = serde_yaml::from_str(&conf).unwrap();
+            assert!(create_geoip(value).await.is_err(), "{conf}");
+        }
+        std::fs::remove_file(asn).unwrap();
+    }
+}
@@ -0,0 +1,585 @@
+//! Grok processor for turning unstructured text into structured JSON fields.
+//!
+//! Patterns are written with `%{PATTERN}` references to the bundled library
+//! (or custom `pattern_definitions`), `%{PATTERN:field}` to capture a field and
+//! `%{PATTERN:field:int}` / `%{PATTERN:field:float}` to convert the capture.
+//! Plain named captures such as `(?<field>...)` are supported as well.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - grok:
+//!       patterns:                          # Required: tried in order, first match wins
+//!         - "%{SYSLOGBASE} %{GREEDYDATA:message}"
+//!       pattern_definitions:               # Optional: custom or overriding patterns
+//!         SSHUSER: "[a-z_][a-z0-9_-]*"
+//!       field: "log.line"                  # Optional: JSON field holding the text
+//!       metadata: "syslog_raw"             # Optional: metadata key holding the text
+//!       target: "parsed"                   # Optional: path to place captured fields under
+//!       on_no_match: passthrough           # Optional: error, drop or passthrough (default: error)
+//!       tag: "_grokparsefailure"           # Optional: metadata key set on passthrough
+//! ```
+
+use super::path::{get_path, parse_path, set_path};
+use crate::config::register_plugin;
+use crate::config::ItemType;
+use crate::config::{ConfigSpec, ExecutionType};
+use crate::Message;
+use crate::MessageBatch;
+use crate::{Closer, Error, Processor};
+use async_trait::async_trait;
+use fiddler_macros::fiddler_registration_func;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{Map, Value as JsonValue};
+use serde_yaml::Value;
+use std::collections::HashMap;
+mod patterns;
+
+/// Maximum nesting of pattern references before expansion is aborted
+const MAX_EXPANSION_DEPTH: usize = 32;
+
+/// Prefix of the capture group names generated for `%{PATTERN:field}` references
+const GROUP_PREFIX: &str = "__grok";
+
+static REFERENCE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"%\{(\w+)(?::([^:}]+))?(?::(int|float))?\}")
+        .expect("grok reference pattern is valid")
+});
+
+fn default_tag() -> String {
+    "_grokparsefailure".into()
+}
+
+/// Behaviour when none of the configured patterns match
+#[derive(Deserialize, Clone, Default, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NoMatch {
+    /// Fail processing of the message
+    #[default]
+    Error,
+    /// Drop the message from the pipeline
+    Drop,
+    /// Forward the message unchanged with the failure tag set in metadata
+    Passthrough,
+}
+
+#[derive(Deserialize)]
+struct GrokConfig {
+    patterns: Vec<String>,
+    #[serde(default)]
+    pattern_definitions: HashMap<String, String>,
+    field: Option<String>,
+    metadata: Option<String>,
+    target: Option<String>,
+    #[serde(default)]
+    on_no_match: NoMatch,
+    #[serde(default = "default_tag")]
+    tag: String,
+}
+
+#[derive(Clone, Copy)]
+enum Conversion {
+    Int,
+    Float,
+}
+
+struct CaptureField {
+    path: Vec<String>,
+    conversion: Option<Conversion>,
+}
+
+struct CompiledPattern {
+    regex: Regex,
+    fields: HashMap<String, CaptureField>,
+}
+
+enum Source {
+    Body,
+    Field(Vec<String>),
+    Metadata(String),
+}
+
+pub struct Grok {
+    patterns: Vec<CompiledPattern>,
+    source: Source,
+    target: Vec<String>,
+    on_no_match: NoMatch,
+    tag: String,
+}
+
+/// Recursively replace `%{...}` references with their regular expressions.
+fn expand(
+    pattern: &str,
+    definitions: &HashMap<String, String>,
+    fields: &mut HashMap<String, CaptureField>,
+    depth: usize,
+) -> Result<String, Error> {
+    if depth > MAX_EXPANSION_DEPTH {
+        return Err(Error::ConfigFailedValidation(format!(
+            "grok pattern '{pattern}' exceeds the maximum nesting depth"
+        )));
+    }
+
+    let mut output = String::with_capacity(pattern.len());
+    let mut last = 0;
+    for caps in REFERENCE.captures_iter(pattern) {
+        let (Some(whole), Some(name)) = (caps.get(0), caps.get(1)) else {
+            continue;
+        };
+        output.push_str(&pattern[last..whole.start()]);
+        last = whole.end();
+
+        let definition = definitions.get(name.as_str()).ok_or_else(|| {
+            Error::ConfigFailedValidation(format!("unknown grok pattern '{}'", name.as_str()))
+        })?;
+        let inner = expand(definition, definitions, fields, depth + 1)?;
+
+        match caps.get(2) {
+            Some(field) => {
+                let group = format!("{GROUP_PREFIX}{}", fields.len());
+                let conversion = caps.get(3).map(|c| match c.as_str() {
+                    "int" => Conversion::Int,
+                    _ => Conversion::Float,
+                });
+                fields.insert(
+                    group.clone(),
+                    CaptureField {
+                        path: parse_path(field.as_str())?,
+                        conversion,
+                    },
+                );
+                output.push_str(&format!("(?P<{group}>{inner})"));
+            }
+            None => output.push_str(&format!("(?:{inner})")),
+        }
+    }
+    output.push_str(&pattern[last..]);
+    Ok(output)
+}
+
+fn compile(pattern: &str, definitions: &HashMap<String, String>) -> Result<CompiledPattern, Error> {
+    let mut fields = HashMap::new();
+    let expanded = expand(pattern, definitions, &mut fields, 0)?;
+    let regex = Regex::new(&expanded).map_err(|e| {
+        Error::ConfigFailedValidation(format!("invalid grok pattern '{pattern}': {e}"))
+    })?;
+    Ok(CompiledPattern { regex, fields })
+}
+
+fn convert(value: &str, conversion: Option<Conversion>) -> JsonValue {
+    match conversion {
+        Some(Conversion::Int) => value
+            .parse::<i64>()
+            .map(JsonValue::from)
+            .unwrap_or_else(|_| JsonValue::String(value.into())),
+        Some(Conversion::Float) => value
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map(JsonValue::Number)
+            .unwrap_or_else(|| JsonValue::String(value.into())),
+        None => JsonValue::String(value.into()),
+    }
+}
+
+impl Grok {
+    /// Return the captured fields of the first matching pattern.
+    fn extract(&self, text: &str) -> Option<Vec<(Vec<String>, JsonValue)>> {
+        for p in &self.patterns {
+            let Some(caps) = p.regex.captures(text) else {
+                continue;
+            };
+            let mut values = Vec::new();
+            for name in p.regex.capture_names().flatten() {
+                let Some(m) = caps.name(name) else {
+                    continue;
+                };
+                match p.fields.get(name) {
+                    Some(f) => values.push((f.path.clone(), convert(m.as_str(), f.conversion))),
+                    None => values.push((vec![name.to_string()], convert(m.as_str(), None))),
+                }
+            }
+            return Some(values);
+        }
+        None
+    }
+
+    fn no_match(&self, mut message: Message) -> Result<MessageBatch, Error> {
+        match self.on_no_match {
+            NoMatch::Error => Err(Error::MessageFailed(
+                "no grok pattern matched the message".into(),
+            )),
+            NoMatch::Drop => Ok(Vec::new()),
+            NoMatch::Passthrough => {
+                message.metadata.insert(self.tag.clone(), Value::Bool(true));
+                Ok(vec![message])
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Processor for Grok {
+    async fn process(&self, mut message: Message) -> Result<MessageBatch, Error> {
+        let document: Option<JsonValue> = serde_json::from_slice::<JsonValue>(&message.bytes)
+            .ok()
+            .filter(|d| d.is_object());
+
+        let text = match &self.source {
+            Source::Body => Some(
+                String::from_utf8(message.bytes.clone())
+                    .map_err(|e| Error::MessageFailed(format!("{e}")))?,
+            ),
+            Source::Field(path) => {
+                let doc = document.as_ref().ok_or_else(|| {
+                    Error::MessageFailed("grok field source requires a JSON object".into())
+                })?;
+                get_path(doc, path)
+                    .and_then(|v| v.as_str())
+                    .map(|s| s.to_string())
+            }
+            Source::Metadata(key) => message
+                .metadata
+                .get(key)
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string()),
+        };
+
+        let Some(values) = text.and_then(|t| self.extract(&t)) else {
+            return self.no_match(message);
+        };
+
+        let mut output = document.unwrap_or_else(|| JsonValue::Object(Map::new()));
+        for (path, value) in values {
+            let full_path: Vec<String> = self.target.iter().chain(path.iter()).cloned().collect();
+            set_path(&mut output, &full_path, value)?;
+        }
+
+        message.bytes =
+            serde_json::to_vec(&output).map_err(|e| Error::ProcessingError(format!("{e}")))?;
+        Ok(vec![message])
+    }
+}
+
+impl Closer for Grok {}
+
+#[fiddler_registration_func]
+fn create_grok(conf: Value) -> Result<ExecutionType, Error> {
+    let c: GrokConfig = serde_yaml::from_value(conf)?;
+
+    let mut definitions: HashMap<String, String> = patterns::BUILTIN_PATTERNS
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    definitions.extend(c.pattern_definitions);
+
+    let compiled = c
+        .patterns
+        .iter()
+        .map(|p| compile(p, &definitions))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let source = match (c.field, c.metadata) {
+        (Some(_), Some(_)) => {
+            return Err(Error::ConfigFailedValidation(
+                "only one of field or metadata may be provided".into(),
+            ))
+        }
+        (Some(f), None) => Source::Field(parse_path(&f)?),
+        (None, Some(m)) => Source::Metadata(m),
+        (None, None) => Source::Body,
+    };
+
+    let target = match c.target {
+        Some(t) => parse_path(&t)?,
+        None => Vec::new(),
+    };
+
+    Ok(ExecutionType::Processor(Box::new(Grok {
+        patterns: compiled,
+        source,
+        target,
+        on_no_match: c.on_no_match,
+        tag: c.tag,
+    })))
+}
+
+pub(super) fn register_grok() -> Result<(), Error> {
+    let config = "type: object
+properties:
+  patterns:
+    type: array
+    minItems: 1
+    items:
+      type: string
+  pattern_definitions:
+    type: object
+    additionalProperties:
+      type: string
+  field:
+    type: string
+  metadata:
+    type: string
+  target:
+    type: string
+  on_no_match:
+    type: string
+    enum:
+      - error
+      - drop
+      - passthrough
+  tag:
+    type: string
+required:
+  - patterns";
+    let conf_spec = ConfigSpec::from_schema(config)?;
+
+    register_plugin("grok".into(), ItemType::Processor, conf_spec, create_grok)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::ParsedRegisteredItem;
+    use crate::modules::processors::run_processor;
+    use crate::runtime::{InternalMessage, MessageStatus};
+    use serde_json::json;
+
+    #[test]
+    fn register_plugin() {
+        register_grok().unwrap()
+    }
+
+    async fn build(conf: &str) -> Box<dyn Processor + Send + Sync> {
+        let conf: Value = serde_yaml::from_str(conf).unwrap();
+        match create_grok(conf).await.unwrap() {
+            ExecutionType::Processor(p) => p,
+            _ => panic!("expected processor"),
+        }
+    }
+
+    fn body(messages: &[Message]) -> JsonValue {
+        serde_json::from_slice(&messages[0].bytes).unwrap()
+    }
+
+    #[test]
+    fn builtin_patterns_compile() {
+        let definitions: HashMap<String, String> = patterns::BUILTIN_PATTERNS
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        for (name, _) in patterns::BUILTIN_PATTERNS {
+            compile(&format!("%{{{name}}}"), &definitions).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn nginx_access_log() {
+        let p = build(r#"patterns: ["%{COMBINEDAPACHELOG}"]"#).await;
+        let msg = Message {
+            bytes: br#"10.0.0.1 - - [10/Oct/2023:13:55:36 +0000] "GET /index.html HTTP/1.1" 200 2326 "-" "curl/8.0""#.to_vec(),
+            ..Default::default()
+        };
+        let output = body(&p.process(msg).await.unwrap());
+        assert_eq!(output["clientip"], "10.0.0.1");
+        assert_eq!(output["verb"], "GET");
+        assert_eq!(output["request"], "/index.html");
+        assert_eq!(output["response"], "200");
+        assert_eq!(output["bytes"], "2326");
+        assert_eq!(output["timestamp"], "10/Oct/2023:13:55:36 +0000");
+        assert_eq!(output["agent"], "\"curl/8.0\"");
+    }
+
+    #[tokio::test]
+    async fn sshd_with_conversion_and_custom_pattern() {
+        let p = build(
+            r#"patterns:
+  - "Failed password for %{SSHUSER:user.name} from %{IP:source.ip} port %{INT:source.port:int}"
+pattern_definitions:
+  SSHUSER: "[a-z_][a-z0-9_-]*""#,
+        )
+        .await;
+        let msg = Message {
+            bytes: b"Failed password for root from 192.168.1.20 port 52144 ssh2".to_vec(),
+            ..Default::default()
+        };
+        let output = body(&p.process(msg).await.unwrap());
+        assert_eq!(
+            output,
+            json!({"user": {"name": "root"}, "source": {"ip": "192.168.1.20", "port": 52144}})
+        );
+    }
+
+    #[tokio::test]
+    async fn named_capture_regex() {
+        let p = build(r#"patterns: ["action=(?<action>\\w+) bytes=(?P<bytes>\\d+)"]"#).await;
+        let msg = Message {
+            bytes: b"fw: action=DROP bytes=60".to_vec(),
+            ..Default::default()
+        };
+        let output = body(&p.process(msg).await.unwrap());
+        assert_eq!(output, json!({"action": "DROP", "bytes": "60"}));
+    }
+
+    #[tokio::test]
+    async fn first_matching_pattern_wins() {
+        let p = build(
+            r#"patterns:
+  - "^%{IPV6:addr}$"
+  - "^%{IPV4:addr}$""#,
+        )
+        .await;
+        let msg = Message {
+            bytes: b"fe80::1".to_vec(),
+            ..Default::default()
+        };
+        assert_eq!(
+            body(&p.process(msg).await.unwrap()),
+            json!({"addr": "fe80::1"})
+        );
+        let msg = Message {
+            bytes: b"10.1.2.3".to_vec(),
+            ..Default::default()
+        };
+        assert_eq!(
+            body(&p.process(msg).await.unwrap()),
+            json!({"addr": "10.1.2.3"})
+        );
+    }
+
+    #[tokio::test]
+    async fn field_source_merges_into_document() {
+        let p = build(
+            r#"patterns: ["%{LOGLEVEL:level} %{GREEDYDATA:text}"]
+field: log.line
+target: parsed"#,
+        )
+        .await;
+        let msg = Message {
+            bytes: br#"{"host": "a", "log": {"line": "ERROR disk full"}}"#.to_vec(),
+            ..Default::default()
+        };
+        let output = body(&p.process(msg).await.unwrap());
+        assert_eq!(output["host"], "a");
+        assert_eq!(
+            output["parsed"],
+            json!({"level": "ERROR", "text": "disk full"})
+        );
+    }
+
+    #[tokio::test]
+    async fn metadata_source() {
+        let p = build(
+            r#"patterns: ["%{SYSLOGPROG}: %{GREEDYDATA:text}"]
+metadata: syslog_raw"#,
+        )
+        .await;
+        let mut metadata = HashMap::new();
+        metadata.insert(
+            "syslog_raw".to_string(),
+            Value::String("sshd[123]: session opened".into()),
+        );
+        let msg = Message {
+            bytes: b"session opened".to_vec(),
+            metadata,
+            ..Default::default()
+        };
+        let output = body(&p.process(msg).await.unwrap());
+        assert_eq!(
+            output,
+            json!({"program": "sshd", "pid": "123", "text": "session opened"})
+        );
+    }
+
+    #[tokio::test]
+    async fn no_match_behaviours() {
+        let msg = Message {
+            bytes: b"nothing to see".to_vec(),
+            ..Default::default()
+        };
+
+        let p = build(r#"patterns: ["^%{IP:ip}"]"#).await;
+        assert!(matches!(
+            p.process(msg.clone()).await,
+            Err(Error::MessageFailed(_))
+        ));
+
+        let p = build("patterns: [\"^%{IP:ip}\"]\non_no_match: drop").await;
+        assert!(p.process(msg.clone()).await.unwrap().is_empty());
+
+        let p = build("patterns: [\"^%{IP:ip}\"]\non_no_match: passthrough").await;
+        let output = p.process(msg.clone()).await.unwrap();
+        assert_eq!(output[0].bytes, msg.bytes);
+        assert_eq!(
+            output[0].metadata.get("_grokparsefailure"),
+            Some(&Value::Bool(true))
+        );
+    }
+
+    #[tokio::test]
+    async fn no_match_fails_message_in_pipeline() {
+        let (input_tx, input_rx) = flume::bounded(10);
+        let (output_tx, output_rx) = flume::bounded(10);
+        let (state_tx, state_rx) = flume::bounded(10);
+        let (handle_tx, _handle_rx) = flume::bounded(10);
+        let item = ParsedRegisteredItem {
+            creator: create_grok,
+            config: serde_yaml::from_str(r#"patterns: ["^%{IP:ip}"]"#).unwrap(),
+        };
+        let task = tokio::spawn(run_processor(
+            item, output_tx, input_rx, state_tx, handle_tx,
+        ));
+
+        for (message_id, body) in [("unmatched", "nothing to see"), ("matched", "10.0.0.1 up")] {
+            input_tx
+                .send_async(InternalMessage {
+                    message: Message {
+                        bytes: body.as_bytes().to_vec(),
+                        ..Default::default()
+                    },
+                    message_id: message_id.into(),
+                    status: MessageStatus::New,
+                })
+                .await
+                .unwrap();
+        }
+        drop(input_tx);
+        // The unmatched line fails on its own and the processor keeps running
+        task.await.unwrap().unwrap();
+
+        let output: Vec<InternalMessage> = output_rx.drain().collect();
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].message_id, "matched");
+        let states: Vec<(String, String)> = state_rx
+            .drain()
+            .map(|s| (s.message_id, s.status.to_string()))
+            .collect();
+        assert_eq!(states, vec![("unmatched".into(), "ProcessError".into())]);
+    }
+
+    #[tokio::test]
+    async fn unknown_pattern_rejected() {
+        let conf: Value = serde_yaml::from_str(r#"patterns: ["%{NOPE:x}"]"#).unwrap();
+        assert!(matches!(
+            create_grok(conf).await,
+            Err(Error::ConfigFailedValidation(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn recursive_pattern_rejected() {
+        let conf: Value = serde_yaml::from_str(
+            r#"patterns: ["%{LOOP}"]
+pattern_definitions:
+  LOOP: "a%{LOOP}""#,
+        )
+        .unwrap();
+        assert!(matches!(
+            create_grok(conf).await,
+            Err(Error::ConfigFailedValidation(_))
+        ));
+    }
+}
@@ -0,0 +1,565 @@
+//! HTTP processor for enriching or transforming messages with a remote service.
+//!
+//! Each message is sent to the configured endpoint, either as-is or through a
+//! body template, and the response body replaces the message or is merged into
+//! it.  The URL and body templates replace `${path}` placeholders with fields
+//! of the JSON message, percent-encoded in the URL and JSON-escaped in the
+//! body.  The response status code and headers are added to the message
+//! metadata.  Server errors and failed connections are retried according to the
+//! optional retry policy.  Requests that still fail, or return a non-2xx
+//! status, fail only the message being processed.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - http:
+//!       url: "https://api.example.com/users/${user.id}"  # Required: endpoint, may use ${path}
+//!       method: "GET"                        # Optional: GET, POST, PUT, PATCH or DELETE (default: POST)
+//!       headers:                             # Optional: custom headers
+//!         Accept: "application/json"
+//!       auth:                                # Optional: basic or bearer authentication
+//!         type: "bearer"
+//!         token: "secret-token"
+//!       body: '{"id": "${user.id}"}'         # Optional: request body template
+//!       result: merge                        # Optional: replace or merge (default: replace)
+//!       target: "user.details"               # Optional: field to merge the response into
+//!       timeout_secs: 30                     # Optional: request timeout (default: 30)
+//!       retry:                               # Optional: retry policy for 5xx responses
+//!         max_retries: 3
+//!         initial_wait: 1s
+//!       tls:                                 # Optional: client TLS configuration
+//!         ca: /etc/ssl/ca.pem
+//! ```
+
+use crate::config::register_plugin;
+use crate::config::ItemType;
+use crate::config::{ConfigSpec, ExecutionType};
+use crate::modules::outputs::http::{build_client, build_request, AuthConfig};
+use crate::modules::processors::path::{parse_path, set_path, Escape, FieldTemplate};
+use crate::modules::tls::ClientTlsConfig;
+use crate::Message;
+use crate::MessageBatch;
+use crate::RetryPolicy;
+use crate::{Closer, Error, Processor};
+use async_trait::async_trait;
+use fiddler_macros::fiddler_registration_func;
+use reqwest::{Client, Method, StatusCode};
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
+use std::str::FromStr;
+use tracing::warn;
+
+const STATUS_CODE_METADATA: &str = "http_status_code";
+const HEADERS_METADATA: &str = "http_headers";
+
+fn default_method() -> String {
+    "POST".into()
+}
+
+fn default_timeout_secs() -> u64 {
+    30
+}
+
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ResultMode {
+    #[default]
+    Replace,
+    Merge,
+}
+
+#[derive(Deserialize)]
+struct HttpProcessorConfig {
+    url: String,
+    #[serde(default = "default_method")]
+    method: String,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    auth: Option<AuthConfig>,
+    body: Option<String>,
+    #[serde(default)]
+    result: ResultMode,
+    target: Option<String>,
+    #[serde(default = "default_timeout_secs")]
+    timeout_secs: u64,
+    retry: Option<RetryPolicy>,
+    tls: Option<ClientTlsConfig>,
+}
+
+/// Response received from the endpoint
+struct Response {
+    status: StatusCode,
+    headers: Mapping,
+    body: Vec<u8>,
+}
+
+pub struct HttpProcessor {
+    client: Client,
+    url: FieldTemplate,
+    method: Method,
+    headers: HashMap<String, String>,
+    auth: Option<AuthConfig>,
+    body: Option<FieldTemplate>,
+    result: ResultMode,
+    target: Option<Vec<String>>,
+    retry: Option<RetryPolicy>,
+}
+
+impl HttpProcessor {
+    /// Send the request, retrying server errors and failed connections
+    async fn send(&self, url: &str, body: Vec<u8>) -> Result<Response, Error> {
+        let max_attempts = self.retry.as_ref().map_or(1, |r| r.max_retries + 1);
+        let mut attempt = 0;
+
+        loop {
+            let req = build_request(
+                &self.client,
+                &self.method,
+                url,
+                &self.headers,
+                &self.auth,
+                body.clone(),
+            );
+
+            let error = match req.send().await {
+                Ok(response) if !response.status().is_server_error() => {
+                    let status = response.status();
+                    let mut headers = Mapping::new();
+                    for name in response.headers().keys() {
+                        let values: Vec<&str> = response
+                            .headers()
+                            .get_all(name)
+                            .iter()
+                            .filter_map(|v| v.to_str().ok())
+                            .collect();
+                        headers.insert(name.as_str().into(), values.join(", ").into());
+                    }
+                    let body = response.bytes().await.map_err(|e| {
+                        Error::MessageFailed(format!("failed to read HTTP response: {e}"))
+                    })?;
+                    return Ok(Response {
+                        status,
+                        headers,
+                        body: body.to_vec(),
+                    });
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let text = response.text().await.unwrap_or_default();
+                    format!("HTTP request failed with status {status}: {text}")
+                }
+                Err(e) => format!("HTTP request failed: {e}"),
+            };
+
+            attempt += 1;
+            if attempt >= max_attempts {
+                return Err(Error::MessageFailed(error));
+            }
+
+            let wait = self
+                .retry
+                .as_ref()
+                .map(|r| r.compute_wait(attempt - 1))
+                .unwrap_or_default();
+            warn!(
+                attempt = attempt,
+                max_retries = max_attempts - 1,
+                wait_ms = wait.as_millis() as u64,
+                error = error,
+                "http processor request failed, retrying"
+            );
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    fn needs_document(&self) -> bool {
+        self.result == ResultMode::Merge
+            || self.url.has_fields()
+            || self.body.as_ref().is_some_and(|b| b.has_fields())
+    }
+}
+
+#[async_trait]
+impl Processor for HttpProcessor {
+    async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
+        let doc: JsonValue = if self.needs_document() {
+            serde_json::from_slice(&message.bytes)
+                .map_err(|e| Error::MessageFailed(format!("{e}")))?
+        } else {
+            JsonValue::Null
+        };
+
+        let url = self.url.render_escaped(&doc, Escape::Url)?;
+        let body = match &self.body {
+            Some(template) => template.render_escaped(&doc, Escape::Json)?.into_bytes(),
+            None if self.method == Method::GET || self.method == Method::DELETE => Vec::new(),
+            None => message.bytes.clone(),
+        };
+
+        let response = self.send(&url, body).await?;
+        if !response.status.is_success() {
+            return Err(Error::MessageFailed(format!(
+                "HTTP request failed with status {}: {}",
+                response.status,
+                String::from_utf8_lossy(&response.body)
+            )));
+        }
+
+        let mut message = message;
+        message.bytes = match self.result {
+            ResultMode::Replace => response.body,
+            ResultMode::Merge => {
+                let result: JsonValue = serde_json::from_slice(&response.body).map_err(|e| {
+                    Error::MessageFailed(format!("HTTP response is not valid JSON: {e}"))
+                })?;
+                let mut doc = doc;
+                match (&self.target, &mut doc, result) {
+                    (Some(path), doc, result) => set_path(doc, path, result)?,
+                    (None, JsonValue::Object(root), JsonValue::Object(fields)) => {
+                        root.extend(fields)
+                    }
+                    _ => {
+                        return Err(Error::MessageFailed(
+                            "merging without a target requires JSON objects".into(),
+                        ))
+                    }
+                }
+                serde_json::to_vec(&doc).map_err(|e| Error::ProcessingError(format!("{e}")))?
+            }
+        };
+
+        message.metadata.insert(
+            STATUS_CODE_METADATA.into(),
+            Value::Number(response.status.as_u16().into()),
+        );
+        message
+            .metadata
+            .insert(HEADERS_METADATA.into(), Value::Mapping(response.headers));
+
+        Ok(vec![message])
+    }
+}
+
+impl Closer for HttpProcessor {}
+
+#[fiddler_registration_func]
+fn create_http(conf: Value) -> Result<ExecutionType, Error> {
+    let c: HttpProcessorConfig = serde_yaml::from_value(conf.clone())?;
+
+    let method = Method::from_str(&c.method.to_uppercase())
+        .ok()
+        .filter(|m| {
+            [
+                Method::GET,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+            ]
+            .contains(m)
+        })
+        .ok_or_else(|| {
+            Error::ConfigFailedValidation(format!("unsupported HTTP method '{}'", c.method))
+        })?;
+
+    reqwest::Url::parse(&c.url)
+        .map_err(|e| Error::ConfigFailedValidation(format!("Invalid URL: {}", e)))?;
+
+    if c.target.is_some() && c.result != ResultMode::Merge {
+        return Err(Error::ConfigFailedValidation(
+            "target requires result: merge".into(),
+        ));
+    }
+
+    Ok(ExecutionType::Processor(Box::new(HttpProcessor {
+        client: build_client(c.timeout_secs, c.tls.as_ref())?,
+        url: FieldTemplate::parse(&c.url)?,
+        method,
+        headers: c.headers,
+        auth: c.auth,
+        body: c.body.as_deref().map(FieldTemplate::parse).transpose()?,
+        result: c.result,
+        target: c.target.as_deref().map(parse_path).transpose()?,
+        retry: c.retry,
+    })))
+}
+
+pub(super) fn register_http() -> Result<(), Error> {
+    let config = "type: object
+properties:
+  url:
+    type: string
+  method:
+    type: string
+  headers:
+    type: object
+    additionalProperties:
+      type: string
+  auth:
+    type: object
+    properties:
+      type:
+        type: string
+        enum: [\"basic\", \"bearer\"]
+      username:
+        type: string
+      password:
+        type: string
+      token:
+        type: string
+    required:
+      - type
+  body:
+    type: string
+  result:
+    type: string
+    enum: [\"replace\", \"merge\"]
+  target:
+    type: string
+  timeout_secs:
+    type: integer
+  retry:
+    type: object
+    properties:
+      max_retries:
+        type: integer
+      initial_wait:
+        type: string
+      max_wait:
+        type: string
+      backoff:
+        type: string
+        enum: [\"constant\", \"linear\", \"exponential\"]
+  tls:
+    type: object
+    properties:
+      ca:
+        type: string
+      cert:
+        type: string
+      key:
+        type: string
+      skip_verify:
+        type: boolean
+required:
+  - url";
+    let conf_spec = ConfigSpec::from_schema(config)?;
+
+    register_plugin("http".into(), ItemType::Processor, conf_spec, create_http)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::modules::processors::test_util::{processor, text_message};
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn register_plugin() {
+        register_http().unwrap()
+    }
+
+    /// Serve the given `(status line, body)` responses in order, one per
+    /// connection, and collect the raw requests received.
+    async fn mock_server(
+        responses: Vec<(&'static str, &'static str)>,
+    ) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = requests.clone();
+
+        tokio::spawn(async move {
+            for (status, body) in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let length = text
+                            .lines()
+                            .find_map(|l| {
+                                l.to_lowercase()
+                                    .strip_prefix("content-length: ")
+                                    .map(|v| v.trim().parse::<usize>().unwrap())
+                            })
+                            .unwrap_or(0);
+                        if request.len() >= end + 4 + length || n == 0 {
+                            break;
+                        }
+                    }
+                }
+                received
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8_lossy(&request).to_string());
+
+                let response = format!(
+                    "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nX-Request-Id: abc\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+                socket.shutdown().await.unwrap();
+            }
+        });
+
+        (addr, requests)
+    }
+
+    #[tokio::test]
+    async fn replace_with_response() {
+        let (addr, requests) = mock_server(vec![("200 OK", r#"{"ok":true}"#)]).await;
+        let p = processor(create_http, &format!("url: {addr}/transform")).await;
+
+        let result = p.process(text_message(r#"{"id":1}"#)).await.unwrap();
+        assert_eq!(result[0].bytes, br#"{"ok":true}"#.to_vec());
+        assert_eq!(
+            result[0].metadata.get(STATUS_CODE_METADATA),
+            Some(&Value::Number(200.into()))
+        );
+        let headers = result[0].metadata.get(HEADERS_METADATA).unwrap();
+        assert_eq!(headers.get("x-request-id"), Some(&Value::from("abc")));
+
+        let request = requests.lock().unwrap()[0].clone();
+        assert!(request.starts_with("POST /transform HTTP/1.1"));
+        assert!(request.ends_with(r#"{"id":1}"#));
+    }
+
+    #[tokio::test]
+    async fn merge_with_templated_url() {
+        let (addr, requests) = mock_server(vec![("200 OK", r#"{"name":"alice"}"#)]).await;
+        let p = processor(
+            create_http,
+            &format!(
+                "url: {addr}/users/${{user.id}}\nmethod: get\nresult: merge\ntarget: user.details"
+            ),
+        )
+        .await;
+
+        let result = p
+            .process(text_message(r#"{"user":{"id":42}}"#))
+            .await
+            .unwrap();
+        let body: JsonValue = serde_json::from_slice(&result[0].bytes).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"user": {"id": 42, "details": {"name": "alice"}}})
+        );
+
+        let request = requests.lock().unwrap()[0].clone();
+        assert!(request.starts_with("GET /users/42 HTTP/1.1"));
+    }
+
+    #[tokio::test]
+    async fn body_template_and_auth() {
+        let (addr, requests) = mock_server(vec![("200 OK", r#"{"score":7}"#)]).await;
+        let p = processor(create_http, &format!(
+            "url: {addr}\nbody: '{{\"lookup\": \"${{name}}\"}}'\nresult: merge\nauth:\n  type: bearer\n  token: secret"
+        ))
+        .await;
+
+        let result = p.process(text_message(r#"{"name":"bob"}"#)).await.unwrap();
+        let body: JsonValue = serde_json::from_slice(&result[0].bytes).unwrap();
+        assert_eq!(body, serde_json::json!({"name": "bob", "score": 7}));
+
+        let request = requests.lock().unwrap()[0].clone();
+        assert!(request.contains("authorization: Bearer secret"));
+        assert!(request.ends_with(r#"{"lookup": "bob"}"#));
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors() {
+        let (addr, requests) = mock_server(vec![
+            ("503 Service Unavailable", "busy"),
+            ("200 OK", r#"{"ok":true}"#),
+        ])
+        .await;
+        let p = processor(
+            create_http,
+            &format!("url: {addr}\nretry:\n  max_retries: 2\n  initial_wait: 10ms"),
+        )
+        .await;
+
+        let result = p.process(text_message("{}")).await.unwrap();
+        assert_eq!(result[0].bytes, br#"{"ok":true}"#.to_vec());
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let (addr, requests) = mock_server(vec![("404 Not Found", "missing")]).await;
+        let p = processor(
+            create_http,
+            &format!("url: {addr}\nretry:\n  max_retries: 2\n  initial_wait: 10ms"),
+        )
+        .await;
+
+        let err = p.process(text_message("{}")).await.unwrap_err();
+        assert!(matches!(&err, Error::MessageFailed(reason) if reason.contains("404")));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn exhausted_retries_fail_the_message() {
+        let (addr, requests) = mock_server(vec![
+            ("503 Service Unavailable", "busy"),
+            ("503 Service Unavailable", "busy"),
+        ])
+        .await;
+        let p = processor(
+            create_http,
+            &format!("url: {addr}\nretry:\n  max_retries: 1\n  initial_wait: 10ms"),
+        )
+        .await;
+
+        let err = p.process(text_message("{}")).await.unwrap_err();
+        assert!(matches!(&err, Error::MessageFailed(reason) if reason.contains("503")));
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn substitutions_are_escaped() {
+        let (addr, requests) = mock_server(vec![("200 OK", r#"{"ok":true}"#)]).await;
+        let p = processor(
+            create_http,
+            &format!(
+                "url: {addr}/users/${{name}}?q=${{name}}\nbody: '{{\"lookup\": \"${{name}}\"}}'"
+            ),
+        )
+        .await;
+
+        p.process(text_message(r#"{"name":"a/b?c#d \"e\""}"#))
+            .await
+            .unwrap();
+
+        let request = requests.lock().unwrap()[0].clone();
+        assert!(request
+            .starts_with("POST /users/a%2Fb%3Fc%23d%20%22e%22?q=a%2Fb%3Fc%23d%20%22e%22 HTTP/1.1"));
+        let body = request.split("\r\n\r\n").nth(1).unwrap();
+        assert_eq!(
+            serde_json::from_str::<JsonValue>(body).unwrap(),
+            serde_json::json!({"lookup": "a/b?c#d \"e\""})
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_config() {
+        for conf in [
+            "url: 'not a url'",
+            "url: http://localhost\nmethod: TRACE",
+            "url: http://localhost\ntarget: a",
+            "url: 'http://localhost/${id'",
+        ] {
+            let value: Value = serde_yaml::from_str(conf).unwrap();
+            assert!(create_http(value).await.is_err(), "{conf}");
+        }
+    }
+}
@@ -1,3 +1,21 @@
+//! Lines processor for splitting a message into multiple messages.
+//!
+//! Splitting operates on raw bytes, so messages do not need to be valid UTF-8.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - lines:
+//!       split: delimiter       # Optional: delimiter, regex, nul or length_prefixed (default: delimiter)
+//!       delimiter: "\n"        # Optional: byte sequence used by `split: delimiter`
+//!       pattern: '\r?\n'       # Required with `split: regex`
+//!       length_bytes: 4        # Optional: size of the length prefix (1, 2, 4 or 8)
+//!       byte_order: big        # Optional: byte order of the length prefix (big or little)
+//!       skip_empty: false      # Optional: drop empty lines
+//!       strip_cr: false        # Optional: remove a trailing carriage return from each line
+//! ```
+
 use crate::config::register_plugin;
 use crate::config::ItemType;
 use crate::config::{ConfigSpec, ExecutionType};
@@ -6,22 +24,160 @@ use crate::MessageBatch;
 use crate::{Closer, Error, Processor};
 use async_trait::async_trait;
 use fiddler_macros::fiddler_registration_func;
+use regex::bytes::Regex;
+use serde::Deserialize;
 use serde_yaml::Value;
 
-#[derive(Clone)]
-pub struct Lines {}
+/// Metadata key holding the position of a line within the original message
+const LINE_INDEX_METADATA: &str = "line_index";
+
+fn default_delimiter() -> String {
+    "\n".into()
+}
+
+fn default_length_bytes() -> usize {
+    4
+}
+
+/// How the message is divided into lines
+#[derive(Deserialize, Clone, Default, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SplitMode {
+    /// Split on the configured delimiter byte sequence
+    #[default]
+    Delimiter,
+    /// Split on matches of a regular expression
+    Regex,
+    /// Split on NUL bytes
+    Nul,
+    /// Read frames preceded by their length as an unsigned integer
+    LengthPrefixed,
+}
+
+/// Byte order of length prefixes
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ByteOrder {
+    #[default]
+    Big,
+    Little,
+}
+
+#[derive(Deserialize)]
+struct LinesConfig {
+    #[serde(default)]
+    split: SplitMode,
+    #[serde(default = "default_delimiter")]
+    delimiter: String,
+    pattern: Option<String>,
+    #[serde(default = "default_length_bytes")]
+    length_bytes: usize,
+    #[serde(default)]
+    byte_order: ByteOrder,
+    #[serde(default)]
+    skip_empty: bool,
+    #[serde(default)]
+    strip_cr: bool,
+}
+
+enum Splitter {
+    Delimiter(Vec<u8>),
+    Regex(Regex),
+    LengthPrefixed(usize, ByteOrder),
+}
+
+pub struct Lines {
+    splitter: Splitter,
+    skip_empty: bool,
+    strip_cr: bool,
+}
+
+fn split_delimiter<'a>(bytes: &'a [u8], delimiter: &[u8]) -> Vec<&'a [u8]> {
+    let mut frames = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    while i + delimiter.len() <= bytes.len() {
+        if &bytes[i..i + delimiter.len()] == delimiter {
+            frames.push(&bytes[start..i]);
+            i += delimiter.len();
+            start = i;
+        } else {
+            i += 1;
+        }
+    }
+    frames.push(&bytes[start..]);
+    frames
+}
+
+fn split_length_prefixed(
+    bytes: &[u8],
+    length_bytes: usize,
+    byte_order: ByteOrder,
+) -> Result<Vec<&[u8]>, Error> {
+    let mut frames = Vec::new();
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        if rest.len() < length_bytes {
+            return Err(Error::MessageFailed(format!(
+                "truncated length prefix: {} of {length_bytes} bytes",
+                rest.len()
+            )));
+        }
+        let (prefix, body) = rest.split_at(length_bytes);
+        let mut buf = [0u8; 8];
+        let length = match byte_order {
+            ByteOrder::Big => {
+                buf[8 - length_bytes..].copy_from_slice(prefix);
+                u64::from_be_bytes(buf)
+            }
+            ByteOrder::Little => {
+                buf[..length_bytes].copy_from_slice(prefix);
+                u64::from_le_bytes(buf)
+            }
+        };
+        let length = usize::try_from(length)
+            .ok()
+            .filter(|l| *l <= body.len())
+            .ok_or_else(|| {
+                Error::MessageFailed(format!(
+                    "truncated frame: expected {length} bytes, {} available",
+                    body.len()
+                ))
+            })?;
+        let (frame, remaining) = body.split_at(length);
+        frames.push(frame);
+        rest = remaining;
+    }
+    Ok(frames)
+}
 
 #[async_trait]
 impl Processor for Lines {
     async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
-        let content = String::from_utf8(message.bytes)
-            .map_err(|e| Error::ProcessingError(format!("{}", e)))?;
-        let output: Vec<Message> = content
-            .split('\n')
-            .map(|msg| Message {
-                bytes: msg.as_bytes().into(),
-                metadata: message.metadata.clone(),
-                ..Default::default()
+        let frames = match &self.splitter {
+            Splitter::Delimiter(d) => split_delimiter(&message.bytes, d),
+            Splitter::Regex(r) => r.split(&message.bytes).collect(),
+            Splitter::LengthPrefixed(length_bytes, byte_order) => {
+                split_length_prefixed(&message.bytes, *length_bytes, *byte_order)?
+            }
+        };
+
+        let output: Vec<Message> = frames
+            .into_iter()
+            .enumerate()
+            .map(|(i, frame)| match frame.strip_suffix(b"\r") {
+                Some(stripped) if self.strip_cr => (i, stripped),
+                _ => (i, frame),
+            })
+            .filter(|(_, frame)| !(self.skip_empty && frame.is_empty()))
+            .map(|(i, frame)| {
+                let mut metadata = message.metadata.clone();
+                metadata.insert(LINE_INDEX_METADATA.into(), Value::Number(i.into()));
+                Message {
+                    bytes: frame.to_vec(),
+                    metadata,
+                    ..Default::default()
+                }
             })
             .collect();
         Ok(output)
@@ -31,12 +187,64 @@ impl Processor for Lines {
 impl Closer for Lines {}
 
 #[fiddler_registration_func]
-fn create_lines(_conf: Value) -> Result<ExecutionType, Error> {
-    Ok(ExecutionType::Processor(Box::new(Lines {})))
+fn create_lines(conf: Value) -> Result<ExecutionType, Error> {
+    let c: LinesConfig = serde_yaml::from_value(conf.clone())?;
+
+    let splitter = match c.split {
+        SplitMode::Delimiter => {
+            if c.delimiter.is_empty() {
+                return Err(Error::ConfigFailedValidation(
+                    "delimiter must not be empty".into(),
+                ));
+            }
+            Splitter::Delimiter(c.delimiter.into_bytes())
+        }
+        SplitMode::Nul => Splitter::Delimiter(vec![0]),
+        SplitMode::Regex => {
+            let pattern = c.pattern.ok_or_else(|| {
+                Error::ConfigFailedValidation("pattern is required with split: regex".into())
+            })?;
+            let regex = Regex::new(&pattern)
+                .map_err(|e| Error::ConfigFailedValidation(format!("invalid pattern: {e}")))?;
+            Splitter::Regex(regex)
+        }
+        SplitMode::LengthPrefixed => {
+            if ![1, 2, 4, 8].contains(&c.length_bytes) {
+                return Err(Error::ConfigFailedValidation(
+                    "length_bytes must be one of 1, 2, 4 or 8".into(),
+                ));
+            }
+            Splitter::LengthPrefixed(c.length_bytes, c.byte_order)
+        }
+    };
+
+    Ok(ExecutionType::Processor(Box::new(Lines {
+        splitter,
+        skip_empty: c.skip_empty,
+        strip_cr: c.strip_cr,
+    })))
 }
 
 pub(super) fn register_lines() -> Result<(), Error> {
-    let config = "type: object";
+    let config = "type: object
+properties:
+  split:
+    type: string
+    enum: [delimiter, regex, nul, length_prefixed]
+  delimiter:
+    type: string
+  pattern:
+    type: string
+  length_bytes:
+    type: integer
+    enum: [1, 2, 4, 8]
+  byte_order:
+    type: string
+    enum: [big, little]
+  skip_empty:
+    type: boolean
+  strip_cr:
+    type: boolean";
     let conf_spec = ConfigSpec::from_schema(config)?;
 
     register_plugin("lines".into(), ItemType::Processor, conf_spec, create_lines)
@@ -45,12 +253,38 @@ pub(super) fn register_lines() -> Result<(), Error> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::collections::HashMap;
 
     #[test]
     fn register_plugin() {
         register_lines().unwrap()
     }
 
+    async fn split(conf: &str, input: &[u8]) -> Vec<Message> {
+        let conf: Value = serde_yaml::from_str(conf).unwrap();
+        match create_lines(conf).await.unwrap() {
+            ExecutionType::Processor(p) => p
+                .process(Message {
+                    bytes: input.to_vec(),
+                    ..Default::default()
+                })
+                .await
+                .unwrap(),
+            _ => panic!("wrong execution type"),
+        }
+    }
+
+    fn bodies(messages: &[Message]) -> Vec<Vec<u8>> {
+        messages.iter().map(|m| m.bytes.clone()).collect()
+    }
+
+    fn indexes(messages: &[Message]) -> Vec<Value> {
+        messages
+            .iter()
+            .map(|m| m.metadata.get(LINE_INDEX_METADATA).cloned().unwrap())
+            .collect()
+    }
+
     #[tokio::test]
     async fn split_lines() {
         let input_str = "Hello
@@ -59,20 +293,136 @@ world";
             bytes: input_str.as_bytes().into(),
             ..Default::default()
         };
-        let processor = Lines {};
+        let processor = Lines {
+            splitter: Splitter::Delimiter(b"\n".to_vec()),
+            skip_empty: false,
+            strip_cr: false,
+        };
         let output = processor.process(msg).await.unwrap();
         assert_eq!(
             output,
             vec![
                 Message {
                     bytes: "Hello".as_bytes().into(),
+                    metadata: HashMap::from([(
+                        LINE_INDEX_METADATA.into(),
+                        Value::Number(0.into())
+                    )]),
                     ..Default::default()
                 },
                 Message {
                     bytes: "world".as_bytes().into(),
+                    metadata: HashMap::from([(
+                        LINE_INDEX_METADATA.into(),
+                        Value::Number(1.into())
+                    )]),
                     ..Default::default()
                 }
             ]
         )
     }
+
+    #[tokio::test]
+    async fn default_config_keeps_empty_lines() {
+        let output = split("{}", b"a\n\nb\n").await;
+        assert_eq!(
+            bodies(&output),
+            vec![b"a".to_vec(), vec![], b"b".to_vec(), vec![]]
+        );
+    }
+
+    #[tokio::test]
+    async fn skip_empty_keeps_original_index() {
+        let output = split("skip_empty: true", b"a\n\nb\n").await;
+        assert_eq!(bodies(&output), vec![b"a".to_vec(), b"b".to_vec()]);
+        assert_eq!(
+            indexes(&output),
+            vec![Value::Number(0.into()), Value::Number(2.into())]
+        );
+    }
+
+    #[tokio::test]
+    async fn strip_cr() {
+        let output = split("strip_cr: true\nskip_empty: true", b"a\r\nb\r\n\r\n").await;
+        assert_eq!(bodies(&output), vec![b"a".to_vec(), b"b".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn multi_byte_delimiter() {
+        let output = split("delimiter: '||'", b"a||b|c||").await;
+        assert_eq!(
+            bodies(&output),
+            vec![b"a".to_vec(), b"b|c".to_vec(), vec![]]
+        );
+    }
+
+    #[tokio::test]
+    async fn binary_safe() {
+        let output = split("{}", &[0xff, 0xfe, b'\n', 0x80]).await;
+        assert_eq!(bodies(&output), vec![vec![0xff, 0xfe], vec![0x80]]);
+    }
+
+    #[tokio::test]
+    async fn nul_delimiter() {
+        let output = split("split: nul", b"a\0b").await;
+        assert_eq!(bodies(&output), vec![b"a".to_vec(), b"b".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn regex_delimiter() {
+        let output = split("split: regex\npattern: '[,;]\\s*'", b"a, b;c").await;
+        assert_eq!(
+            bodies(&output),
+            vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]
+        );
+    }
+
+    #[tokio::test]
+    async fn length_prefixed_big_endian() {
+        let output = split(
+            "split: length_prefixed\nlength_bytes: 2",
+            &[0, 2, b'h', b'i', 0, 0, 0, 1, b'!'],
+        )
+        .await;
+        assert_eq!(bodies(&output), vec![b"hi".to_vec(), vec![], b"!".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn length_prefixed_little_endian() {
+        let output = split(
+            "split: length_prefixed\nbyte_order: little",
+            &[3, 0, 0, 0, b'a', b'b', b'c'],
+        )
+        .await;
+        assert_eq!(bodies(&output), vec![b"abc".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn length_prefixed_truncated() {
+        let conf: Value = serde_yaml::from_str("split: length_prefixed").unwrap();
+        let ExecutionType::Processor(p) = create_lines(conf).await.unwrap() else {
+            panic!("wrong execution type")
+        };
+        for bytes in [vec![0, 0, 0, 5, b'a'], vec![0, 0, 0, 1, b'a', 0, 0]] {
+            let result = p
+                .process(Message {
+                    bytes,
+                    ..Default::default()
+                })
+                .await;
+            assert!(matches!(result, Err(Error::MessageFailed(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_config() {
+        for conf in [
+            "split: regex",
+            "delimiter: ''",
+            "split: length_prefixed\nlength_bytes: 3",
+        ] {
+            let conf: Value = serde_yaml::from_str(conf).unwrap();
+            assert!(create_lines(conf).await.is_err());
+        }
+    }
 }
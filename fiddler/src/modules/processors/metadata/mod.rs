@@ -0,0 +1,401 @@
+//! Metadata processor for setting, copying and removing metadata keys.
+//!
+//! Operations are applied in the order `set`, `copy_to_body`, `delete` and
+//! `keep`.  Values are set from a literal, a JMESPath expression evaluated
+//! against the JSON body, or an environment variable read when the processor
+//! is created.  Expressions selecting nothing leave the key untouched, as do
+//! missing keys when copying to the body.  Keys in `delete` and `keep` may use
+//! `*` as a wildcard, such as `syslog_*`.  A body that is not JSON fails the
+//! message when a `source` expression or `copy_to_body` needs it.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - metadata:
+//!       set:                         # Optional: keys to set
+//!         - key: "tenant"
+//!           value: "acme"            # Literal value
+//!         - key: "user_id"
+//!           source: "user.id"        # JMESPath expression against the body
+//!         - key: "region"
+//!           env: "AWS_REGION"        # Environment variable
+//!       copy_to_body:                # Optional: keys copied into the JSON body
+//!         - key: "syslog_hostname"
+//!           target: "host"           # Field within the body (default: key)
+//!       delete: ["syslog_*"]         # Optional: keys to remove
+//!       keep: ["tenant", "user_id"]  # Optional: remove every other key
+//! ```
+
+use super::path::{parse_path, set_path, variable, Expression};
+use crate::config::register_plugin;
+use crate::config::ItemType;
+use crate::config::{ConfigSpec, ExecutionType};
+use crate::Message;
+use crate::MessageBatch;
+use crate::{Closer, Error, Processor};
+use async_trait::async_trait;
+use fiddler_macros::fiddler_registration_func;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use serde_yaml::Value;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SetConfig {
+    key: String,
+    value: Option<Value>,
+    source: Option<String>,
+    env: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CopyConfig {
+    key: String,
+    target: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MetadataConfig {
+    #[serde(default)]
+    set: Vec<SetConfig>,
+    #[serde(default)]
+    copy_to_body: Vec<CopyConfig>,
+    #[serde(default)]
+    delete: Vec<String>,
+    keep: Option<Vec<String>>,
+}
+
+/// Where the value of a set operation comes from
+enum SetValue {
+    /// Literal value, including values read from the environment
+    Literal(Value),
+    /// JMESPath expression evaluated against the body
+    Source(Expression),
+}
+
+pub struct Metadata {
+    set: Vec<(String, SetValue)>,
+    copy_to_body: Vec<(String, Vec<String>)>,
+    delete: Vec<String>,
+    keep: Option<Vec<String>>,
+}
+
+/// Match a key against a pattern where `*` matches any run of characters
+fn key_matches(pattern: &str, key: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = key.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[async_trait]
+impl Processor for Metadata {
+    async fn process(&self, mut message: Message) -> Result<MessageBatch, Error> {
+        let needs_data = self
+            .set
+            .iter()
+            .any(|(_, v)| matches!(v, SetValue::Source(_)));
+        let data = if needs_data {
+            Some(variable(&message.bytes)?)
+        } else {
+            None
+        };
+
+        for (key, value) in &self.set {
+            let value = match (value, &data) {
+                (SetValue::Literal(v), _) => v.clone(),
+                (SetValue::Source(expression), Some(data)) => match expression.search(data)? {
+                    JsonValue::Null => continue,
+                    result => serde_yaml::to_value(result)?,
+                },
+                (SetValue::Source(_), None) => continue,
+            };
+            message.metadata.insert(key.clone(), value);
+        }
+
+        if !self.copy_to_body.is_empty() {
+            let mut doc: JsonValue = serde_json::from_slice(&message.bytes)
+                .map_err(|e| Error::MessageFailed(format!("{e}")))?;
+            for (key, target) in &self.copy_to_body {
+                if let Some(value) = message.metadata.get(key) {
+                    let value = serde_json::to_value(value)
+                        .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+                    set_path(&mut doc, target, value)?;
+                }
+            }
+            message.bytes =
+                serde_json::to_vec(&doc).map_err(|e| Error::ProcessingError(format!("{e}")))?;
+        }
+
+        if !self.delete.is_empty() {
+            message
+                .metadata
+                .retain(|key, _| !self.delete.iter().any(|p| key_matches(p, key)));
+        }
+
+        if let Some(keep) = &self.keep {
+            message
+                .metadata
+                .retain(|key, _| keep.iter().any(|p| key_matches(p, key)));
+        }
+
+        Ok(vec![message])
+    }
+}
+
+impl Closer for Metadata {}
+
+/// Build the processor, reading `env` values through the given lookup
+fn build_metadata(
+    c: MetadataConfig,
+    env_var: impl Fn(&str) -> Result<String, std::env::VarError>,
+) -> Result<Metadata, Error> {
+    let mut set = Vec::new();
+    for s in c.set {
+        let value = match (s.value, s.source, s.env) {
+            (Some(value), None, None) => SetValue::Literal(value),
+            (None, Some(source), None) => SetValue::Source(Expression::compile(&source)?),
+            (None, None, Some(env)) => {
+                let value = env_var(&env).map_err(|e| {
+                    Error::ConfigFailedValidation(format!("environment variable {env}: {e}"))
+                })?;
+                SetValue::Literal(Value::String(value))
+            }
+            _ => {
+                return Err(Error::ConfigFailedValidation(format!(
+                    "exactly one of value, source or env is required for key '{}'",
+                    s.key
+                )))
+            }
+        };
+        set.push((s.key, value));
+    }
+
+    let copy_to_body = c
+        .copy_to_body
+        .into_iter()
+        .map(|cp| {
+            let target = parse_path(cp.target.as_deref().unwrap_or(&cp.key))?;
+            Ok((cp.key, target))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    Ok(Metadata {
+        set,
+        copy_to_body,
+        delete: c.delete,
+        keep: c.keep,
+    })
+}
+
+#[fiddler_registration_func]
+fn create_metadata(conf: Value) -> Result<ExecutionType, Error> {
+    let c: MetadataConfig = serde_yaml::from_value(conf)?;
+    let metadata = build_metadata(c, |name| std::env::var(name))?;
+    Ok(ExecutionType::Processor(Box::new(metadata)))
+}
+
+pub(super) fn register_metadata() -> Result<(), Error> {
+    let config = "type: object
+properties:
+  set:
+    type: array
+    items:
+      type: object
+      properties:
+        key:
+          type: string
+        source:
+          type: string
+        env:
+          type: string
+      required:
+        - key
+  copy_to_body:
+    type: array
+    items:
+      type: object
+      properties:
+        key:
+          type: string
+        target:
+          type: string
+      required:
+        - key
+  delete:
+    type: array
+    items:
+      type: string
+  keep:
+    type: array
+    items:
+      type: string
+additionalProperties: false";
+    let conf_spec = ConfigSpec::from_schema(config)?;
+
+    register_plugin(
+        "metadata".into(),
+        ItemType::Processor,
+        conf_spec,
+        create_metadata,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::modules::processors::test_util::{self, try_processor};
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[test]
+    fn register_plugin() {
+        register_metadata().unwrap()
+    }
+
+    fn message(body: JsonValue) -> Message {
+        Message {
+            metadata: HashMap::from([
+                ("source".into(), Value::String("syslog".into())),
+                ("syslog_hostname".into(), Value::String("web1".into())),
+                ("syslog_facility".into(), Value::String("auth".into())),
+            ]),
+            ..test_util::message(body)
+        }
+    }
+
+    #[test]
+    fn wildcard() {
+        for (pattern, key, expected) in [
+            ("syslog_*", "syslog_host", true),
+            ("syslog_*", "syslog_", true),
+            ("syslog_*", "source", false),
+            ("*_id", "user_id", true),
+            ("a*b*c", "a-b-c", true),
+            ("a*b*c", "a-c", false),
+            ("*", "anything", true),
+            ("exact", "exact", true),
+            ("exact", "exactly", false),
+        ] {
+            assert_eq!(key_matches(pattern, key), expected, "{pattern} {key}");
+        }
+    }
+
+    #[tokio::test]
+    async fn set_values() {
+        let c = serde_yaml::from_str(
+            "set:
+  - key: tenant
+    value: acme
+  - key: user
+    source: user
+  - key: missing
+    source: nothing.here
+  - key: region
+    env: REGION",
+        )
+        .unwrap();
+        let p = build_metadata(c, |name| match name {
+            "REGION" => Ok("eu-west-1".into()),
+            _ => Err(std::env::VarError::NotPresent),
+        })
+        .unwrap();
+        let output = p
+            .process(message(json!({"user": {"id": 7}})))
+            .await
+            .unwrap();
+        let metadata = &output[0].metadata;
+        assert_eq!(metadata["tenant"], Value::String("acme".into()));
+        assert_eq!(
+            metadata["user"],
+            serde_yaml::from_str::<Value>("id: 7").unwrap()
+        );
+        assert_eq!(metadata["region"], Value::String("eu-west-1".into()));
+        assert!(!metadata.contains_key("missing"));
+    }
+
+    #[tokio::test]
+    async fn copy_delete_and_keep() {
+        let p = try_processor(
+            create_metadata,
+            "copy_to_body:
+  - key: syslog_hostname
+    target: host.name
+  - key: source
+  - key: absent
+delete: ['syslog_*']",
+        )
+        .await
+        .unwrap();
+        let output = p.process(message(json!({"msg": "hi"}))).await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<JsonValue>(&output[0].bytes).unwrap(),
+            json!({"msg": "hi", "host": {"name": "web1"}, "source": "syslog"})
+        );
+        assert_eq!(output[0].metadata.len(), 1);
+        assert!(output[0].metadata.contains_key("source"));
+
+        let p = try_processor(create_metadata, "keep: ['syslog_h*']")
+            .await
+            .unwrap();
+        let input = Message {
+            bytes: b"not json".to_vec(),
+            ..message(json!({}))
+        };
+        let output = p.process(input).await.unwrap();
+        assert_eq!(output[0].bytes, b"not json".to_vec());
+        assert_eq!(
+            output[0].metadata.keys().collect::<Vec<_>>(),
+            vec!["syslog_hostname"]
+        );
+    }
+
+    #[tokio::test]
+    async fn body_not_json() {
+        for conf in [
+            "set: [{key: user, source: user}]",
+            "copy_to_body: [{key: source}]",
+        ] {
+            let p = try_processor(create_metadata, conf).await.unwrap();
+            let input = Message {
+                bytes: b"<13>Oct 18 web1 sshd: accepted".to_vec(),
+                ..message(json!({}))
+            };
+            let err = p.process(input).await.unwrap_err();
+            assert!(matches!(err, Error::MessageFailed(_)), "{conf}: {err}");
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_config() {
+        for conf in [
+            "set: [{key: a}]",
+            "set: [{key: a, value: 1, source: b}]",
+            "set: [{key: a, source: 'b[['}]",
+            "set: [{key: a, env: FIDDLER_METADATA_TEST_UNSET}]",
+            "copy_to_body: [{key: a, target: 'b..c'}]",
+            "remove: [a]",
+        ] {
+            assert!(
+                try_processor(create_metadata, conf).await.is_err(),
+                "{conf}"
+            );
+        }
+    }
+}
@@ -1,20 +1,52 @@
 use crate::Error;
+pub mod archive;
+pub mod branch;
 pub mod compression;
+pub mod csv;
 pub mod decode;
+pub mod dedupe;
+pub mod enrich;
 pub mod exception;
 pub mod fiddlerscript;
 pub mod filter;
+pub mod for_each;
+pub mod geoip;
+pub mod grok;
+#[cfg(feature = "http_client")]
+pub mod http;
 pub mod lines;
+pub mod metadata;
+pub mod multiline;
 pub mod noop;
+pub(crate) mod path;
 #[cfg(feature = "python")]
 pub mod python;
+pub mod redact;
+#[cfg(feature = "redis")]
+pub mod redis_lookup;
+pub mod sample;
 pub mod switch;
+pub mod template;
+#[cfg(test)]
+pub(crate) mod test_util;
+pub mod timestamp;
 pub mod transform;
+pub mod unnest;
+pub mod validate;
+pub mod window;
 
-use crate::config::{ExecutionType, ParsedRegisteredItem};
-use crate::runtime::{InternalMessage, InternalMessageState, MessageStatus};
+use crate::config::{
+    parse_configuration_item, ExecutionType, Item, ItemType, ParsedRegisteredItem,
+};
+use crate::runtime::{InternalMessage, InternalMessageState, MessageHandle, MessageStatus};
+use crate::{MessageBatch, Processor};
 use flume::{Receiver, Sender};
+use tokio::time::{interval, Duration, MissedTickBehavior};
 use tracing::{debug, error, trace};
+use uuid::Uuid;
+
+/// Interval at which processors are asked to flush held back messages
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
 
 pub(crate) fn register_plugins() -> Result<(), Error> {
     lines::register_lines()?;
@@ -22,20 +54,101 @@ pub(crate) fn register_plugins() -> Result<(), Error> {
     #[cfg(feature = "python")]
     python::register_python()?;
     switch::register_switch()?;
+    archive::register_archive()?;
+    branch::register_branch()?;
     compression::register_compress()?;
+    csv::register_csv()?;
     decode::register_decode()?;
+    dedupe::register_dedupe()?;
+    enrich::register_enrich()?;
     exception::register_try()?;
     fiddlerscript::register_fiddlerscript()?;
     filter::register_filter()?;
+    for_each::register_for_each()?;
+    geoip::register_geoip()?;
+    grok::register_grok()?;
+    #[cfg(feature = "http_client")]
+    http::register_http()?;
+    metadata::register_metadata()?;
+    multiline::register_multiline()?;
+    redact::register_redact()?;
+    #[cfg(feature = "redis")]
+    redis_lookup::register_redis_lookup()?;
+    sample::register_sample()?;
+    template::register_template()?;
+    timestamp::register_timestamp()?;
     transform::register_transform()?;
+    unnest::register_unnest()?;
+    validate::register_validate()?;
+    window::register_window()?;
     Ok(())
 }
 
+/// Build the processors nested within another processor, in configuration order
+pub(crate) async fn build_processors(
+    items: Vec<Item>,
+) -> Result<Vec<Box<dyn Processor + Send + Sync>>, Error> {
+    let mut processors = Vec::with_capacity(items.len());
+    for item in &items {
+        processors.push(build_processor(item).await?);
+    }
+    Ok(processors)
+}
+
+/// Build a single nested processor
+pub(crate) async fn build_processor(
+    item: &Item,
+) -> Result<Box<dyn Processor + Send + Sync>, Error> {
+    let ri = parse_configuration_item(ItemType::Processor, &item.extra).await?;
+    match (ri.creator)(ri.config).await? {
+        ExecutionType::Processor(p) => Ok(p),
+        _ => Err(Error::ConfigFailedValidation(
+            "invalid execution type provided".into(),
+        )),
+    }
+}
+
+/// Run the messages through each processor in turn, handing the output of one
+/// processor to the next.  Used by processors that nest other processors.
+pub(crate) async fn run_chain(
+    processors: &[Box<dyn Processor + Send + Sync>],
+    mut messages: MessageBatch,
+) -> Result<MessageBatch, Error> {
+    for p in processors {
+        let mut new_messages = Vec::new();
+        for m in messages.drain(..) {
+            new_messages.extend(p.process(m).await?);
+        }
+        messages = new_messages;
+    }
+    Ok(messages)
+}
+
+/// Flush each processor of a chain in turn.  Messages released by a processor
+/// are run through the processors after it, each of which is flushed only once
+/// they have been processed.
+pub(crate) async fn flush_chain(
+    processors: &[Box<dyn Processor + Send + Sync>],
+    shutdown: bool,
+) -> Result<MessageBatch, Error> {
+    let mut messages = Vec::new();
+    for p in processors {
+        let mut new_messages = Vec::new();
+        for m in messages.drain(..) {
+            new_messages.extend(p.process(m).await?);
+        }
+        new_messages.extend(p.flush(shutdown).await?);
+        messages = new_messages;
+    }
+    Ok(messages)
+}
+
 pub(crate) async fn run_processor(
     processor: ParsedRegisteredItem,
     output: Sender<InternalMessage>,
     input: Receiver<InternalMessage>,
     state_tx: Sender<InternalMessageState>,
+    handle_tx: Sender<MessageHandle>,
 ) -> Result<(), Error> {
     trace!("Started processor");
     // let proc = (processor.creator)(&processor.config)?;
@@ -47,8 +160,23 @@ pub(crate) async fn run_processor(
         }
     };
 
+    let mut flush_timer = interval(FLUSH_INTERVAL);
+    flush_timer.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    // Skip the first immediate tick
+    flush_timer.tick().await;
+
     loop {
-        match input.recv_async().await {
+        let received = tokio::select! {
+            biased;
+            msg = input.recv_async() => msg,
+            _ = flush_timer.tick() => {
+                let flushed = p.flush(false).await?;
+                send_flushed(flushed, &output, &handle_tx).await?;
+                continue;
+            }
+        };
+
+        match received {
             Ok(msg) => {
                 trace!("received processing message");
                 let stream_id = msg.message.stream_id.clone();
@@ -88,26 +216,41 @@ pub(crate) async fn run_processor(
                             }
                         }
 
-                        for message in m.into_iter() {
-                            let new_msg = InternalMessage {
-                                message_id: message_id.clone(),
-                                status: status.clone(),
-                                message: crate::Message {
-                                    stream_id: stream_id.clone(),
-                                    ..message
-                                },
-                            };
+                        forward(m, &message_id, &stream_id, &status, &output).await?;
+                    }
+                    Err(e) => match e {
+                        Error::MessageFailedWithOutput(reason, m) => {
+                            debug!(error = reason, "message failed in processor with output");
 
-                            trace!("message processed");
-                            output
-                                .send_async(new_msg)
+                            // The failure counts as an instance of the message alongside the
+                            // output, so the input is notified once both have completed
+                            for _ in 0..m.len() {
+                                state_tx
+                                    .send_async(InternalMessageState {
+                                        message_id: message_id.clone(),
+                                        status: MessageStatus::New,
+                                        stream_id: stream_id.clone(),
+                                        ..Default::default()
+                                    })
+                                    .await
+                                    .map_err(|e| Error::UnableToSendToChannel(format!("{e}")))?;
+                            }
+                            state_tx
+                                .send_async(InternalMessageState {
+                                    message_id: message_id.clone(),
+                                    status: MessageStatus::ProcessError(format!(
+                                        "Message failed: {reason}"
+                                    )),
+                                    stream_id: stream_id.clone(),
+                                    ..Default::default()
+                                })
                                 .await
                                 .map_err(|e| Error::UnableToSendToChannel(format!("{e}")))?;
+
+                            forward(m, &message_id, &stream_id, &status, &output).await?;
                         }
-                    }
-                    Err(e) => match e {
-                        Error::ConditionalCheckfailed => {
-                            debug!("conditional check failed for processor");
+                        Error::ConditionalCheckfailed | Error::MessageFailed(_) => {
+                            debug!(error = format!("{e}"), "message failed in processor");
 
                             state_tx
                                 .send_async(InternalMessageState {
@@ -127,7 +270,9 @@ pub(crate) async fn run_processor(
                 }
             }
             Err(_) => {
-                // Channel disconnected - clean shutdown
+                // Channel disconnected - flush held back messages and clean shutdown
+                let flushed = p.flush(true).await?;
+                send_flushed(flushed, &output, &handle_tx).await?;
                 p.close().await?;
                 debug!("processor closed");
                 return Ok(());
@@ -135,3 +280,69 @@ pub(crate) async fn run_processor(
         }
     }
 }
+
+/// Forward processed messages to the next step under the id of the message they came from
+async fn forward(
+    messages: MessageBatch,
+    message_id: &str,
+    stream_id: &Option<String>,
+    status: &MessageStatus,
+    output: &Sender<InternalMessage>,
+) -> Result<(), Error> {
+    for message in messages {
+        let new_msg = InternalMessage {
+            message_id: message_id.to_string(),
+            status: status.clone(),
+            message: crate::Message {
+                stream_id: stream_id.clone(),
+                ..message
+            },
+        };
+
+        trace!("message processed");
+        output
+            .send_async(new_msg)
+            .await
+            .map_err(|e| Error::UnableToSendToChannel(format!("{e}")))?;
+    }
+    Ok(())
+}
+
+/// Register messages emitted by [crate::Processor::flush] with the state handler and forward
+/// them down the pipeline.  Flushed messages are not tied to an input message, so each one is
+/// tracked under a new message id.
+async fn send_flushed(
+    messages: MessageBatch,
+    output: &Sender<InternalMessage>,
+    handle_tx: &Sender<MessageHandle>,
+) -> Result<(), Error> {
+    for message in messages {
+        let message_id: String = Uuid::new_v4().into();
+        trace!(message_id = message_id, "forwarding flushed message");
+
+        handle_tx
+            .send_async(MessageHandle {
+                message_id: message_id.clone(),
+                closure: None,
+                stream_id: None,
+                is_stream: false,
+                stream_complete: false,
+                input_bytes: message.bytes.len() as u64,
+            })
+            .await
+            .map_err(|e| Error::UnableToSendToChannel(format!("{e}")))?;
+
+        output
+            .send_async(InternalMessage {
+                message: crate::Message {
+                    stream_id: None,
+                    ..message
+                },
+                message_id,
+                status: MessageStatus::New,
+            })
+            .await
+            .map_err(|e| Error::UnableToSendToChannel(format!("{e}")))?;
+    }
+    Ok(())
+}
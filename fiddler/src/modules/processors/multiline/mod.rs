@@ -0,0 +1,456 @@
+//! Multiline processor for joining related lines into a single event.
+//!
+//! Each received message is treated as one line.  Lines are grouped per source,
+//! identified by the values of the configured metadata `keys`, so interleaved
+//! streams (for example several syslog hosts) are never mixed.  A group is
+//! emitted once the next event starts, a size limit is reached or no new line
+//! arrived for `timeout`.  Pending groups are emitted on shutdown.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - multiline:
+//!       pattern: '^\s+(at |\.\.\.)|^Caused by:'  # Required: regex tested against each line
+//!       match: continuation                     # Optional: start or continuation (default: continuation)
+//!       negate: false                           # Optional: invert the pattern match
+//!       keys: [syslog_hostname]                 # Optional: metadata keys identifying the source
+//!       separator: "\n"                         # Optional: inserted between joined lines
+//!       max_lines: 500                          # Optional: maximum lines per event
+//!       max_bytes: 1048576                      # Optional: maximum bytes per event
+//!       timeout: 5s                             # Optional: emit an idle event after this duration
+//! ```
+
+use crate::config::register_plugin;
+use crate::config::ItemType;
+use crate::config::{ConfigSpec, ExecutionType};
+use crate::Message;
+use crate::MessageBatch;
+use crate::{Closer, Error, Processor};
+use async_trait::async_trait;
+use fiddler_macros::fiddler_registration_func;
+use regex::bytes::Regex;
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Metadata key holding the number of lines joined into an event
+const LINE_COUNT_METADATA: &str = "multiline_lines";
+
+fn default_separator() -> String {
+    "\n".into()
+}
+
+fn default_max_lines() -> usize {
+    500
+}
+
+fn default_max_bytes() -> usize {
+    1_048_576
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_secs(5)
+}
+
+/// How a line matching the pattern relates to the current event
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMode {
+    /// A matching line begins a new event; other lines are appended
+    Start,
+    /// A matching line is appended to the current event; other lines begin a new event
+    #[default]
+    Continuation,
+}
+
+#[derive(Deserialize)]
+struct MultilineConfig {
+    pattern: String,
+    #[serde(default, rename = "match")]
+    match_mode: MatchMode,
+    #[serde(default)]
+    negate: bool,
+    #[serde(default)]
+    keys: Vec<String>,
+    #[serde(default = "default_separator")]
+    separator: String,
+    #[serde(default = "default_max_lines")]
+    max_lines: usize,
+    #[serde(default = "default_max_bytes")]
+    max_bytes: usize,
+    #[serde(
+        default = "default_timeout",
+        deserialize_with = "crate::deserialize_duration"
+    )]
+    timeout: Duration,
+}
+
+/// Lines collected for a single source
+struct Pending {
+    bytes: Vec<u8>,
+    metadata: HashMap<String, Value>,
+    lines: usize,
+    started: Instant,
+    updated: Instant,
+}
+
+impl Pending {
+    fn new(message: Message) -> Self {
+        let now = Instant::now();
+        Pending {
+            bytes: message.bytes,
+            metadata: message.metadata,
+            lines: 1,
+            started: now,
+            updated: now,
+        }
+    }
+
+    fn into_message(self) -> Message {
+        let mut metadata = self.metadata;
+        metadata.insert(LINE_COUNT_METADATA.into(), Value::Number(self.lines.into()));
+        Message {
+            bytes: self.bytes,
+            metadata,
+            ..Default::default()
+        }
+    }
+}
+
+pub struct Multiline {
+    pattern: Regex,
+    match_mode: MatchMode,
+    negate: bool,
+    keys: Vec<String>,
+    separator: Vec<u8>,
+    max_lines: usize,
+    max_bytes: usize,
+    timeout: Duration,
+    pending: Mutex<HashMap<Vec<Value>, Pending>>,
+}
+
+impl Multiline {
+    fn source_key(&self, message: &Message) -> Vec<Value> {
+        self.keys
+            .iter()
+            .map(|k| message.metadata.get(k).cloned().unwrap_or(Value::Null))
+            .collect()
+    }
+
+    /// Whether the line belongs to the event currently collected for its source
+    fn continues(&self, line: &[u8]) -> bool {
+        let matched = self.pattern.is_match(line) != self.negate;
+        match self.match_mode {
+            MatchMode::Start => !matched,
+            MatchMode::Continuation => matched,
+        }
+    }
+
+    fn fits(&self, pending: &Pending, line: &[u8]) -> bool {
+        pending.lines < self.max_lines
+            && pending.bytes.len() + self.separator.len() + line.len() <= self.max_bytes
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, HashMap<Vec<Value>, Pending>>, Error> {
+        self.pending
+            .lock()
+            .map_err(|e| Error::ProcessingError(format!("{e}")))
+    }
+}
+
+#[async_trait]
+impl Processor for Multiline {
+    async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
+        let key = self.source_key(&message);
+        let continues = self.continues(&message.bytes);
+        let mut pending = self.lock()?;
+
+        if let Some(current) = pending.get_mut(&key) {
+            if continues && self.fits(current, &message.bytes) {
+                current.bytes.extend_from_slice(&self.separator);
+                current.bytes.extend_from_slice(&message.bytes);
+                current.lines += 1;
+                current.updated = Instant::now();
+                return Ok(Vec::new());
+            }
+        }
+
+        match pending.insert(key, Pending::new(message)) {
+            Some(completed) => Ok(vec![completed.into_message()]),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn flush(&self, shutdown: bool) -> Result<MessageBatch, Error> {
+        let mut pending = self.lock()?;
+
+        let expired: Vec<Vec<Value>> = pending
+            .iter()
+            .filter(|(_, p)| shutdown || p.updated.elapsed() >= self.timeout)
+            .map(|(k, _)| k.clone())
+            .collect();
+
+        let mut completed: Vec<Pending> =
+            expired.iter().filter_map(|k| pending.remove(k)).collect();
+        completed.sort_by_key(|p| p.started);
+
+        Ok(completed.into_iter().map(Pending::into_message).collect())
+    }
+}
+
+impl Closer for Multiline {}
+
+#[fiddler_registration_func]
+fn create_multiline(conf: Value) -> Result<ExecutionType, Error> {
+    let c: MultilineConfig = serde_yaml::from_value(conf.clone())?;
+
+    let pattern = Regex::new(&c.pattern)
+        .map_err(|e| Error::ConfigFailedValidation(format!("invalid pattern: {e}")))?;
+
+    if c.max_lines == 0 {
+        return Err(Error::ConfigFailedValidation(
+            "max_lines must be greater than 0".into(),
+        ));
+    }
+
+    if c.max_bytes == 0 {
+        return Err(Error::ConfigFailedValidation(
+            "max_bytes must be greater than 0".into(),
+        ));
+    }
+
+    Ok(ExecutionType::Processor(Box::new(Multiline {
+        pattern,
+        match_mode: c.match_mode,
+        negate: c.negate,
+        keys: c.keys,
+        separator: c.separator.into_bytes(),
+        max_lines: c.max_lines,
+        max_bytes: c.max_bytes,
+        timeout: c.timeout,
+        pending: Mutex::new(HashMap::new()),
+    })))
+}
+
+pub(super) fn register_multiline() -> Result<(), Error> {
+    let config = "type: object
+properties:
+  pattern:
+    type: string
+  match:
+    type: string
+    enum: [start, continuation]
+  negate:
+    type: boolean
+  keys:
+    type: array
+    items:
+      type: string
+  separator:
+    type: string
+  max_lines:
+    type: integer
+    minimum: 1
+  max_bytes:
+    type: integer
+    minimum: 1
+  timeout:
+    type: string
+required:
+  - pattern";
+    let conf_spec = ConfigSpec::from_schema(config)?;
+
+    register_plugin(
+        "multiline".into(),
+        ItemType::Processor,
+        conf_spec,
+        create_multiline,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn register_plugin() {
+        register_multiline().unwrap()
+    }
+
+    fn processor(conf: &str) -> Multiline {
+        let c: MultilineConfig = serde_yaml::from_str(conf).unwrap();
+        Multiline {
+            pattern: Regex::new(&c.pattern).unwrap(),
+            match_mode: c.match_mode,
+            negate: c.negate,
+            keys: c.keys,
+            separator: c.separator.into_bytes(),
+            max_lines: c.max_lines,
+            max_bytes: c.max_bytes,
+            timeout: c.timeout,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn line(text: &str) -> Message {
+        Message {
+            bytes: text.as_bytes().to_vec(),
+            ..Default::default()
+        }
+    }
+
+    fn host_line(host: &str, text: &str) -> Message {
+        Message {
+            bytes: text.as_bytes().to_vec(),
+            metadata: HashMap::from([("host".into(), Value::String(host.into()))]),
+            ..Default::default()
+        }
+    }
+
+    async fn run(p: &Multiline, lines: Vec<Message>) -> Vec<String> {
+        let mut output = Vec::new();
+        for l in lines {
+            output.extend(p.process(l).await.unwrap());
+        }
+        output.extend(p.flush(true).await.unwrap());
+        output
+            .into_iter()
+            .map(|m| String::from_utf8(m.bytes).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn java_stack_trace() {
+        let p = processor(r"pattern: '^\s+at |^Caused by:'");
+        let output = run(
+            &p,
+            vec![
+                line("Exception in thread \"main\" java.lang.NullPointerException"),
+                line("    at com.example.App.run(App.java:10)"),
+                line("    at com.example.App.main(App.java:5)"),
+                line("Caused by: java.lang.IllegalStateException"),
+                line("INFO next event"),
+            ],
+        )
+        .await;
+        assert_eq!(
+            output,
+            vec![
+                "Exception in thread \"main\" java.lang.NullPointerException\n    at com.example.App.run(App.java:10)\n    at com.example.App.main(App.java:5)\nCaused by: java.lang.IllegalStateException",
+                "INFO next event"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn start_pattern() {
+        let p = processor(
+            r"pattern: '^\d{4}-\d{2}-\d{2}'
+match: start",
+        );
+        let output = run(
+            &p,
+            vec![
+                line("2024-01-01 ERROR failed"),
+                line("Traceback (most recent call last):"),
+                line("  File \"app.py\", line 1"),
+                line("2024-01-01 INFO ok"),
+            ],
+        )
+        .await;
+        assert_eq!(
+            output,
+            vec![
+                "2024-01-01 ERROR failed\nTraceback (most recent call last):\n  File \"app.py\", line 1",
+                "2024-01-01 INFO ok"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn negate_pattern() {
+        let p = processor(
+            r"pattern: '^\['
+negate: true",
+        );
+        let output = run(&p, vec![line("[a]"), line("b"), line("[c]")]).await;
+        assert_eq!(output, vec!["[a]\nb", "[c]"]);
+    }
+
+    #[tokio::test]
+    async fn keyed_sources_are_not_mixed() {
+        let p = processor(
+            r"pattern: '^\s'
+keys: [host]",
+        );
+        let output = run(
+            &p,
+            vec![
+                host_line("a", "a1"),
+                host_line("b", "b1"),
+                host_line("a", " a2"),
+                host_line("b", " b2"),
+                host_line("a", "a3"),
+            ],
+        )
+        .await;
+        assert_eq!(output, vec!["a1\n a2", "b1\n b2", "a3"]);
+    }
+
+    #[tokio::test]
+    async fn max_lines_splits_event() {
+        let p = processor(
+            r"pattern: '^\s'
+max_lines: 2",
+        );
+        let output = run(&p, vec![line("a"), line(" b"), line(" c")]).await;
+        assert_eq!(output, vec!["a\n b", " c"]);
+    }
+
+    #[tokio::test]
+    async fn max_bytes_splits_event() {
+        let p = processor(
+            r"pattern: '^\s'
+max_bytes: 5",
+        );
+        let output = run(&p, vec![line("ab"), line(" c"), line(" d")]).await;
+        assert_eq!(output, vec!["ab\n c", " d"]);
+    }
+
+    #[tokio::test]
+    async fn line_count_metadata() {
+        let p = processor(r"pattern: '^\s'");
+        assert!(p.process(line("a")).await.unwrap().is_empty());
+        assert!(p.process(line(" b")).await.unwrap().is_empty());
+        let output = p.flush(true).await.unwrap();
+        assert_eq!(output.len(), 1);
+        assert_eq!(
+            output[0].metadata.get(LINE_COUNT_METADATA),
+            Some(&Value::Number(2.into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn flush_respects_timeout() {
+        let p = processor(
+            r"pattern: '^\s'
+timeout: 50ms",
+        );
+        assert!(p.process(line("a")).await.unwrap().is_empty());
+        assert!(p.flush(false).await.unwrap().is_empty());
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let output = p.flush(false).await.unwrap();
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0].bytes, b"a".to_vec());
+        assert!(p.flush(true).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn invalid_pattern() {
+        let conf: Value = serde_yaml::from_str("pattern: '('").unwrap();
+        assert!(create_multiline(conf).await.is_err());
+    }
+}
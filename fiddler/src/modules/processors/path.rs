@@ -0,0 +1,324 @@
+//! Shared helpers for addressing fields inside JSON message bodies.
+//!
+//! Paths are written either in dotted notation (`user.profile.email`) or as a
+//! JSON pointer (`/user/profile/email`).  A value starting with `/` is treated
+//! as a JSON pointer, where `~1` and `~0` escape `/` and `~` respectively;
+//! otherwise the value is split on `.`.  Every segment addresses an object key.
+//!
+//! [`FieldTemplate`] builds strings such as lookup keys or request URLs from a
+//! message, replacing `${path}` placeholders with the referenced field.
+//! Substituted values can be escaped for the context they are placed in, so a
+//! field cannot change the structure of a URL or JSON document.
+//!
+//! [`Expression`] is a JMESPath expression compiled once, when the processor
+//! is created, and evaluated against each message with [`variable`].
+
+use crate::Error;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde_json::{Map, Value};
+
+/// Everything except the RFC 3986 unreserved characters
+pub const PERCENT_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Split a dotted or JSON pointer path into its object key segments.
+pub fn parse_path(path: &str) -> Result<Vec<String>, Error> {
+    let segments: Vec<String> = match path.strip_prefix('/') {
+        Some(pointer) => pointer
+            .split('/')
+            .map(|s| s.replace("~1", "/").replace("~0", "~"))
+            .collect(),
+        None => path.split('.').map(|s| s.to_string()).collect(),
+    };
+
+    if segments.iter().any(|s| s.is_empty()) {
+        return Err(Error::ConfigFailedValidation(format!(
+            "invalid field path '{path}': empty segment"
+        )));
+    }
+
+    Ok(segments)
+}
+
+/// Return a reference to the value at `path`, if every segment exists.
+pub fn get_path<'a>(root: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter().try_fold(root, |current, key| current.get(key))
+}
+
+/// Return a mutable reference to the value at `path`, if every segment exists.
+pub fn get_path_mut<'a>(root: &'a mut Value, path: &[String]) -> Option<&'a mut Value> {
+    path.iter()
+        .try_fold(root, |current, key| current.get_mut(key))
+}
+
+/// Insert `value` at `path`, creating intermediate objects as needed.
+///
+/// Fails if an intermediate segment already holds a non-object value.
+pub fn set_path(root: &mut Value, path: &[String], value: Value) -> Result<(), Error> {
+    let Some((last, parents)) = path.split_last() else {
+        *root = value;
+        return Ok(());
+    };
+
+    let mut current = root;
+    for key in parents {
+        let obj = current.as_object_mut().ok_or_else(|| {
+            Error::MessageFailed(format!(
+                "cannot set '{}': '{key}' is not within an object",
+                path.join(".")
+            ))
+        })?;
+        current = obj
+            .entry(key.clone())
+            .or_insert_with(|| Value::Object(Map::new()));
+    }
+
+    match current.as_object_mut() {
+        Some(obj) => {
+            obj.insert(last.clone(), value);
+            Ok(())
+        }
+        None => Err(Error::MessageFailed(format!(
+            "cannot set '{}': parent of '{last}' is not an object",
+            path.join(".")
+        ))),
+    }
+}
+
+/// JMESPath expression compiled with the shared runtime of the `jmespath`
+/// crate, which has the built-in functions registered.
+pub struct Expression(jmespath::Expression<'static>);
+
+impl Expression {
+    /// Compile an expression, failing the configuration if it is invalid.
+    pub fn compile(expression: &str) -> Result<Self, Error> {
+        jmespath::compile(expression)
+            .map(Expression)
+            .map_err(|e| Error::ConfigFailedValidation(format!("{e}")))
+    }
+
+    /// Evaluate the expression against `data`, returning the result as JSON.
+    pub fn search(&self, data: &jmespath::Variable) -> Result<Value, Error> {
+        let result = self
+            .0
+            .search(data)
+            .map_err(|e| Error::MessageFailed(format!("{e}")))?;
+        serde_json::to_value(&*result).map_err(|e| Error::MessageFailed(format!("{e}")))
+    }
+}
+
+impl std::fmt::Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Parse a JSON message body for evaluating expressions against.
+pub fn variable(bytes: &[u8]) -> Result<jmespath::Variable, Error> {
+    let json_str = std::str::from_utf8(bytes).map_err(|e| Error::MessageFailed(format!("{e}")))?;
+    jmespath::Variable::from_json(json_str).map_err(Error::MessageFailed)
+}
+
+#[cfg_attr(not(any(feature = "redis", feature = "http_client")), allow(dead_code))]
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Field(Vec<String>),
+}
+
+/// How [`FieldTemplate::render_escaped`] escapes substituted values.
+#[cfg_attr(not(feature = "http_client"), allow(dead_code))]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Escape {
+    /// Insert values unchanged
+    None,
+    /// Percent-encode everything except the RFC 3986 unreserved characters
+    Url,
+    /// Escape values for use inside a JSON string
+    Json,
+}
+
+impl Escape {
+    fn apply(&self, value: &str) -> String {
+        match self {
+            Escape::None => value.to_string(),
+            Escape::Url => utf8_percent_encode(value, PERCENT_ENCODE_SET).to_string(),
+            Escape::Json => {
+                let quoted = Value::String(value.to_string()).to_string();
+                quoted[1..quoted.len() - 1].to_string()
+            }
+        }
+    }
+}
+
+/// String template with `${path}` placeholders referencing message fields.
+#[cfg_attr(not(any(feature = "redis", feature = "http_client")), allow(dead_code))]
+#[derive(Debug, PartialEq)]
+pub struct FieldTemplate {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+#[cfg_attr(not(any(feature = "redis", feature = "http_client")), allow(dead_code))]
+impl FieldTemplate {
+    /// Parse a template, validating every placeholder path.
+    pub fn parse(source: &str) -> Result<Self, Error> {
+        let mut segments = Vec::new();
+        let mut rest = source;
+
+        while let Some(start) = rest.find("${") {
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_string()));
+            }
+            let after = &rest[start + 2..];
+            let end = after.find('}').ok_or_else(|| {
+                Error::ConfigFailedValidation(format!("unclosed placeholder in '{source}'"))
+            })?;
+            segments.push(Segment::Field(parse_path(&after[..end])?));
+            rest = &after[end + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_string()));
+        }
+
+        Ok(FieldTemplate {
+            source: source.to_string(),
+            segments,
+        })
+    }
+
+    /// Returns `true` if the template references at least one field.
+    pub fn has_fields(&self) -> bool {
+        self.segments.iter().any(|s| matches!(s, Segment::Field(_)))
+    }
+
+    /// Render the template, failing if a field is missing or not a scalar.
+    pub fn render(&self, root: &Value) -> Result<String, Error> {
+        self.render_escaped(root, Escape::None)
+    }
+
+    /// Render the template, escaping every substituted value.
+    pub fn render_escaped(&self, root: &Value, escape: Escape) -> Result<String, Error> {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(s) => rendered.push_str(s),
+                Segment::Field(path) => match get_path(root, path) {
+                    Some(Value::String(s)) => rendered.push_str(&escape.apply(s)),
+                    Some(v @ (Value::Number(_) | Value::Bool(_))) => {
+                        rendered.push_str(&v.to_string())
+                    }
+                    _ => {
+                        return Err(Error::MessageFailed(format!(
+                            "template '{}': field '{}' is missing or not a scalar",
+                            self.source,
+                            path.join(".")
+                        )))
+                    }
+                },
+            }
+        }
+        Ok(rendered)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parse_dotted() {
+        assert_eq!(parse_path("user.name").unwrap(), vec!["user", "name"]);
+        assert_eq!(parse_path("name").unwrap(), vec!["name"]);
+    }
+
+    #[test]
+    fn parse_pointer() {
+        assert_eq!(
+            parse_path("/user/first.name").unwrap(),
+            vec!["user", "first.name"]
+        );
+        assert_eq!(parse_path("/a~1b/c~0d").unwrap(), vec!["a/b", "c~d"]);
+    }
+
+    #[test]
+    fn parse_rejects_empty_segments() {
+        assert!(parse_path("").is_err());
+        assert!(parse_path("user..name").is_err());
+        assert!(parse_path("/user/").is_err());
+    }
+
+    #[test]
+    fn set_creates_nested_objects() {
+        let mut root = json!({});
+        set_path(&mut root, &parse_path("a.b.c").unwrap(), json!(1)).unwrap();
+        assert_eq!(root, json!({"a": {"b": {"c": 1}}}));
+    }
+
+    #[test]
+    fn get_nested_value() {
+        let root = json!({"a": {"b": 2}});
+        assert_eq!(
+            get_path(&root, &parse_path("a.b").unwrap()),
+            Some(&json!(2))
+        );
+        assert_eq!(get_path(&root, &parse_path("a.c").unwrap()), None);
+    }
+
+    #[test]
+    fn set_conflicts_with_scalar() {
+        let mut root = json!({"a": "scalar"});
+        assert!(set_path(&mut root, &parse_path("a.b").unwrap(), json!(1)).is_err());
+    }
+
+    #[test]
+    fn template_render() {
+        let t = FieldTemplate::parse("user:${user.id}:${/region}").unwrap();
+        let root = json!({"user": {"id": 42}, "region": "eu"});
+        assert!(t.has_fields());
+        assert_eq!(t.render(&root).unwrap(), "user:42:eu");
+
+        let plain = FieldTemplate::parse("static").unwrap();
+        assert!(!plain.has_fields());
+        assert_eq!(plain.render(&root).unwrap(), "static");
+
+        assert!(t.render(&json!({"user": {}})).is_err());
+        assert!(t
+            .render(&json!({"user": {"id": [1]}, "region": "eu"}))
+            .is_err());
+    }
+
+    #[test]
+    fn template_escaping() {
+        let t = FieldTemplate::parse("${value}").unwrap();
+        let root = json!({"value": "a/b?c=1#d \"e\" é"});
+        assert_eq!(
+            t.render_escaped(&root, Escape::Url).unwrap(),
+            "a%2Fb%3Fc%3D1%23d%20%22e%22%20%C3%A9"
+        );
+        assert_eq!(
+            t.render_escaped(&root, Escape::Json).unwrap(),
+            r#"a/b?c=1#d \"e\" é"#
+        );
+        assert_eq!(
+            t.render_escaped(&json!({"value": "line\nbreak\\"}), Escape::Json)
+                .unwrap(),
+            r#"line\nbreak\\"#
+        );
+        assert_eq!(
+            t.render_escaped(&json!({"value": 4.5}), Escape::Url)
+                .unwrap(),
+            "4.5"
+        );
+    }
+
+    #[test]
+    fn template_parse_errors() {
+        assert!(FieldTemplate::parse("user:${id").is_err());
+        assert!(FieldTemplate::parse("user:${}").is_err());
+    }
+}
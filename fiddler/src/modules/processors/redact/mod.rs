@@ -0,0 +1,610 @@
+//! Redact processor for masking or pseudonymizing personal data.
+//!
+//! Built-in detectors and custom regular expressions are applied to selected
+//! fields of a JSON message, or to every string of the message when no fields
+//! are configured.  Matches are masked, removed, or replaced with a keyed
+//! HMAC-SHA256 digest so the same value always maps to the same pseudonym.
+//! Whole field values can be hashed with `hash_fields`.  The number of
+//! redactions is added to the message metadata.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - redact:
+//!       detectors: [email, ipv4, ipv6, credit_card, ssn]  # Optional: built-in detectors
+//!       patterns:                          # Optional: custom regular expressions
+//!         - name: employee_id
+//!           pattern: 'EMP-\d{6}'
+//!       fields: ["message", "user.email"]  # Optional: fields to scan (default: whole message)
+//!       action: mask                       # Optional: mask, remove or hash (default: mask)
+//!       mask: "[REDACTED]"                 # Optional: replacement used by mask
+//!       hash_fields: ["user.id"]           # Optional: fields replaced by their HMAC
+//!       key: "{{ REDACT_KEY }}"            # Required for hash and hash_fields
+//! ```
+
+use crate::config::register_plugin;
+use crate::config::ItemType;
+use crate::config::{ConfigSpec, ExecutionType};
+use crate::modules::processors::path::{get_path_mut, parse_path};
+use crate::Message;
+use crate::MessageBatch;
+use crate::{Closer, Error, Processor};
+use async_trait::async_trait;
+use fiddler_macros::fiddler_registration_func;
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use serde_yaml::{Mapping, Value};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::net::Ipv6Addr;
+
+const COUNT_METADATA: &str = "redaction_count";
+const DETAILS_METADATA: &str = "redactions";
+const HASHED_FIELDS: &str = "hash_fields";
+
+const EMAIL_PATTERN: &str = r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}";
+const IPV4_PATTERN: &str =
+    r"\b(?:(?:25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)\.){3}(?:25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)\b";
+// Hex groups optionally ending in an embedded IPv4 address, such as ::ffff:1.2.3.4
+const IPV6_PATTERN: &str =
+    r"(?:[0-9A-Fa-f]{0,4}:){2,7}(?:(?:\d{1,3}\.){3}\d{1,3}|[0-9A-Fa-f]{1,4}|:)?";
+const CREDIT_CARD_PATTERN: &str = r"\b(?:\d[ -]?){12,18}\d\b";
+const SSN_PATTERN: &str = r"\b\d{3}-\d{2}-\d{4}\b";
+
+static EMAIL: Lazy<Regex> =
+    Lazy::new(|| Regex::new(EMAIL_PATTERN).expect("email pattern is valid"));
+static IPV4: Lazy<Regex> = Lazy::new(|| Regex::new(IPV4_PATTERN).expect("ipv4 pattern is valid"));
+static IPV6: Lazy<Regex> = Lazy::new(|| Regex::new(IPV6_PATTERN).expect("ipv6 pattern is valid"));
+static CREDIT_CARD: Lazy<Regex> =
+    Lazy::new(|| Regex::new(CREDIT_CARD_PATTERN).expect("credit card pattern is valid"));
+static SSN: Lazy<Regex> = Lazy::new(|| Regex::new(SSN_PATTERN).expect("ssn pattern is valid"));
+
+fn default_mask() -> String {
+    "[REDACTED]".into()
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum BuiltIn {
+    Email,
+    Ipv4,
+    Ipv6,
+    CreditCard,
+    Ssn,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    #[default]
+    Mask,
+    Remove,
+    Hash,
+}
+
+#[derive(Deserialize)]
+struct PatternConfig {
+    name: String,
+    pattern: String,
+}
+
+#[derive(Deserialize)]
+struct RedactConfig {
+    #[serde(default)]
+    detectors: Vec<BuiltIn>,
+    #[serde(default)]
+    patterns: Vec<PatternConfig>,
+    fields: Option<Vec<String>>,
+    #[serde(default)]
+    action: Action,
+    #[serde(default = "default_mask")]
+    mask: String,
+    #[serde(default)]
+    hash_fields: Vec<String>,
+    key: Option<String>,
+}
+
+/// Passes the Luhn checksum used by payment card numbers
+fn luhn_valid(candidate: &str) -> bool {
+    let digits: Vec<u32> = candidate.chars().filter_map(|c| c.to_digit(10)).collect();
+    if !(13..=19).contains(&digits.len()) {
+        return false;
+    }
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| match (!i.is_multiple_of(2), d * 2) {
+            (true, doubled) if doubled > 9 => doubled - 9,
+            (true, doubled) => doubled,
+            (false, _) => d,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// Rejects area, group and serial numbers that are never issued
+fn ssn_valid(candidate: &str) -> bool {
+    let mut parts = candidate.split('-');
+    let (Some(area), Some(group), Some(serial)) = (parts.next(), parts.next(), parts.next()) else {
+        return false;
+    };
+    area != "000" && area != "666" && !area.starts_with('9') && group != "00" && serial != "0000"
+}
+
+/// Requires a hex group, so a bare `::` path separator is not an address
+fn ipv6_valid(candidate: &str) -> bool {
+    candidate.chars().any(|c| c.is_ascii_hexdigit()) && candidate.parse::<Ipv6Addr>().is_ok()
+}
+
+/// The match is not part of a longer identifier, such as the `d::` in `std::vector`
+fn standalone(text: &str, start: usize, end: usize) -> bool {
+    let identifier = |c: char| c.is_alphanumeric() || c == '_';
+    !text[..start].chars().next_back().is_some_and(identifier)
+        && !text[end..].chars().next().is_some_and(identifier)
+}
+
+/// Check applied to regex matches to reject false positives
+type Validator = fn(&str) -> bool;
+
+struct Detector {
+    name: String,
+    regex: Regex,
+    validate: Option<Validator>,
+    /// Reject matches touching identifier characters, which the regex cannot express
+    standalone: bool,
+}
+
+impl Detector {
+    fn built_in(kind: BuiltIn) -> Self {
+        let (name, regex, validate): (&str, &Regex, Option<Validator>) = match kind {
+            BuiltIn::Email => ("email", &EMAIL, None),
+            BuiltIn::Ipv4 => ("ipv4", &IPV4, None),
+            BuiltIn::Ipv6 => ("ipv6", &IPV6, Some(ipv6_valid)),
+            BuiltIn::CreditCard => ("credit_card", &CREDIT_CARD, Some(luhn_valid)),
+            BuiltIn::Ssn => ("ssn", &SSN, Some(ssn_valid)),
+        };
+        Detector {
+            name: name.into(),
+            regex: regex.clone(),
+            validate,
+            standalone: kind == BuiltIn::Ipv6,
+        }
+    }
+}
+
+pub struct Redact {
+    detectors: Vec<Detector>,
+    fields: Option<Vec<Vec<String>>>,
+    action: Action,
+    mask: String,
+    hash_fields: Vec<Vec<String>>,
+    key: Option<Vec<u8>>,
+}
+
+impl Redact {
+    fn hash(&self, value: &str) -> Result<String, Error> {
+        let key = self
+            .key
+            .as_deref()
+            .ok_or_else(|| Error::ProcessingError("redact hashing requires a key".into()))?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(key)
+            .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+        mac.update(value.as_bytes());
+        Ok(mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect())
+    }
+
+    /// Apply every detector to the text, counting redactions per detector
+    fn redact_text(&self, text: &str, counts: &mut BTreeMap<String, u64>) -> Result<String, Error> {
+        let mut text = text.to_string();
+        for detector in &self.detectors {
+            let mut count = 0;
+            let mut error = None;
+            let replaced = detector.regex.replace_all(&text, |caps: &Captures| {
+                let found = caps.get(0).expect("group 0 is the whole match");
+                let matched = found.as_str();
+                if detector.validate.is_some_and(|valid| !valid(matched))
+                    || (detector.standalone && !standalone(&text, found.start(), found.end()))
+                {
+                    return matched.to_string();
+                }
+                count += 1;
+                match self.action {
+                    Action::Mask => self.mask.clone(),
+                    Action::Remove => String::new(),
+                    Action::Hash => self.hash(matched).unwrap_or_else(|e| {
+                        error = Some(e);
+                        String::new()
+                    }),
+                }
+            });
+            let replaced = replaced.into_owned();
+            if let Some(e) = error {
+                return Err(e);
+            }
+            if count > 0 {
+                *counts.entry(detector.name.clone()).or_default() += count;
+                text = replaced;
+            }
+        }
+        Ok(text)
+    }
+
+    /// Redact every string nested within the value
+    fn redact_value(
+        &self,
+        value: &mut JsonValue,
+        counts: &mut BTreeMap<String, u64>,
+    ) -> Result<(), Error> {
+        match value {
+            JsonValue::String(s) => *s = self.redact_text(s, counts)?,
+            JsonValue::Array(items) => {
+                for item in items {
+                    self.redact_value(item, counts)?;
+                }
+            }
+            JsonValue::Object(map) => {
+                for (_, item) in map.iter_mut() {
+                    self.redact_value(item, counts)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn redact_document(
+        &self,
+        doc: &mut JsonValue,
+        counts: &mut BTreeMap<String, u64>,
+    ) -> Result<(), Error> {
+        match &self.fields {
+            Some(fields) => {
+                for path in fields {
+                    if let Some(value) = get_path_mut(doc, path) {
+                        self.redact_value(value, counts)?;
+                    }
+                }
+            }
+            None => self.redact_value(doc, counts)?,
+        }
+
+        for path in &self.hash_fields {
+            if let Some(value) = get_path_mut(doc, path) {
+                let plain = match &*value {
+                    JsonValue::Null => continue,
+                    JsonValue::String(s) => s.clone(),
+                    other => other.to_string(),
+                };
+                *value = JsonValue::String(self.hash(&plain)?);
+                *counts.entry(HASHED_FIELDS.into()).or_default() += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Processor for Redact {
+    async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
+        let mut counts = BTreeMap::new();
+        let mut message = message;
+
+        match serde_json::from_slice::<JsonValue>(&message.bytes) {
+            Ok(mut doc) => {
+                self.redact_document(&mut doc, &mut counts)?;
+                if !counts.is_empty() {
+                    message.bytes = serde_json::to_vec(&doc)
+                        .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+                }
+            }
+            Err(_) if self.fields.is_none() && self.hash_fields.is_empty() => {
+                let text = String::from_utf8(message.bytes.clone())
+                    .map_err(|e| Error::MessageFailed(format!("{e}")))?;
+                message.bytes = self.redact_text(&text, &mut counts)?.into_bytes();
+            }
+            Err(e) => return Err(Error::MessageFailed(format!("{e}"))),
+        }
+
+        let total: u64 = counts.values().sum();
+        let details: Mapping = counts
+            .into_iter()
+            .map(|(name, count)| (Value::String(name), Value::Number(count.into())))
+            .collect();
+        message
+            .metadata
+            .insert(COUNT_METADATA.into(), Value::Number(total.into()));
+        message
+            .metadata
+            .insert(DETAILS_METADATA.into(), Value::Mapping(details));
+
+        Ok(vec![message])
+    }
+}
+
+impl Closer for Redact {}
+
+#[fiddler_registration_func]
+fn create_redact(conf: Value) -> Result<ExecutionType, Error> {
+    let c: RedactConfig = serde_yaml::from_value(conf.clone())?;
+
+    // IPv6 runs first so addresses with an embedded IPv4 part are redacted whole
+    let mut built_in = c.detectors;
+    built_in.sort_by_key(|kind| *kind != BuiltIn::Ipv6);
+    let mut detectors: Vec<Detector> = built_in.into_iter().map(Detector::built_in).collect();
+    for p in c.patterns {
+        let regex = Regex::new(&p.pattern)
+            .map_err(|e| Error::ConfigFailedValidation(format!("pattern '{}': {e}", p.name)))?;
+        detectors.push(Detector {
+            name: p.name,
+            regex,
+            validate: None,
+            standalone: false,
+        });
+    }
+
+    if detectors.is_empty() && c.hash_fields.is_empty() {
+        return Err(Error::ConfigFailedValidation(
+            "at least one detector, pattern or hash field is required".into(),
+        ));
+    }
+
+    let needs_key = c.action == Action::Hash || !c.hash_fields.is_empty();
+    let key = match c.key {
+        Some(key) if !key.is_empty() => Some(key.into_bytes()),
+        _ if needs_key => {
+            return Err(Error::ConfigFailedValidation(
+                "a non-empty key is required for hashing".into(),
+            ))
+        }
+        _ => None,
+    };
+
+    Ok(ExecutionType::Processor(Box::new(Redact {
+        detectors,
+        fields: c
+            .fields
+            .map(|f| f.iter().map(|p| parse_path(p)).collect::<Result<_, _>>())
+            .transpose()?,
+        action: c.action,
+        mask: c.mask,
+        hash_fields: c
+            .hash_fields
+            .iter()
+            .map(|p| parse_path(p))
+            .collect::<Result<_, _>>()?,
+        key,
+    })))
+}
+
+pub(super) fn register_redact() -> Result<(), Error> {
+    let config = "type: object
+properties:
+  detectors:
+    type: array
+    items:
+      type: string
+      enum: [\"email\", \"ipv4\", \"ipv6\", \"credit_card\", \"ssn\"]
+  patterns:
+    type: array
+    items:
+      type: object
+      properties:
+        name:
+          type: string
+        pattern:
+          type: string
+      required:
+        - name
+        - pattern
+  fields:
+    type: array
+    items:
+      type: string
+  action:
+    type: string
+    enum: [\"mask\", \"remove\", \"hash\"]
+  mask:
+    type: string
+  hash_fields:
+    type: array
+    items:
+      type: string
+  key:
+    type: string";
+    let conf_spec = ConfigSpec::from_schema(config)?;
+
+    register_plugin(
+        "redact".into(),
+        ItemType::Processor,
+        conf_spec,
+        create_redact,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::modules::processors::test_util::{processor, text_message};
+    use serde_json::json;
+
+    #[test]
+    fn register_plugin() {
+        register_redact().unwrap()
+    }
+
+    async fn run(conf: &str, body: &str) -> Message {
+        let p = processor(create_redact, conf).await;
+        p.process(text_message(body)).await.unwrap().remove(0)
+    }
+
+    fn text(message: &Message) -> String {
+        String::from_utf8(message.bytes.clone()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn built_in_detectors_on_text() {
+        let result = run(
+            "detectors: [email, ipv4, ipv6, credit_card, ssn]",
+            "mail bob@example.co.uk from 10.1.2.3 and fe80::1 card 4111 1111 1111 1111 ssn 123-45-6789",
+        )
+        .await;
+        assert_eq!(
+            text(&result),
+            "mail [REDACTED] from [REDACTED] and [REDACTED] card [REDACTED] ssn [REDACTED]"
+        );
+        assert_eq!(
+            result.metadata.get(COUNT_METADATA),
+            Some(&Value::Number(5.into()))
+        );
+        let details = result.metadata.get(DETAILS_METADATA).unwrap();
+        assert_eq!(details.get("email"), Some(&Value::Number(1.into())));
+        assert_eq!(details.get("credit_card"), Some(&Value::Number(1.into())));
+    }
+
+    #[tokio::test]
+    async fn ipv6_with_embedded_ipv4() {
+        for detectors in ["[ipv6]", "[ipv4, ipv6]"] {
+            let result = run(
+                &format!("detectors: {detectors}"),
+                "from ::ffff:1.2.3.4 and 64:ff9b::192.0.2.33 via 10.0.0.1",
+            )
+            .await;
+            let expected = if detectors == "[ipv6]" {
+                "from [REDACTED] and [REDACTED] via 10.0.0.1"
+            } else {
+                "from [REDACTED] and [REDACTED] via [REDACTED]"
+            };
+            assert_eq!(text(&result), expected, "{detectors}");
+            let details = result.metadata.get(DETAILS_METADATA).unwrap();
+            assert_eq!(details.get("ipv6"), Some(&Value::Number(2.into())));
+        }
+    }
+
+    #[tokio::test]
+    async fn fields_require_json() {
+        let p = processor(create_redact, "detectors: [email]\nfields: [contact]").await;
+        let result = p.process(text_message("mail a@b.io")).await;
+        assert!(matches!(result, Err(Error::MessageFailed(_))), "{result:?}");
+    }
+
+    #[tokio::test]
+    async fn ipv6_ignores_path_separators() {
+        for body in [
+            "std::vector<int> v;",
+            "returned Error::ProcessingError here",
+            "a bare :: separator",
+            "::",
+            "crate::modules::processors::redact",
+            "xfe80::1 and fe80::1y",
+        ] {
+            let result = run("detectors: [ipv6]", body).await;
+            assert_eq!(text(&result), body);
+        }
+
+        let result = run("detectors: [ipv6]", "from fe80::1, to ::1 (1::)").await;
+        assert_eq!(text(&result), "from [REDACTED], to [REDACTED] ([REDACTED])");
+    }
+
+    #[tokio::test]
+    async fn validators_reject_false_positives() {
+        let body = "order 4111 1111 1111 1112 at 12:30:45 ref 000-12-3456 ip 999.1.1.1";
+        let result = run("detectors: [ipv4, ipv6, credit_card, ssn]", body).await;
+        assert_eq!(text(&result), body);
+        assert_eq!(
+            result.metadata.get(COUNT_METADATA),
+            Some(&Value::Number(0.into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn selected_fields_and_remove() {
+        let result = run(
+            "detectors: [email]\nfields: [contact]\naction: remove",
+            r#"{"contact": "mail: a@b.io", "note": "c@d.io"}"#,
+        )
+        .await;
+        let body: JsonValue = serde_json::from_slice(&result.bytes).unwrap();
+        assert_eq!(body, json!({"contact": "mail: ", "note": "c@d.io"}));
+    }
+
+    #[tokio::test]
+    async fn nested_strings_and_custom_patterns() {
+        let result = run(
+            "patterns:\n  - name: employee_id\n    pattern: 'EMP-\\d{6}'\nmask: '<id>'",
+            r#"{"users": [{"id": "EMP-123456"}, {"id": "EMP-1"}], "count": 2}"#,
+        )
+        .await;
+        let body: JsonValue = serde_json::from_slice(&result.bytes).unwrap();
+        assert_eq!(
+            body,
+            json!({"users": [{"id": "<id>"}, {"id": "EMP-1"}], "count": 2})
+        );
+        let details = result.metadata.get(DETAILS_METADATA).unwrap();
+        assert_eq!(details.get("employee_id"), Some(&Value::Number(1.into())));
+    }
+
+    #[tokio::test]
+    async fn consistent_hashing() {
+        let conf = "detectors: [email]\naction: hash\nhash_fields: [user.id]\nkey: secret";
+        let first = run(conf, r#"{"user": {"id": 42}, "msg": "from a@b.io"}"#).await;
+        let second = run(conf, r#"{"user": {"id": 42}, "msg": "to a@b.io"}"#).await;
+        let other_key = run(
+            "hash_fields: [user.id]\nkey: other",
+            r#"{"user": {"id": 42}}"#,
+        )
+        .await;
+
+        let a: JsonValue = serde_json::from_slice(&first.bytes).unwrap();
+        let b: JsonValue = serde_json::from_slice(&second.bytes).unwrap();
+        let c: JsonValue = serde_json::from_slice(&other_key.bytes).unwrap();
+
+        let id = a["user"]["id"].as_str().unwrap();
+        assert_eq!(id.len(), 64);
+        assert_eq!(a["user"]["id"], b["user"]["id"]);
+        assert_ne!(a["user"]["id"], c["user"]["id"]);
+        assert_eq!(
+            a["msg"].as_str().unwrap().strip_prefix("from "),
+            b["msg"].as_str().unwrap().strip_prefix("to ")
+        );
+
+        let details = first.metadata.get(DETAILS_METADATA).unwrap();
+        assert_eq!(details.get(HASHED_FIELDS), Some(&Value::Number(1.into())));
+        assert_eq!(
+            first.metadata.get(COUNT_METADATA),
+            Some(&Value::Number(2.into()))
+        );
+    }
+
+    #[test]
+    fn luhn() {
+        assert!(luhn_valid("4111111111111111"));
+        assert!(luhn_valid("5500-0000-0000-0004"));
+        assert!(!luhn_valid("4111111111111112"));
+        assert!(!luhn_valid("1234"));
+    }
+
+    #[tokio::test]
+    async fn invalid_config() {
+        for conf in [
+            "{}",
+            "detectors: [phone]",
+            "patterns: [{name: bad, pattern: '('}]",
+            "detectors: [email]\naction: hash",
+            "hash_fields: [id]\nkey: ''",
+            "detectors: [email]\nfields: ['a..b']",
+        ] {
+            let value: Value = serde_yaml::from_str(conf).unwrap();
+            assert!(create_redact(value).await.is_err(), "{conf}");
+        }
+    }
+}
@@ -0,0 +1,497 @@
+//! Redis lookup processor for enriching messages with values held in Redis.
+//!
+//! Each lookup builds one or more keys from a template, where `${path}`
+//! placeholders are replaced by fields of the JSON message body, and issues
+//! `GET`, `HGETALL` or `MGET`.  The reply is written to `target`, or merged into
+//! the root of the document when no target is given.  All lookups of a message
+//! that are not answered by the local cache are sent in a single pipeline.
+//! Pipelining is per message: processors receive one message at a time, so
+//! lookups are not batched across messages, and each message costs one round
+//! trip unless every lookup is cached.  Key values are inserted unescaped.  A
+//! message that is not JSON, misses a key field or whose lookup fails in Redis
+//! fails on its own, so it can be retried while other messages continue.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - redis_lookup:
+//!       url: "redis://localhost:6379/0"     # Required: Redis connection URL
+//!       lookups:                            # Required: lookups to perform
+//!         - command: get                    # Optional: get, hgetall or mget (default: get)
+//!           key: "user:${user.id}"          # Required for get and hgetall
+//!           target: "user.profile"          # Optional: field to write the result to
+//!         - command: mget
+//!           keys: ["geo:${ip}", "asn:${ip}"]  # Required for mget
+//!           target: "network"
+//!       parse_json: true                    # Optional: decode values holding JSON (default: true)
+//!       cache_ttl: 1m                       # Optional: cache replies locally for this long
+//!       cache_size: 10000                   # Optional: maximum cached replies
+//! ```
+
+use crate::config::register_plugin;
+use crate::config::ItemType;
+use crate::config::{ConfigSpec, ExecutionType};
+use crate::modules::processors::path::{parse_path, set_path, FieldTemplate};
+use crate::modules::redis::connection_manager;
+use crate::Message;
+use crate::MessageBatch;
+use crate::{Closer, Error, Processor};
+use async_trait::async_trait;
+use fiddler_macros::fiddler_registration_func;
+use lru::LruCache;
+use redis::aio::ConnectionManager;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use serde_yaml::Value;
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+fn default_parse_json() -> bool {
+    true
+}
+
+fn default_cache_size() -> usize {
+    10_000
+}
+
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Command {
+    #[default]
+    Get,
+    Hgetall,
+    Mget,
+}
+
+impl Command {
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Get => "GET",
+            Command::Hgetall => "HGETALL",
+            Command::Mget => "MGET",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct LookupConfig {
+    #[serde(default)]
+    command: Command,
+    key: Option<String>,
+    keys: Option<Vec<String>>,
+    target: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RedisLookupConfig {
+    url: String,
+    lookups: Vec<LookupConfig>,
+    #[serde(default = "default_parse_json")]
+    parse_json: bool,
+    #[serde(default, deserialize_with = "crate::deserialize_optional_duration")]
+    cache_ttl: Option<Duration>,
+    #[serde(default = "default_cache_size")]
+    cache_size: usize,
+}
+
+struct Lookup {
+    command: Command,
+    keys: Vec<FieldTemplate>,
+    target: Option<Vec<String>>,
+}
+
+impl Lookup {
+    fn from_config(c: LookupConfig) -> Result<Self, Error> {
+        let templates = match (c.command, c.key, c.keys) {
+            (Command::Get | Command::Hgetall, Some(key), None) => vec![key],
+            (Command::Mget, None, Some(keys)) if !keys.is_empty() => keys,
+            (Command::Mget, _, _) => {
+                return Err(Error::ConfigFailedValidation(
+                    "mget lookups require a non-empty 'keys' list".into(),
+                ))
+            }
+            (command, _, _) => {
+                return Err(Error::ConfigFailedValidation(format!(
+                    "{} lookups require a single 'key'",
+                    command.name().to_lowercase()
+                )))
+            }
+        };
+
+        Ok(Lookup {
+            command: c.command,
+            keys: templates
+                .iter()
+                .map(|t| FieldTemplate::parse(t))
+                .collect::<Result<_, _>>()?,
+            target: c.target.as_deref().map(parse_path).transpose()?,
+        })
+    }
+}
+
+/// Convert a Redis value holding a string, decoding JSON when requested
+fn decode_value(value: String, parse_json: bool) -> JsonValue {
+    if parse_json {
+        if let Ok(v) = serde_json::from_str(&value) {
+            return v;
+        }
+    }
+    JsonValue::String(value)
+}
+
+/// Convert a command reply into JSON, where a missing key becomes `null`
+fn convert_reply(
+    command: Command,
+    reply: &redis::Value,
+    parse_json: bool,
+) -> Result<JsonValue, Error> {
+    let map_err = |e: redis::RedisError| {
+        Error::MessageFailed(format!("unexpected {} reply: {e}", command.name()))
+    };
+
+    let result = match command {
+        Command::Get => {
+            let value: Option<String> = redis::from_redis_value(reply).map_err(map_err)?;
+            value.map_or(JsonValue::Null, |v| decode_value(v, parse_json))
+        }
+        Command::Hgetall => {
+            let fields: HashMap<String, String> =
+                redis::from_redis_value(reply).map_err(map_err)?;
+            if fields.is_empty() {
+                JsonValue::Null
+            } else {
+                JsonValue::Object(
+                    fields
+                        .into_iter()
+                        .map(|(k, v)| (k, decode_value(v, parse_json)))
+                        .collect(),
+                )
+            }
+        }
+        Command::Mget => {
+            let values: Vec<Option<String>> = redis::from_redis_value(reply).map_err(map_err)?;
+            JsonValue::Array(
+                values
+                    .into_iter()
+                    .map(|v| v.map_or(JsonValue::Null, |v| decode_value(v, parse_json)))
+                    .collect(),
+            )
+        }
+    };
+
+    Ok(result)
+}
+
+/// Write a lookup result into the document, skipping missing values
+fn merge_result(
+    doc: &mut JsonValue,
+    target: Option<&[String]>,
+    result: JsonValue,
+) -> Result<(), Error> {
+    if result.is_null() {
+        return Ok(());
+    }
+
+    match target {
+        Some(path) => set_path(doc, path, result),
+        None => {
+            let (JsonValue::Object(root), JsonValue::Object(fields)) = (doc, result) else {
+                return Err(Error::MessageFailed(
+                    "lookups without a target require an object result and message".into(),
+                ));
+            };
+            root.extend(fields);
+            Ok(())
+        }
+    }
+}
+
+pub struct RedisLookup {
+    conn: ConnectionManager,
+    lookups: Vec<Lookup>,
+    parse_json: bool,
+    cache_ttl: Option<Duration>,
+    cache: Mutex<LruCache<String, (Instant, JsonValue)>>,
+}
+
+impl RedisLookup {
+    fn cached(&self, cache_key: &str) -> Result<Option<JsonValue>, Error> {
+        let Some(ttl) = self.cache_ttl else {
+            return Ok(None);
+        };
+        let mut cache = self
+            .cache
+            .lock()
+            .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+        Ok(cache
+            .get(cache_key)
+            .filter(|(inserted, _)| inserted.elapsed() < ttl)
+            .map(|(_, value)| value.clone()))
+    }
+
+    fn store(&self, cache_key: String, value: &JsonValue) -> Result<(), Error> {
+        if self.cache_ttl.is_none() {
+            return Ok(());
+        }
+        let mut cache = self
+            .cache
+            .lock()
+            .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+        cache.put(cache_key, (Instant::now(), value.clone()));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Processor for RedisLookup {
+    async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
+        let mut doc: JsonValue = serde_json::from_slice(&message.bytes)
+            .map_err(|e| Error::MessageFailed(format!("{e}")))?;
+
+        let mut results: Vec<Option<JsonValue>> = Vec::with_capacity(self.lookups.len());
+        let mut pending = Vec::new();
+        let mut pipe = redis::pipe();
+
+        for (i, lookup) in self.lookups.iter().enumerate() {
+            let keys = lookup
+                .keys
+                .iter()
+                .map(|t| t.render(&doc))
+                .collect::<Result<Vec<_>, _>>()?;
+            let cache_key = format!("{} {}", lookup.command.name(), keys.join("\0"));
+
+            let cached = self.cached(&cache_key)?;
+            if cached.is_none() {
+                pipe.cmd(lookup.command.name()).arg(&keys);
+                pending.push((i, cache_key));
+            }
+            results.push(cached);
+        }
+
+        if !pending.is_empty() {
+            debug!(lookups = pending.len(), "sending redis lookups");
+            let mut conn = self.conn.clone();
+            let replies: Vec<redis::Value> = pipe
+                .query_async(&mut conn)
+                .await
+                .map_err(|e| Error::MessageFailed(format!("Redis lookup failed: {e}")))?;
+
+            for ((i, cache_key), reply) in pending.into_iter().zip(replies.iter()) {
+                let value = convert_reply(self.lookups[i].command, reply, self.parse_json)?;
+                self.store(cache_key, &value)?;
+                results[i] = Some(value);
+            }
+        }
+
+        for (lookup, result) in self.lookups.iter().zip(results) {
+            merge_result(
+                &mut doc,
+                lookup.target.as_deref(),
+                result.unwrap_or(JsonValue::Null),
+            )?;
+        }
+
+        let mut message = message;
+        message.bytes =
+            serde_json::to_vec(&doc).map_err(|e| Error::ProcessingError(format!("{e}")))?;
+        Ok(vec![message])
+    }
+}
+
+impl Closer for RedisLookup {}
+
+#[fiddler_registration_func]
+fn create_redis_lookup(conf: Value) -> Result<ExecutionType, Error> {
+    let c: RedisLookupConfig = serde_yaml::from_value(conf)?;
+
+    if c.lookups.is_empty() {
+        return Err(Error::ConfigFailedValidation(
+            "at least one lookup is required".into(),
+        ));
+    }
+    let lookups = c
+        .lookups
+        .into_iter()
+        .map(Lookup::from_config)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let cache_size = NonZeroUsize::new(c.cache_size)
+        .ok_or_else(|| Error::ConfigFailedValidation("cache_size must be greater than 0".into()))?;
+
+    let conn = connection_manager(&c.url).await?;
+
+    Ok(ExecutionType::Processor(Box::new(RedisLookup {
+        conn,
+        lookups,
+        parse_json: c.parse_json,
+        cache_ttl: c.cache_ttl,
+        cache: Mutex::new(LruCache::new(cache_size)),
+    })))
+}
+
+pub(super) fn register_redis_lookup() -> Result<(), Error> {
+    let config = "type: object
+properties:
+  url:
+    type: string
+  lookups:
+    type: array
+    items:
+      type: object
+      properties:
+        command:
+          type: string
+          enum: [\"get\", \"hgetall\", \"mget\"]
+        key:
+          type: string
+        keys:
+          type: array
+          items:
+            type: string
+        target:
+          type: string
+  parse_json:
+    type: boolean
+  cache_ttl:
+    type: string
+  cache_size:
+    type: integer
+    minimum: 1
+required:
+  - url
+  - lookups";
+    let conf_spec = ConfigSpec::from_schema(config)?;
+
+    register_plugin(
+        "redis_lookup".into(),
+        ItemType::Processor,
+        conf_spec,
+        create_redis_lookup,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn register_plugin() {
+        register_redis_lookup().unwrap()
+    }
+
+    #[test]
+    fn lookup_validation() {
+        let valid = [
+            "key: a",
+            "command: hgetall\nkey: a",
+            "command: mget\nkeys: [a, b]",
+        ];
+        for conf in valid {
+            let c: LookupConfig = serde_yaml::from_str(conf).unwrap();
+            assert!(Lookup::from_config(c).is_ok(), "{conf}");
+        }
+
+        let invalid = [
+            "keys: [a]",
+            "command: mget\nkey: a",
+            "command: mget\nkeys: []",
+            "key: a\nkeys: [b]",
+            "key: a\ntarget: 'a..b'",
+            "key: 'user:${id'",
+        ];
+        for conf in invalid {
+            let c: LookupConfig = serde_yaml::from_str(conf).unwrap();
+            assert!(Lookup::from_config(c).is_err(), "{conf}");
+        }
+    }
+
+    #[test]
+    fn key_templates() {
+        let c: LookupConfig =
+            serde_yaml::from_str("command: mget\nkeys: ['geo:${ip}', 'user:${user.id}:${ip}']")
+                .unwrap();
+        let lookup = Lookup::from_config(c).unwrap();
+        let doc = json!({"ip": "10.0.0.1", "user": {"id": "a/b"}});
+        let keys: Vec<String> = lookup
+            .keys
+            .iter()
+            .map(|t| t.render(&doc).unwrap())
+            .collect();
+        assert_eq!(keys, vec!["geo:10.0.0.1", "user:a/b:10.0.0.1"]);
+
+        let err = lookup.keys[1]
+            .render(&json!({"ip": "10.0.0.1"}))
+            .unwrap_err();
+        assert!(matches!(err, Error::MessageFailed(_)));
+    }
+
+    #[test]
+    fn convert_replies() {
+        let get = redis::Value::BulkString(br#"{"name": "alice"}"#.to_vec());
+        assert_eq!(
+            convert_reply(Command::Get, &get, true).unwrap(),
+            json!({"name": "alice"})
+        );
+        assert_eq!(
+            convert_reply(Command::Get, &get, false).unwrap(),
+            json!(r#"{"name": "alice"}"#)
+        );
+        assert_eq!(
+            convert_reply(Command::Get, &redis::Value::Nil, true).unwrap(),
+            JsonValue::Null
+        );
+
+        let hash = redis::Value::Array(vec![
+            redis::Value::BulkString(b"role".to_vec()),
+            redis::Value::BulkString(b"admin".to_vec()),
+            redis::Value::BulkString(b"level".to_vec()),
+            redis::Value::BulkString(b"3".to_vec()),
+        ]);
+        assert_eq!(
+            convert_reply(Command::Hgetall, &hash, true).unwrap(),
+            json!({"role": "admin", "level": 3})
+        );
+        assert_eq!(
+            convert_reply(Command::Hgetall, &redis::Value::Array(vec![]), true).unwrap(),
+            JsonValue::Null
+        );
+
+        let mget = redis::Value::Array(vec![
+            redis::Value::BulkString(b"a".to_vec()),
+            redis::Value::Nil,
+        ]);
+        assert_eq!(
+            convert_reply(Command::Mget, &mget, true).unwrap(),
+            json!(["a", null])
+        );
+    }
+
+    #[test]
+    fn merge_results() {
+        let mut doc = json!({"id": 1});
+        merge_result(&mut doc, Some(&["user".into(), "name".into()]), json!("a")).unwrap();
+        merge_result(&mut doc, None, json!({"extra": true})).unwrap();
+        merge_result(&mut doc, Some(&["missing".into()]), JsonValue::Null).unwrap();
+        assert_eq!(doc, json!({"id": 1, "user": {"name": "a"}, "extra": true}));
+
+        let err = merge_result(&mut doc, None, json!("scalar")).unwrap_err();
+        assert!(matches!(err, Error::MessageFailed(_)));
+    }
+
+    #[tokio::test]
+    async fn invalid_config() {
+        for conf in [
+            "url: redis://localhost\nlookups: []",
+            "url: redis://localhost\nlookups: [{command: mget, key: a}]",
+            "url: redis://localhost\nlookups: [{key: a}]\ncache_size: 0",
+        ] {
+            let value: Value = serde_yaml::from_str(conf).unwrap();
+            assert!(create_redis_lookup(value).await.is_err(), "{conf}");
+        }
+    }
+}
@@ -0,0 +1,458 @@
+//! Sample processor for downsampling and throttling messages.
+//!
+//! Dropped messages are reported as filtered, so the input still acknowledges
+//! them.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - sample:
+//!       mode: random        # Optional: random, hash, rate_limit or reservoir (default: random)
+//!       rate: 0.1           # Required for random and hash: fraction of messages kept
+//!       key: "trace_id"     # Required for hash, optional for rate_limit: JMESPath expression
+//!       limit: 100          # Required for rate_limit and reservoir
+//!       interval: 1s        # Optional: rate_limit refill interval and reservoir interval
+//!       burst: 200          # Optional: rate_limit bucket capacity (default: limit)
+//! ```
+
+use super::path::{variable, Expression};
+use crate::config::register_plugin;
+use crate::config::ItemType;
+use crate::config::{ConfigSpec, ExecutionType};
+use crate::Message;
+use crate::MessageBatch;
+use crate::{Closer, Error, Processor};
+use async_trait::async_trait;
+use fiddler_macros::fiddler_registration_func;
+use lru::LruCache;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use serde_yaml::Value;
+use sha2::{Digest, Sha256};
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Number of rate limit buckets kept before the least recently used is evicted
+const MAX_BUCKETS: NonZeroUsize = NonZeroUsize::new(100_000).unwrap();
+
+fn default_interval() -> Duration {
+    Duration::from_secs(1)
+}
+
+/// Sampling strategy
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SampleMode {
+    /// Keep each message with probability `rate`
+    #[default]
+    Random,
+    /// Keep messages whose key hashes below `rate`, so equal keys share the decision
+    Hash,
+    /// Keep up to `limit` messages per `interval` for each key
+    RateLimit,
+    /// Keep a uniform sample of `limit` messages from each `interval`
+    Reservoir,
+}
+
+#[derive(Deserialize)]
+struct SampleConfig {
+    #[serde(default)]
+    mode: SampleMode,
+    rate: Option<f64>,
+    key: Option<String>,
+    limit: Option<usize>,
+    #[serde(
+        default = "default_interval",
+        deserialize_with = "crate::deserialize_duration"
+    )]
+    interval: Duration,
+    burst: Option<usize>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Reservoir {
+    started: Instant,
+    seen: usize,
+    samples: Vec<Message>,
+}
+
+enum Sampler {
+    Random {
+        rate: f64,
+    },
+    Hash {
+        rate: f64,
+        key: Expression,
+    },
+    RateLimit {
+        key: Option<Expression>,
+        /// Tokens added per second
+        refill: f64,
+        burst: f64,
+        buckets: Mutex<LruCache<String, Bucket>>,
+    },
+    Reservoir {
+        limit: usize,
+        interval: Duration,
+        reservoir: Mutex<Reservoir>,
+    },
+}
+
+pub struct Sample {
+    sampler: Sampler,
+}
+
+fn lock_err<T>(e: std::sync::PoisonError<T>) -> Error {
+    Error::ProcessingError(format!("{e}"))
+}
+
+/// Evaluate the key expression, using the raw value of string results
+fn sample_key(expression: &Expression, message: &Message) -> Result<String, Error> {
+    match expression.search(&variable(&message.bytes)?)? {
+        JsonValue::String(s) => Ok(s),
+        other => Ok(other.to_string()),
+    }
+}
+
+/// Map a key onto `[0, 1)` with a hash that is stable across instances and restarts
+fn hash_fraction(key: &str) -> f64 {
+    let digest = Sha256::digest(key.as_bytes());
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&digest[..8]);
+    (u64::from_be_bytes(buf) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Take a token from the bucket of `key`, returning `false` if it is empty
+fn take_token(
+    buckets: &Mutex<LruCache<String, Bucket>>,
+    key: String,
+    refill: f64,
+    burst: f64,
+) -> Result<bool, Error> {
+    let mut buckets = buckets.lock().map_err(lock_err)?;
+    let now = Instant::now();
+
+    let bucket = buckets.get_or_insert_mut(key, || Bucket {
+        tokens: burst,
+        updated: now,
+    });
+    bucket.tokens =
+        (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * refill).min(burst);
+    bucket.updated = now;
+
+    if bucket.tokens >= 1.0 {
+        bucket.tokens -= 1.0;
+        Ok(true)
+    } else {
+        Ok(false)
+    }
+}
+
+/// Algorithm R: keep each of the `seen` messages with equal probability
+fn add_to_reservoir(reservoir: &mut Reservoir, limit: usize, message: Message) {
+    reservoir.seen += 1;
+    if reservoir.samples.len() < limit {
+        reservoir.samples.push(message);
+    } else {
+        let j = fastrand::usize(..reservoir.seen);
+        if j < limit {
+            reservoir.samples[j] = message;
+        }
+    }
+}
+
+fn drain_reservoir(reservoir: &mut Reservoir) -> MessageBatch {
+    reservoir.started = Instant::now();
+    reservoir.seen = 0;
+    std::mem::take(&mut reservoir.samples)
+}
+
+#[async_trait]
+impl Processor for Sample {
+    async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
+        let keep = match &self.sampler {
+            Sampler::Random { rate } => fastrand::f64() < *rate,
+            Sampler::Hash { rate, key } => hash_fraction(&sample_key(key, &message)?) < *rate,
+            Sampler::RateLimit {
+                key,
+                refill,
+                burst,
+                buckets,
+            } => {
+                let k = match key {
+                    Some(expression) => sample_key(expression, &message)?,
+                    None => String::new(),
+                };
+                take_token(buckets, k, *refill, *burst)?
+            }
+            Sampler::Reservoir {
+                limit,
+                interval,
+                reservoir,
+            } => {
+                let mut reservoir = reservoir.lock().map_err(lock_err)?;
+                let completed = match reservoir.started.elapsed() >= *interval {
+                    true => drain_reservoir(&mut reservoir),
+                    false => Vec::new(),
+                };
+                add_to_reservoir(&mut reservoir, *limit, message);
+                return Ok(completed);
+            }
+        };
+
+        match keep {
+            true => Ok(vec![message]),
+            false => Ok(Vec::new()),
+        }
+    }
+
+    async fn flush(&self, shutdown: bool) -> Result<MessageBatch, Error> {
+        match &self.sampler {
+            Sampler::Reservoir {
+                interval,
+                reservoir,
+                ..
+            } => {
+                let mut reservoir = reservoir.lock().map_err(lock_err)?;
+                if shutdown || reservoir.started.elapsed() >= *interval {
+                    Ok(drain_reservoir(&mut reservoir))
+                } else {
+                    Ok(Vec::new())
+                }
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+}
+
+impl Closer for Sample {}
+
+fn require_rate(rate: Option<f64>) -> Result<f64, Error> {
+    match rate {
+        Some(r) if (0.0..=1.0).contains(&r) => Ok(r),
+        _ => Err(Error::ConfigFailedValidation(
+            "rate must be between 0 and 1".into(),
+        )),
+    }
+}
+
+fn require_limit(limit: Option<usize>) -> Result<usize, Error> {
+    match limit {
+        Some(l) if l > 0 => Ok(l),
+        _ => Err(Error::ConfigFailedValidation(
+            "limit must be greater than 0".into(),
+        )),
+    }
+}
+
+#[fiddler_registration_func]
+fn create_sample(conf: Value) -> Result<ExecutionType, Error> {
+    let c: SampleConfig = serde_yaml::from_value(conf)?;
+    let key = c.key.as_deref().map(Expression::compile).transpose()?;
+
+    if c.interval.is_zero() {
+        return Err(Error::ConfigFailedValidation(
+            "interval must be greater than 0".into(),
+        ));
+    }
+
+    let sampler = match c.mode {
+        SampleMode::Random => Sampler::Random {
+            rate: require_rate(c.rate)?,
+        },
+        SampleMode::Hash => Sampler::Hash {
+            rate: require_rate(c.rate)?,
+            key: key.ok_or_else(|| {
+                Error::ConfigFailedValidation("key is required with mode: hash".into())
+            })?,
+        },
+        SampleMode::RateLimit => {
+            let limit = require_limit(c.limit)?;
+            let burst = c.burst.unwrap_or(limit);
+            if burst == 0 {
+                return Err(Error::ConfigFailedValidation(
+                    "burst must be greater than 0".into(),
+                ));
+            }
+            Sampler::RateLimit {
+                key,
+                refill: limit as f64 / c.interval.as_secs_f64(),
+                burst: burst as f64,
+                buckets: Mutex::new(LruCache::new(MAX_BUCKETS)),
+            }
+        }
+        SampleMode::Reservoir => Sampler::Reservoir {
+            limit: require_limit(c.limit)?,
+            interval: c.interval,
+            reservoir: Mutex::new(Reservoir {
+                started: Instant::now(),
+                seen: 0,
+                samples: Vec::new(),
+            }),
+        },
+    };
+
+    Ok(ExecutionType::Processor(Box::new(Sample { sampler })))
+}
+
+pub(super) fn register_sample() -> Result<(), Error> {
+    let config = "type: object
+properties:
+  mode:
+    type: string
+    enum: [random, hash, rate_limit, reservoir]
+  rate:
+    type: number
+    minimum: 0
+    maximum: 1
+  key:
+    type: string
+  limit:
+    type: integer
+    minimum: 1
+  interval:
+    type: string
+  burst:
+    type: integer
+    minimum: 1";
+    let conf_spec = ConfigSpec::from_schema(config)?;
+
+    register_plugin(
+        "sample".into(),
+        ItemType::Processor,
+        conf_spec,
+        create_sample,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::modules::processors::test_util::{processor, text_message};
+
+    #[test]
+    fn register_plugin() {
+        register_sample().unwrap()
+    }
+
+    async fn kept(p: &(dyn Processor + Send + Sync), bodies: &[String]) -> usize {
+        let mut count = 0;
+        for body in bodies {
+            count += p.process(text_message(body)).await.unwrap().len();
+        }
+        count
+    }
+
+    #[tokio::test]
+    async fn random_bounds() {
+        let bodies: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+        let none = processor(create_sample, "rate: 0").await;
+        assert_eq!(kept(none.as_ref(), &bodies).await, 0);
+        let all = processor(create_sample, "rate: 1").await;
+        assert_eq!(kept(all.as_ref(), &bodies).await, 100);
+    }
+
+    #[tokio::test]
+    async fn random_rate() {
+        let p = processor(create_sample, "rate: 0.5").await;
+        let bodies: Vec<String> = (0..2000).map(|i| i.to_string()).collect();
+        let count = kept(p.as_ref(), &bodies).await;
+        assert!((700..1300).contains(&count), "kept {count}");
+    }
+
+    #[tokio::test]
+    async fn hash_is_deterministic() {
+        let p = processor(create_sample, "mode: hash\nrate: 0.5\nkey: trace").await;
+        for i in 0..50 {
+            let body = format!(r#"{{"trace": "t{i}"}}"#);
+            let first = p.process(text_message(&body)).await.unwrap().len();
+            let other = format!(r#"{{"trace": "t{i}", "span": 2}}"#);
+            let second = p.process(text_message(&other)).await.unwrap().len();
+            assert_eq!(first, second);
+        }
+        let bodies: Vec<String> = (0..1000)
+            .map(|i| format!(r#"{{"trace": "t{i}"}}"#))
+            .collect();
+        let count = kept(p.as_ref(), &bodies).await;
+        assert!((350..650).contains(&count), "kept {count}");
+    }
+
+    #[tokio::test]
+    async fn rate_limit_per_key() {
+        let p = processor(
+            create_sample,
+            "mode: rate_limit\nlimit: 2\ninterval: 1h\nkey: host",
+        )
+        .await;
+        let a: Vec<String> = (0..5).map(|_| r#"{"host": "a"}"#.to_string()).collect();
+        let b: Vec<String> = (0..5).map(|_| r#"{"host": "b"}"#.to_string()).collect();
+        assert_eq!(kept(p.as_ref(), &a).await, 2);
+        assert_eq!(kept(p.as_ref(), &b).await, 2);
+    }
+
+    #[tokio::test]
+    async fn rate_limit_refills() {
+        let p = processor(create_sample, "mode: rate_limit\nlimit: 1\ninterval: 50ms").await;
+        let bodies = vec!["x".to_string(), "x".to_string()];
+        assert_eq!(kept(p.as_ref(), &bodies).await, 1);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(kept(p.as_ref(), &bodies).await, 1);
+    }
+
+    #[test]
+    fn rate_limit_bucket_cap() {
+        let buckets = Mutex::new(LruCache::new(NonZeroUsize::new(2).unwrap()));
+        for key in ["a", "b", "a", "c"] {
+            take_token(&buckets, key.into(), 0.001, 1.0).unwrap();
+        }
+        {
+            let buckets = buckets.lock().unwrap();
+            assert_eq!(buckets.len(), 2);
+            // "b" was used least recently and made room for "c"
+            assert!(!buckets.contains("b"));
+        }
+        assert!(!take_token(&buckets, "a".into(), 0.001, 1.0).unwrap());
+        assert!(take_token(&buckets, "b".into(), 0.001, 1.0).unwrap());
+    }
+
+    #[tokio::test]
+    async fn reservoir_keeps_limit() {
+        let p = processor(create_sample, "mode: reservoir\nlimit: 3\ninterval: 1h").await;
+        let bodies: Vec<String> = (0..20).map(|i| i.to_string()).collect();
+        assert_eq!(kept(p.as_ref(), &bodies).await, 0);
+        assert!(p.flush(false).await.unwrap().is_empty());
+        let samples = p.flush(true).await.unwrap();
+        assert_eq!(samples.len(), 3);
+        assert!(p.flush(true).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reservoir_interval_elapsed() {
+        let p = processor(create_sample, "mode: reservoir\nlimit: 5\ninterval: 20ms").await;
+        let bodies: Vec<String> = (0..3).map(|i| i.to_string()).collect();
+        assert_eq!(kept(p.as_ref(), &bodies).await, 0);
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(p.flush(false).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn invalid_config() {
+        for conf in [
+            "{}",
+            "rate: 1.5",
+            "mode: hash\nrate: 0.5",
+            "mode: rate_limit",
+            "mode: reservoir\nlimit: 0",
+            "rate: 0.5\ninterval: 0s",
+        ] {
+            let value: Value = serde_yaml::from_str(conf).unwrap();
+            assert!(create_sample(value).await.is_err(), "{conf}");
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use crate::config::register_plugin;
-use crate::config::{parse_configuration_item, Item, ItemType};
 use crate::config::{ConfigSpec, ExecutionType};
+use crate::config::{Item, ItemType};
+use crate::modules::processors::{build_processors, flush_chain, run_chain};
 use crate::Message;
 use crate::MessageBatch;
 use crate::{Closer, Error, Processor};
@@ -14,11 +15,14 @@ struct CheckConfig {
     label: Option<String>,
     condition: String,
     processors: Vec<Item>,
+    #[serde(default)]
+    fallthrough: bool,
 }
 
 pub struct Check {
     condition: String,
     processors: Vec<Box<dyn Processor + Send + Sync>>,
+    pub(super) fallthrough: bool,
 }
 
 fn perform_check(condition: &str, json_str: String) -> Result<(), Error> {
@@ -45,24 +49,36 @@ fn perform_check(condition: &str, json_str: String) -> Result<(), Error> {
     }
 }
 
+impl Check {
+    /// Evaluate the condition against the message
+    pub(super) fn matches(&self, message: &Message) -> Result<bool, Error> {
+        let json_str = String::from_utf8(message.bytes.clone())
+            .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+
+        match perform_check(&self.condition, json_str) {
+            Ok(()) => Ok(true),
+            Err(Error::ConditionalCheckfailed) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Run the processors of the case without evaluating the condition
+    pub(super) async fn apply(&self, message: Message) -> Result<MessageBatch, Error> {
+        run_chain(&self.processors, vec![message]).await
+    }
+}
+
 #[async_trait]
 impl Processor for Check {
     async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
-        let mut messages = vec![message.clone()];
-        let json_str =
-            String::from_utf8(message.bytes).map_err(|e| Error::ProcessingError(format!("{e}")))?;
-
-        perform_check(&self.condition, json_str)?;
-
-        for p in &self.processors {
-            let mut new_messages = Vec::new();
-            for m in messages.drain(..) {
-                let processed = p.process(m).await?;
-                new_messages.extend(processed);
-            }
-            messages = new_messages;
+        if !self.matches(&message)? {
+            return Err(Error::ConditionalCheckfailed);
         }
-        Ok(messages)
+        self.apply(message).await
+    }
+
+    async fn flush(&self, shutdown: bool) -> Result<MessageBatch, Error> {
+        flush_chain(&self.processors, shutdown).await
     }
 }
 
@@ -76,26 +92,24 @@ impl Closer for Check {
     }
 }
 
-#[fiddler_registration_func]
-fn create_check(conf: Value) -> Result<ExecutionType, Error> {
-    let c: CheckConfig = serde_yaml::from_value(conf.clone())?;
+/// Build a check from its configuration, used directly by switch cases
+pub(super) async fn build_check(conf: Value) -> Result<Check, Error> {
+    let c: CheckConfig = serde_yaml::from_value(conf)?;
     let _ = jmespath::compile(&c.condition)
         .map_err(|e| Error::ConfigFailedValidation(format!("{e}")))?;
 
-    let mut steps = Vec::new();
-    for p in c.processors {
-        let ri = parse_configuration_item(ItemType::Processor, &p.extra).await?;
-        let proc = ((ri.creator)(ri.config.clone())).await?;
-        if let ExecutionType::Processor(rp) = proc {
-            steps.push(rp);
-        };
-    }
+    let steps = build_processors(c.processors).await?;
 
-    let s = Check {
+    Ok(Check {
         condition: c.condition,
         processors: steps,
-    };
+        fallthrough: c.fallthrough,
+    })
+}
 
+#[fiddler_registration_func]
+fn create_check(conf: Value) -> Result<ExecutionType, Error> {
+    let s = build_check(conf).await?;
     Ok(ExecutionType::Processor(Box::new(s)))
 }
 
@@ -107,7 +121,9 @@ properties:
   condition:
     type: string
   processors:
-    type: array";
+    type: array
+  fallthrough:
+    type: boolean";
     let conf_spec = ConfigSpec::from_schema(config)?;
 
     register_plugin("check".into(), ItemType::Processor, conf_spec, create_check)
@@ -1,38 +1,160 @@
+//! Switch processor for routing messages through conditional cases.
+//!
+//! Each case is a `check` with a JMESPath `condition` and its own processors.
+//! In `first_match` mode a message is handled by the first case whose
+//! condition passes, while `all_matching` applies every passing case in order,
+//! each seeing the output of the previous one.  A case with `fallthrough` also
+//! runs the processors of the next case without evaluating its condition.
+//! Messages matching no case go through the `default` processors, or pass
+//! through unchanged when none are given.  The cases may also be given as a
+//! plain list, which uses `first_match` without a default.  Messages held back
+//! by the processors of a case are flushed on to the cases after it.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - switch:
+//!       mode: all_matching           # Optional: first_match or all_matching (default: first_match)
+//!       cases:                       # Required: check cases evaluated in order
+//!         - check:
+//!             condition: "level == 'error'"
+//!             fallthrough: true      # Optional: also run the next case
+//!             processors:
+//!               - noop: {}
+//!       default:                     # Optional: processors for messages matching no case
+//!         - noop: {}
+//! ```
+
+use super::{build_processors, flush_chain, run_chain};
 use crate::config::register_plugin;
-use crate::config::{parse_configuration_item, Item, ItemType};
 use crate::config::{ConfigSpec, ExecutionType};
+use crate::config::{Item, ItemType};
 use crate::Message;
 use crate::MessageBatch;
 use crate::{Closer, Error, Processor};
 use async_trait::async_trait;
 use fiddler_macros::fiddler_registration_func;
+use serde::Deserialize;
 use serde_yaml::Value;
 mod check;
 
+#[derive(Clone, Copy, Default, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Mode {
+    #[default]
+    FirstMatch,
+    AllMatching,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SwitchOptions {
+    cases: Vec<Item>,
+    #[serde(default)]
+    default: Vec<Item>,
+    #[serde(default)]
+    mode: Mode,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SwitchConfig {
+    Cases(Vec<Item>),
+    Options(SwitchOptions),
+}
+
+/// A message along with whether it matched a case and whether the next case
+/// should run regardless of its condition
+struct Routed {
+    message: Message,
+    matched: bool,
+    fallthrough: bool,
+}
+
 pub struct Switch {
-    steps: Vec<Box<dyn Processor + Send + Sync>>,
+    cases: Vec<check::Check>,
+    default: Vec<Box<dyn Processor + Send + Sync>>,
+    mode: Mode,
+}
+
+impl Switch {
+    /// Route messages through the cases starting at `start`, then send those
+    /// that matched none of them through the default processors
+    async fn route(&self, mut routed: Vec<Routed>, start: usize) -> Result<MessageBatch, Error> {
+        for case in &self.cases[start..] {
+            let mut next = Vec::new();
+            for r in routed.drain(..) {
+                let settled = r.matched && !r.fallthrough && self.mode == Mode::FirstMatch;
+                if settled || !(r.fallthrough || case.matches(&r.message)?) {
+                    next.push(Routed {
+                        fallthrough: false,
+                        ..r
+                    });
+                    continue;
+                }
+                for message in case.apply(r.message).await? {
+                    next.push(Routed {
+                        message,
+                        matched: true,
+                        fallthrough: case.fallthrough,
+                    });
+                }
+            }
+            routed = next;
+        }
+
+        let mut messages = Vec::new();
+        for r in routed {
+            if r.matched {
+                messages.push(r.message);
+                continue;
+            }
+            messages.extend(run_chain(&self.default, vec![r.message]).await?);
+        }
+        Ok(messages)
+    }
 }
 
 #[async_trait]
 impl Processor for Switch {
     async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
-        'steps: for p in &self.steps {
-            match p.process(message.clone()).await {
-                Ok(m) => return Ok(m),
-                Err(e) => match e {
-                    Error::ConditionalCheckfailed => continue 'steps,
-                    _ => return Err(e),
-                },
-            };
+        let routed = vec![Routed {
+            message,
+            matched: false,
+            fallthrough: false,
+        }];
+        self.route(routed, 0).await
+    }
+
+    async fn flush(&self, shutdown: bool) -> Result<MessageBatch, Error> {
+        // Messages released by a case carry on as if that case had just matched
+        let mut messages = Vec::new();
+        for (i, case) in self.cases.iter().enumerate() {
+            let routed = case
+                .flush(shutdown)
+                .await?
+                .into_iter()
+                .map(|message| Routed {
+                    message,
+                    matched: true,
+                    fallthrough: case.fallthrough,
+                })
+                .collect();
+            messages.extend(self.route(routed, i + 1).await?);
         }
-        Ok(vec![message])
+        messages.extend(flush_chain(&self.default, shutdown).await?);
+        Ok(messages)
     }
 }
 
 #[async_trait]
 impl Closer for Switch {
     async fn close(&mut self) -> Result<(), Error> {
-        for p in &mut self.steps {
+        for c in &mut self.cases {
+            c.close().await?;
+        }
+        for p in &mut self.default {
             p.close().await?;
         }
         Ok(())
@@ -41,23 +163,63 @@ impl Closer for Switch {
 
 #[fiddler_registration_func]
 fn create_switch(conf: Value) -> Result<ExecutionType, Error> {
-    let c: Vec<Item> = serde_yaml::from_value(conf.clone())?;
-    let mut steps = Vec::new();
-    for p in c {
-        let ri = parse_configuration_item(ItemType::Processor, &p.extra).await?;
-        let proc = ((ri.creator)(ri.config)).await?;
-        if let ExecutionType::Processor(rp) = proc {
-            steps.push(rp);
-        };
+    let options = match serde_yaml::from_value(conf.clone())? {
+        SwitchConfig::Cases(cases) => SwitchOptions {
+            cases,
+            default: Vec::new(),
+            mode: Mode::FirstMatch,
+        },
+        SwitchConfig::Options(options) => options,
+    };
+
+    let mut cases = Vec::new();
+    for c in options.cases {
+        if c.extra.len() != 1 {
+            return Err(Error::ConfigFailedValidation(
+                "switch case must contain a single check".into(),
+            ));
+        }
+        let (name, conf) = c.extra.into_iter().next().unwrap_or_default();
+        if name != "check" {
+            return Err(Error::ConfigFailedValidation(format!(
+                "switch cases must be check processors, got '{name}'"
+            )));
+        }
+        cases.push(check::build_check(conf).await?);
     }
 
-    let s = Switch { steps };
+    let default = build_processors(options.default).await?;
+
+    let s = Switch {
+        cases,
+        default,
+        mode: options.mode,
+    };
 
     Ok(ExecutionType::Processor(Box::new(s)))
 }
 
 pub(super) fn register_switch() -> Result<(), Error> {
-    let config = "type: array";
+    let config = "oneOf:
+  - type: array
+    items:
+      type: object
+  - type: object
+    properties:
+      cases:
+        type: array
+        items:
+          type: object
+      default:
+        type: array
+        items:
+          type: object
+      mode:
+        type: string
+        enum: [\"first_match\", \"all_matching\"]
+    required:
+      - cases
+    additionalProperties: false";
 
     let conf_spec = ConfigSpec::from_schema(config)?;
 
@@ -73,9 +235,150 @@ pub(super) fn register_switch() -> Result<(), Error> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::modules::processors::test_util::{body, message, try_processor};
+    use serde_json::{json, Value as JsonValue};
+    use std::sync::Once;
+
+    static REGISTER: Once = Once::new();
+
+    /// Appends its configured name to the `seen` array of the body
+    struct Tag(String);
+
+    #[async_trait]
+    impl Processor for Tag {
+        async fn process(&self, mut message: Message) -> Result<MessageBatch, Error> {
+            let mut body: JsonValue = serde_json::from_slice(&message.bytes).unwrap();
+            match body.get_mut("seen") {
+                Some(JsonValue::Array(seen)) => seen.push(json!(self.0)),
+                _ => body["seen"] = json!([self.0]),
+            }
+            message.bytes = serde_json::to_vec(&body).unwrap();
+            Ok(vec![message])
+        }
+    }
+
+    impl Closer for Tag {}
+
+    #[fiddler_registration_func]
+    fn create_tag(conf: Value) -> Result<ExecutionType, Error> {
+        let name = conf["name"].as_str().unwrap_or_default().to_string();
+        Ok(ExecutionType::Processor(Box::new(Tag(name))))
+    }
+
+    async fn processor(conf: &str) -> Result<Box<dyn Processor + Send + Sync>, Error> {
+        REGISTER.call_once(|| {
+            crate::config::register_plugin(
+                "switch_test_tag".into(),
+                ItemType::Processor,
+                ConfigSpec::from_schema("type: object").unwrap(),
+                create_tag,
+            )
+            .unwrap()
+        });
+        try_processor(create_switch, conf).await
+    }
+
+    async fn seen(p: &(dyn Processor + Send + Sync), value: JsonValue) -> Vec<JsonValue> {
+        p.process(message(value))
+            .await
+            .unwrap()
+            .iter()
+            .map(|m| body(m)["seen"].clone())
+            .collect()
+    }
+
+    const CASES: &str = "
+  - check:
+      condition: \"level == 'error'\"
+      processors: [{switch_test_tag: {name: error}}]
+  - check:
+      condition: \"contains(seen || `[]`, 'error') || level == 'warn'\"
+      processors: [{switch_test_tag: {name: alert}}]";
 
     #[test]
     fn register_plugin() {
         register_switch().unwrap()
     }
+
+    #[tokio::test]
+    async fn first_match() {
+        let p = processor(CASES).await.unwrap();
+        assert_eq!(
+            seen(&*p, json!({"level": "error"})).await,
+            vec![json!(["error"])]
+        );
+        assert_eq!(
+            seen(&*p, json!({"level": "warn"})).await,
+            vec![json!(["alert"])]
+        );
+        assert_eq!(
+            seen(&*p, json!({"level": "info"})).await,
+            vec![JsonValue::Null]
+        );
+    }
+
+    #[tokio::test]
+    async fn all_matching_with_default() {
+        let p = processor(&format!(
+            "mode: all_matching\ndefault: [{{switch_test_tag: {{name: default}}}}]\ncases:{CASES}"
+        ))
+        .await
+        .unwrap();
+        assert_eq!(
+            seen(&*p, json!({"level": "error"})).await,
+            vec![json!(["error", "alert"])]
+        );
+        assert_eq!(
+            seen(&*p, json!({"level": "warn"})).await,
+            vec![json!(["alert"])]
+        );
+        assert_eq!(
+            seen(&*p, json!({"level": "info"})).await,
+            vec![json!(["default"])]
+        );
+    }
+
+    #[tokio::test]
+    async fn fallthrough() {
+        let p = processor(
+            "cases:
+  - check:
+      condition: \"level == 'error'\"
+      fallthrough: true
+      processors: [{switch_test_tag: {name: error}}]
+  - check:
+      condition: \"level == 'warn'\"
+      processors: [{switch_test_tag: {name: warn}}]
+  - check:
+      condition: '`true`'
+      processors: [{switch_test_tag: {name: any}}]",
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            seen(&*p, json!({"level": "error"})).await,
+            vec![json!(["error", "warn"])]
+        );
+        assert_eq!(
+            seen(&*p, json!({"level": "warn"})).await,
+            vec![json!(["warn"])]
+        );
+        assert_eq!(
+            seen(&*p, json!({"level": "info"})).await,
+            vec![json!(["any"])]
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_config() {
+        for conf in [
+            "- switch_test_tag: {name: a}",
+            "- check: {condition: 'true', processors: []}\n  switch_test_tag: {}",
+            "cases: []\nmode: every",
+            "cases: []\nfallback: []",
+            "- check: {condition: 'a[[', processors: []}",
+        ] {
+            assert!(processor(conf).await.is_err(), "{conf}");
+        }
+    }
 }
@@ -0,0 +1,394 @@
+//! Template processor for building messages from handlebars templates.
+//!
+//! The template is given inline or read from a file, and is rendered with the
+//! parsed JSON body under `body` and the message metadata under `metadata`.
+//! A body that is not JSON is available as a string.  The output replaces the
+//! message, or is written to a string field when `target` is set.  Values are
+//! rendered without HTML escaping; use the `json` helper to embed values in
+//! JSON payloads.
+//!
+//! Configuration files are themselves rendered with handlebars for variable
+//! substitution, so inline templates must be wrapped in a `{{{{raw}}}}` block
+//! or escape each expression as `\{{`.  Templates read from a file are not
+//! affected.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - template:
+//!       template: |                   # Required unless template_path is set
+//!         {{{{raw}}}}{"text": {{json (default body.message "no message")}}}{{{{/raw}}}}
+//!       template_path: "alert.hbs"    # Optional: file holding the template
+//!       target: "text"                # Optional: field for the rendered output
+//!       strict: false                 # Optional: fail on missing fields (default: false)
+//! ```
+//!
+//! # Helpers
+//!
+//! - `{{json value pretty=false}}` encodes a value as JSON
+//! - `{{date value format="%Y-%m-%dT%H:%M:%S%:z" timezone="UTC"}}` formats an
+//!   RFC 3339 string or unix seconds, or the current time without a value
+//! - `{{default value fallback}}` renders the fallback when the value is
+//!   missing, null or an empty string
+
+use super::path::{parse_path, set_path};
+use crate::config::register_plugin;
+use crate::config::ItemType;
+use crate::config::{ConfigSpec, ExecutionType};
+use crate::Message;
+use crate::MessageBatch;
+use crate::{Closer, Error, Processor};
+use async_trait::async_trait;
+use fiddler_macros::fiddler_registration_func;
+use handlebars::{
+    no_escape, Context, Handlebars, Helper, HelperResult, Output, RenderContext, RenderError,
+    RenderErrorReason,
+};
+use jiff::tz::TimeZone;
+use jiff::Timestamp;
+use serde::Deserialize;
+use serde_json::{json, Value as JsonValue};
+use serde_yaml::Value;
+
+const TEMPLATE_NAME: &str = "template";
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%:z";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TemplateConfig {
+    template: Option<String>,
+    template_path: Option<String>,
+    target: Option<String>,
+    #[serde(default)]
+    strict: bool,
+}
+
+pub struct Template {
+    registry: Handlebars<'static>,
+    target: Option<Vec<String>>,
+}
+
+fn render_error(message: String) -> RenderError {
+    RenderErrorReason::Other(message).into()
+}
+
+/// Encode the parameter as JSON
+fn json_helper(
+    h: &Helper<'_>,
+    _: &Handlebars<'_>,
+    _: &Context,
+    _: &mut RenderContext<'_, '_>,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let value = h.param(0).map(|p| p.value()).unwrap_or(&JsonValue::Null);
+    let pretty = h.hash_get("pretty").and_then(|p| p.value().as_bool()) == Some(true);
+    let encoded = if pretty {
+        serde_json::to_string_pretty(value)
+    } else {
+        serde_json::to_string(value)
+    }
+    .map_err(|e| render_error(format!("{e}")))?;
+    out.write(&encoded)?;
+    Ok(())
+}
+
+/// Format an RFC 3339 string or unix seconds, defaulting to the current time
+fn date_helper(
+    h: &Helper<'_>,
+    _: &Handlebars<'_>,
+    _: &Context,
+    _: &mut RenderContext<'_, '_>,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let timestamp = match h.param(0).map(|p| p.value()) {
+        None => Timestamp::now(),
+        Some(JsonValue::String(s)) => s
+            .parse::<Timestamp>()
+            .map_err(|e| render_error(format!("invalid date '{s}': {e}")))?,
+        Some(JsonValue::Number(n)) => match (n.as_i64(), n.as_f64()) {
+            (Some(seconds), _) => Timestamp::from_second(seconds),
+            (None, Some(seconds)) => Timestamp::from_millisecond((seconds * 1000.0) as i64),
+            _ => return Err(render_error(format!("invalid date {n}"))),
+        }
+        .map_err(|e| render_error(format!("{e}")))?,
+        Some(other) => return Err(render_error(format!("invalid date {other}"))),
+    };
+
+    let format = h
+        .hash_get("format")
+        .and_then(|p| p.value().as_str())
+        .unwrap_or(DEFAULT_DATE_FORMAT);
+    let tz = match h.hash_get("timezone").and_then(|p| p.value().as_str()) {
+        Some(name) => TimeZone::get(name).map_err(|e| render_error(format!("{e}")))?,
+        None => TimeZone::UTC,
+    };
+
+    let formatted = jiff::fmt::strtime::format(format, &timestamp.to_zoned(tz))
+        .map_err(|e| render_error(format!("{e}")))?;
+    out.write(&formatted)?;
+    Ok(())
+}
+
+/// Render the fallback when the value is missing, null or empty
+fn default_helper(
+    h: &Helper<'_>,
+    _: &Handlebars<'_>,
+    _: &Context,
+    _: &mut RenderContext<'_, '_>,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let value = match h.param(0).map(|p| p.value()) {
+        None | Some(JsonValue::Null) => h.param(1).map(|p| p.value()),
+        Some(JsonValue::String(s)) if s.is_empty() => h.param(1).map(|p| p.value()),
+        value => value,
+    };
+    match value {
+        Some(JsonValue::String(s)) => out.write(s)?,
+        Some(JsonValue::Null) | None => {}
+        Some(other) => out.write(&other.to_string())?,
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl Processor for Template {
+    async fn process(&self, mut message: Message) -> Result<MessageBatch, Error> {
+        let parsed = serde_json::from_slice(&message.bytes);
+        if let (Some(_), Err(e)) = (&self.target, &parsed) {
+            return Err(Error::MessageFailed(format!(
+                "target requires a JSON body: {e}"
+            )));
+        }
+        let body = parsed
+            .unwrap_or_else(|_| JsonValue::String(String::from_utf8_lossy(&message.bytes).into()));
+        let metadata = serde_json::to_value(&message.metadata)
+            .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+
+        let mut context = json!({"body": body, "metadata": metadata});
+        let rendered = self
+            .registry
+            .render(TEMPLATE_NAME, &context)
+            .map_err(|e| Error::MessageFailed(format!("{e}")))?;
+
+        message.bytes = match &self.target {
+            Some(target) => {
+                let mut doc = context["body"].take();
+                set_path(&mut doc, target, JsonValue::String(rendered))?;
+                serde_json::to_vec(&doc).map_err(|e| Error::ProcessingError(format!("{e}")))?
+            }
+            None => rendered.into_bytes(),
+        };
+        Ok(vec![message])
+    }
+}
+
+impl Closer for Template {}
+
+#[fiddler_registration_func]
+fn create_template(conf: Value) -> Result<ExecutionType, Error> {
+    let c: TemplateConfig = serde_yaml::from_value(conf)?;
+
+    let source = match (c.template, &c.template_path) {
+        (Some(template), None) => template,
+        (None, Some(path)) => std::fs::read_to_string(path).map_err(|e| {
+            Error::ConfigFailedValidation(format!("unable to read template {path}: {e}"))
+        })?,
+        _ => {
+            return Err(Error::ConfigFailedValidation(
+                "exactly one of template or template_path is required".into(),
+            ))
+        }
+    };
+
+    let mut registry = Handlebars::new();
+    registry.set_strict_mode(c.strict);
+    registry.register_escape_fn(no_escape);
+    registry.register_helper("json", Box::new(json_helper));
+    registry.register_helper("date", Box::new(date_helper));
+    registry.register_helper("default", Box::new(default_helper));
+    registry
+        .register_template_string(TEMPLATE_NAME, source)
+        .map_err(|e| Error::ConfigFailedValidation(format!("{e}")))?;
+
+    Ok(ExecutionType::Processor(Box::new(Template {
+        registry,
+        target: c.target.as_deref().map(parse_path).transpose()?,
+    })))
+}
+
+pub(super) fn register_template() -> Result<(), Error> {
+    let config = "type: object
+properties:
+  template:
+    type: string
+  template_path:
+    type: string
+  target:
+    type: string
+  strict:
+    type: boolean
+additionalProperties: false";
+    let conf_spec = ConfigSpec::from_schema(config)?;
+
+    register_plugin(
+        "template".into(),
+        ItemType::Processor,
+        conf_spec,
+        create_template,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::Config;
+    use crate::modules::processors::test_util::try_processor;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    #[test]
+    fn register_plugin() {
+        register_template().unwrap()
+    }
+
+    async fn render(conf: &str, body: &str) -> Result<String, Error> {
+        let p = try_processor(create_template, conf).await?;
+        let message = Message {
+            bytes: body.as_bytes().to_vec(),
+            metadata: HashMap::from([("source".into(), Value::String("syslog".into()))]),
+            ..Default::default()
+        };
+        let output = p.process(message).await?;
+        Ok(String::from_utf8(output[0].bytes.clone()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn webhook_body() {
+        let output = render(
+            r#"template: '{"text": {{json body.message}}, "from": "{{metadata.source}}", "tags": {{json body.tags}}}'"#,
+            r#"{"message": "disk <full> \"/var\"", "tags": ["a"]}"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            serde_json::from_str::<JsonValue>(&output).unwrap(),
+            json!({"text": "disk <full> \"/var\"", "from": "syslog", "tags": ["a"]})
+        );
+    }
+
+    #[tokio::test]
+    async fn helpers() {
+        for (template, body, expected) in [
+            ("{{default body.name 'unknown'}}", "{}", "unknown"),
+            (
+                "{{default body.name 'unknown'}}",
+                r#"{"name": ""}"#,
+                "unknown",
+            ),
+            ("{{default body.count 1}}", r#"{"count": 0}"#, "0"),
+            (
+                "{{date body.ts}}",
+                r#"{"ts": 1700000000}"#,
+                "2023-11-14T22:13:20+00:00",
+            ),
+            (
+                "{{date body.ts timezone='Europe/Paris'}}",
+                r#"{"ts": 1700000000}"#,
+                "2023-11-14T23:13:20+01:00",
+            ),
+            (
+                "{{date body.ts format='%Y-%m-%d %H:%M %Z' timezone='America/New_York'}}",
+                r#"{"ts": "2023-11-14T22:13:20Z"}"#,
+                "2023-11-14 17:13 EST",
+            ),
+            (
+                "{{json body pretty=true}}",
+                r#"{"a": 1}"#,
+                "{\n  \"a\": 1\n}",
+            ),
+            ("{{body}}!", "plain text", "plain text!"),
+        ] {
+            let conf = format!("template: \"{template}\"");
+            assert_eq!(render(&conf, body).await.unwrap(), expected, "{template}");
+        }
+        assert!(!render("template: '{{date}}'", "{}")
+            .await
+            .unwrap()
+            .is_empty());
+        let result = render("template: '{{date body}}'", "{}").await;
+        assert!(matches!(result, Err(Error::MessageFailed(_))), "{result:?}");
+    }
+
+    #[tokio::test]
+    async fn target_and_strict() {
+        let output = render(
+            "template: '{{body.host}} is down'\ntarget: alert.text",
+            r#"{"host": "web1"}"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            serde_json::from_str::<JsonValue>(&output).unwrap(),
+            json!({"host": "web1", "alert": {"text": "web1 is down"}})
+        );
+
+        assert_eq!(
+            render("template: '[{{body.missing}}]'", "{}")
+                .await
+                .unwrap(),
+            "[]"
+        );
+        let result = render("template: '[{{body.missing}}]'\nstrict: true", "{}").await;
+        assert!(matches!(result, Err(Error::MessageFailed(_))), "{result:?}");
+
+        let result = render("template: 'down'\ntarget: alert.text", "plain text").await;
+        assert!(matches!(result, Err(Error::MessageFailed(_))), "{result:?}");
+    }
+
+    #[tokio::test]
+    async fn template_path() {
+        let path =
+            std::env::temp_dir().join(format!("fiddler_template_{}.hbs", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "Hello {{body.name}}").unwrap();
+        let conf = format!("template_path: {}", path.display());
+        assert_eq!(
+            render(&conf, r#"{"name": "World"}"#).await.unwrap(),
+            "Hello World"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn survives_config_substitution() {
+        let conf = Config::from_str(
+            "input:
+  stdin: {}
+processors:
+  - template:
+      template: '{{{{raw}}}}{{body.a}}{{{{/raw}}}} \\{{body.b}}'
+output:
+  stdout: {}",
+        )
+        .unwrap();
+        let template = &conf.processors[0].extra["template"]["template"];
+        assert_eq!(template.as_str(), Some("{{body.a}} {{body.b}}"));
+    }
+
+    #[tokio::test]
+    async fn invalid_config() {
+        for conf in [
+            "strict: true",
+            "template: 'a'\ntemplate_path: b.hbs",
+            "template_path: /nonexistent/template.hbs",
+            "template: '{{#if body}}'",
+            "template: 'a'\ntarget: 'a..b'",
+            "template: 'a'\nescape: html",
+        ] {
+            assert!(
+                try_processor(create_template, conf).await.is_err(),
+                "{conf}"
+            );
+        }
+    }
+}
@@ -0,0 +1,43 @@
+//! Fixtures shared by the processor unit tests.
+
+use crate::config::{Callback, ExecutionType};
+use crate::{Error, Message, Processor};
+use serde_json::Value as JsonValue;
+
+/// Create a processor from its YAML configuration with the given registration function
+pub(crate) async fn try_processor(
+    create: Callback,
+    conf: &str,
+) -> Result<Box<dyn Processor + Send + Sync>, Error> {
+    let value: serde_yaml::Value = serde_yaml::from_str(conf).unwrap();
+    match create(value).await? {
+        ExecutionType::Processor(p) => Ok(p),
+        _ => panic!("expected processor"),
+    }
+}
+
+/// Create a processor from a configuration expected to be valid
+pub(crate) async fn processor(create: Callback, conf: &str) -> Box<dyn Processor + Send + Sync> {
+    try_processor(create, conf).await.unwrap()
+}
+
+/// Message holding the JSON encoded value
+pub(crate) fn message(body: JsonValue) -> Message {
+    Message {
+        bytes: serde_json::to_vec(&body).unwrap(),
+        ..Default::default()
+    }
+}
+
+/// Message holding the text as-is, for bodies that are not valid JSON
+pub(crate) fn text_message(body: &str) -> Message {
+    Message {
+        bytes: body.as_bytes().to_vec(),
+        ..Default::default()
+    }
+}
+
+/// Decode the JSON body of a message
+pub(crate) fn body(message: &Message) -> JsonValue {
+    serde_json::from_slice(&message.bytes).unwrap()
+}
@@ -0,0 +1,519 @@
+//! Timestamp processor for parsing and normalizing event times.
+//!
+//! The source field is parsed with each configured format in order until one
+//! succeeds.  Formats without an offset, such as RFC 3164 syslog dates or
+//! custom strftime patterns lacking `%z`, are interpreted in `timezone`.
+//! RFC 3164 dates carry no year, so the current year is assumed unless that
+//! places the event more than a week in the future.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - timestamp:
+//!       field: "time"                    # Optional: source field (default: timestamp)
+//!       formats:                         # Optional: tried in order (default: [rfc3339])
+//!         - rfc3339
+//!         - unix_ms
+//!         - rfc3164
+//!         - "%d/%b/%Y:%H:%M:%S %z"
+//!       timezone: "America/New_York"     # Optional: IANA zone for formats without an offset (default: UTC)
+//!       target: "@timestamp"             # Optional: output field (default: field)
+//!       output_format: rfc3339           # Optional: rfc3339, unix, unix_ms, unix_us or unix_ns
+//!       metadata_key: "event_time"       # Optional: also write the result to metadata
+//!       on_failure: ingest_time          # Optional: fail, ingest_time or skip (default: fail)
+//! ```
+
+use crate::config::register_plugin;
+use crate::config::ItemType;
+use crate::config::{ConfigSpec, ExecutionType};
+use crate::modules::processors::path::{get_path, parse_path, set_path};
+use crate::Message;
+use crate::MessageBatch;
+use crate::{Closer, Error, Processor};
+use async_trait::async_trait;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Datelike, NaiveDateTime, SecondsFormat, TimeDelta, Timelike, Utc};
+use fiddler_macros::fiddler_registration_func;
+use jiff::tz::TimeZone;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use serde_yaml::Value;
+
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum OutputFormat {
+    #[default]
+    Rfc3339,
+    Unix,
+    #[serde(rename = "unix_ms")]
+    UnixMs,
+    #[serde(rename = "unix_us")]
+    UnixUs,
+    #[serde(rename = "unix_ns")]
+    UnixNs,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum OnFailure {
+    #[default]
+    Fail,
+    IngestTime,
+    Skip,
+}
+
+#[derive(Deserialize)]
+struct TimestampConfig {
+    #[serde(default = "default_field")]
+    field: String,
+    #[serde(default = "default_formats")]
+    formats: Vec<String>,
+    timezone: Option<String>,
+    target: Option<String>,
+    #[serde(default)]
+    output_format: OutputFormat,
+    metadata_key: Option<String>,
+    #[serde(default)]
+    on_failure: OnFailure,
+}
+
+fn default_field() -> String {
+    "timestamp".into()
+}
+
+fn default_formats() -> Vec<String> {
+    vec!["rfc3339".into()]
+}
+
+#[derive(Debug, PartialEq)]
+enum Format {
+    Rfc3339,
+    Rfc2822,
+    Rfc3164,
+    /// Epoch time, with the number of units per second
+    Epoch(i64),
+    Custom(String),
+}
+
+impl Format {
+    fn from_name(name: &str) -> Result<Self, Error> {
+        Ok(match name {
+            "rfc3339" => Format::Rfc3339,
+            "rfc2822" => Format::Rfc2822,
+            "rfc3164" => Format::Rfc3164,
+            "unix" => Format::Epoch(1),
+            "unix_ms" => Format::Epoch(1_000),
+            "unix_us" => Format::Epoch(1_000_000),
+            "unix_ns" => Format::Epoch(1_000_000_000),
+            // Anything else is a strftime pattern, so catch misspelled names early
+            other => {
+                if !other.contains('%')
+                    || StrftimeItems::new(other).any(|item| matches!(item, Item::Error))
+                {
+                    return Err(Error::ConfigFailedValidation(format!(
+                        "'{other}' is neither a known format nor a valid strftime pattern"
+                    )));
+                }
+                Format::Custom(other.to_string())
+            }
+        })
+    }
+}
+
+pub struct Timestamp {
+    field: Vec<String>,
+    formats: Vec<Format>,
+    timezone: TimeZone,
+    target: Vec<String>,
+    output_format: OutputFormat,
+    metadata_key: Option<String>,
+    on_failure: OnFailure,
+}
+
+/// Convert a wall clock time in `tz` to UTC, picking the earlier time when ambiguous
+fn to_utc(tz: &TimeZone, naive: NaiveDateTime) -> Option<DateTime<Utc>> {
+    let civil = jiff::civil::DateTime::new(
+        i16::try_from(naive.year()).ok()?,
+        naive.month() as i8,
+        naive.day() as i8,
+        naive.hour() as i8,
+        naive.minute() as i8,
+        naive.second() as i8,
+        naive.nanosecond().min(999_999_999) as i32,
+    )
+    .ok()?;
+    let ts = tz.to_ambiguous_timestamp(civil).compatible().ok()?;
+    DateTime::from_timestamp(ts.as_second(), ts.subsec_nanosecond() as u32)
+}
+
+fn parse_epoch(value: &JsonValue, scale: i64) -> Option<DateTime<Utc>> {
+    let (whole, frac) = match value {
+        JsonValue::Number(n) => match n.as_i64() {
+            Some(i) => (i, 0.0),
+            None => {
+                let f = n.as_f64()?;
+                (f.trunc() as i64, f.fract())
+            }
+        },
+        JsonValue::String(s) => match s.trim().parse::<i64>() {
+            Ok(i) => (i, 0.0),
+            Err(_) => {
+                let f = s.trim().parse::<f64>().ok()?;
+                (f.trunc() as i64, f.fract())
+            }
+        },
+        _ => return None,
+    };
+
+    let nanos_per_unit = 1_000_000_000 / scale;
+    let secs = whole.div_euclid(scale);
+    let nanos = whole.rem_euclid(scale) * nanos_per_unit + (frac * nanos_per_unit as f64) as i64;
+    DateTime::from_timestamp(secs, 0)?.checked_add_signed(TimeDelta::nanoseconds(nanos))
+}
+
+impl Timestamp {
+    fn parse_rfc3164(&self, s: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        // Days are space padded, e.g. "Oct  1 22:14:15"
+        let normalized = s.split_whitespace().collect::<Vec<_>>().join(" ");
+        let year = i32::from(
+            jiff::Timestamp::from_second(now.timestamp())
+                .ok()?
+                .to_zoned(self.timezone.clone())
+                .year(),
+        );
+        for year in [year, year - 1] {
+            // Feb 29 only exists in one of the two years
+            let Ok(naive) =
+                NaiveDateTime::parse_from_str(&format!("{year} {normalized}"), "%Y %b %d %H:%M:%S")
+            else {
+                continue;
+            };
+            let Some(parsed) = to_utc(&self.timezone, naive) else {
+                continue;
+            };
+            if parsed <= now + TimeDelta::days(7) {
+                return Some(parsed);
+            }
+        }
+        None
+    }
+
+    fn parse_with(
+        &self,
+        format: &Format,
+        value: &JsonValue,
+        now: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        if let Format::Epoch(scale) = format {
+            return parse_epoch(value, *scale);
+        }
+
+        let s = match value {
+            JsonValue::String(s) => s.trim().to_string(),
+            JsonValue::Number(n) => n.to_string(),
+            _ => return None,
+        };
+
+        match format {
+            Format::Rfc3339 => DateTime::parse_from_rfc3339(&s)
+                .ok()
+                .map(|t| t.with_timezone(&Utc)),
+            Format::Rfc2822 => DateTime::parse_from_rfc2822(&s)
+                .ok()
+                .map(|t| t.with_timezone(&Utc)),
+            Format::Rfc3164 => self.parse_rfc3164(&s, now),
+            Format::Custom(pattern) => match DateTime::parse_from_str(&s, pattern) {
+                Ok(t) => Some(t.with_timezone(&Utc)),
+                Err(_) => NaiveDateTime::parse_from_str(&s, pattern)
+                    .ok()
+                    .and_then(|naive| to_utc(&self.timezone, naive)),
+            },
+            Format::Epoch(_) => None,
+        }
+    }
+
+    fn parse(&self, value: &JsonValue, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.formats
+            .iter()
+            .find_map(|format| self.parse_with(format, value, now))
+    }
+
+    fn format(&self, time: DateTime<Utc>) -> JsonValue {
+        match self.output_format {
+            OutputFormat::Rfc3339 => {
+                JsonValue::String(time.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+            }
+            OutputFormat::Unix => time.timestamp().into(),
+            OutputFormat::UnixMs => time.timestamp_millis().into(),
+            OutputFormat::UnixUs => time.timestamp_micros().into(),
+            OutputFormat::UnixNs => match time.timestamp_nanos_opt() {
+                Some(n) => n.into(),
+                None => JsonValue::Null,
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl Processor for Timestamp {
+    async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
+        let mut doc: JsonValue = serde_json::from_slice(&message.bytes)
+            .map_err(|e| Error::MessageFailed(format!("{e}")))?;
+
+        let now = Utc::now();
+        let parsed = match get_path(&doc, &self.field) {
+            Some(value) => self.parse(value, now),
+            None => None,
+        };
+
+        let time = match (parsed, self.on_failure) {
+            (Some(time), _) => time,
+            (None, OnFailure::IngestTime) => now,
+            (None, OnFailure::Skip) => return Ok(vec![message]),
+            (None, OnFailure::Fail) => {
+                return Err(Error::MessageFailed(format!(
+                    "unable to parse timestamp from '{}'",
+                    self.field.join(".")
+                )))
+            }
+        };
+
+        let output = self.format(time);
+        let mut message = message;
+        if let Some(key) = &self.metadata_key {
+            let value: Value = serde_yaml::to_value(&output)
+                .map_err(|e| Error::ProcessingError(format!("{e}")))?;
+            message.metadata.insert(key.clone(), value);
+        }
+
+        set_path(&mut doc, &self.target, output)?;
+        message.bytes =
+            serde_json::to_vec(&doc).map_err(|e| Error::ProcessingError(format!("{e}")))?;
+
+        Ok(vec![message])
+    }
+}
+
+impl Closer for Timestamp {}
+
+#[fiddler_registration_func]
+fn create_timestamp(conf: Value) -> Result<ExecutionType, Error> {
+    let c: TimestampConfig = serde_yaml::from_value(conf)?;
+
+    if c.formats.is_empty() {
+        return Err(Error::ConfigFailedValidation(
+            "at least one format is required".into(),
+        ));
+    }
+
+    let timezone = match &c.timezone {
+        Some(name) => TimeZone::get(name).map_err(|e| {
+            Error::ConfigFailedValidation(format!("unknown timezone '{name}': {e}"))
+        })?,
+        None => TimeZone::UTC,
+    };
+
+    let formats = c
+        .formats
+        .iter()
+        .map(|f| Format::from_name(f))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    let field = parse_path(&c.field)?;
+    let target = match &c.target {
+        Some(target) => parse_path(target)?,
+        None => field.clone(),
+    };
+
+    Ok(ExecutionType::Processor(Box::new(Timestamp {
+        field,
+        formats,
+        timezone,
+        target,
+        output_format: c.output_format,
+        metadata_key: c.metadata_key,
+        on_failure: c.on_failure,
+    })))
+}
+
+pub(super) fn register_timestamp() -> Result<(), Error> {
+    let config = "type: object
+properties:
+  field:
+    type: string
+  formats:
+    type: array
+    items:
+      type: string
+  timezone:
+    type: string
+  target:
+    type: string
+  output_format:
+    type: string
+    enum: [\"rfc3339\", \"unix\", \"unix_ms\", \"unix_us\", \"unix_ns\"]
+  metadata_key:
+    type: string
+  on_failure:
+    type: string
+    enum: [\"fail\", \"ingest_time\", \"skip\"]";
+    let conf_spec = ConfigSpec::from_schema(config)?;
+
+    register_plugin(
+        "timestamp".into(),
+        ItemType::Processor,
+        conf_spec,
+        create_timestamp,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::modules::processors::test_util::{body, message, processor, text_message};
+    use serde_json::json;
+
+    #[test]
+    fn register_plugin() {
+        register_timestamp().unwrap()
+    }
+
+    async fn run(p: &(dyn Processor + Send + Sync), value: JsonValue) -> Message {
+        p.process(message(value)).await.unwrap().remove(0)
+    }
+
+    #[tokio::test]
+    async fn ordered_formats() {
+        let p = processor(
+            create_timestamp,
+            "formats: [rfc3339, unix_ms, '%d/%b/%Y:%H:%M:%S %z', '%Y-%m-%d %H:%M:%S']",
+        )
+        .await;
+
+        for (input, expected) in [
+            (json!("2024-03-01T12:00:00+02:00"), "2024-03-01T10:00:00Z"),
+            (json!(1709294400123_i64), "2024-03-01T12:00:00.123Z"),
+            (json!("1709294400000"), "2024-03-01T12:00:00Z"),
+            (json!("01/Mar/2024:12:00:00 -0500"), "2024-03-01T17:00:00Z"),
+            (json!("2024-03-01 12:00:00"), "2024-03-01T12:00:00Z"),
+        ] {
+            let result = run(p.as_ref(), json!({"timestamp": input})).await;
+            assert_eq!(body(&result)["timestamp"], json!(expected), "{input}");
+        }
+    }
+
+    #[tokio::test]
+    async fn default_timezone() {
+        let p = processor(
+            create_timestamp,
+            "field: time
+formats: ['%Y-%m-%d %H:%M:%S']
+timezone: America/New_York
+target: '@timestamp'
+output_format: unix",
+        )
+        .await;
+
+        // EST in winter, EDT in summer
+        let result = run(p.as_ref(), json!({"time": "2024-01-15 12:00:00"})).await;
+        assert_eq!(body(&result)["@timestamp"], json!(1705338000));
+        let result = run(p.as_ref(), json!({"time": "2024-07-15 12:00:00"})).await;
+        assert_eq!(body(&result)["@timestamp"], json!(1721059200));
+        assert_eq!(body(&result)["time"], json!("2024-07-15 12:00:00"));
+    }
+
+    #[tokio::test]
+    async fn rfc3164_year() {
+        let p = processor(create_timestamp, "formats: [rfc3164]").await;
+        let tz = TimeZone::UTC;
+        let now = DateTime::parse_from_rfc3339("2024-01-02T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let ts = Timestamp {
+            field: vec!["timestamp".into()],
+            formats: vec![Format::Rfc3164],
+            timezone: tz,
+            target: vec!["timestamp".into()],
+            output_format: OutputFormat::Rfc3339,
+            metadata_key: None,
+            on_failure: OnFailure::Fail,
+        };
+
+        // December events seen in early January belong to the previous year
+        let parsed = ts.parse(&json!("Dec 31 23:59:59"), now).unwrap();
+        assert_eq!(parsed.to_rfc3339(), "2023-12-31T23:59:59+00:00");
+        let parsed = ts.parse(&json!("Jan  1 08:00:00"), now).unwrap();
+        assert_eq!(parsed.to_rfc3339(), "2024-01-01T08:00:00+00:00");
+
+        // Leap days fall back to the previous year when the current one has none
+        let now = DateTime::parse_from_rfc3339("2025-03-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let parsed = ts.parse(&json!("Feb 29 12:00:00"), now).unwrap();
+        assert_eq!(parsed.to_rfc3339(), "2024-02-29T12:00:00+00:00");
+
+        let result = run(p.as_ref(), json!({"timestamp": "Oct 11 22:14:15"})).await;
+        assert!(body(&result)["timestamp"]
+            .as_str()
+            .unwrap()
+            .ends_with("-10-11T22:14:15Z"));
+    }
+
+    #[tokio::test]
+    async fn metadata_copy() {
+        let p = processor(
+            create_timestamp,
+            "formats: [unix]\nmetadata_key: event_time\noutput_format: unix_ms",
+        )
+        .await;
+        let result = run(p.as_ref(), json!({"timestamp": 1709294400})).await;
+        assert_eq!(body(&result)["timestamp"], json!(1709294400000_i64));
+        assert_eq!(
+            result.metadata.get("event_time"),
+            Some(&Value::from(1709294400000_i64))
+        );
+    }
+
+    #[tokio::test]
+    async fn failure_handling() {
+        let p = processor(create_timestamp, "{}").await;
+        for m in [
+            message(json!({"timestamp": "yesterday"})),
+            text_message("not json"),
+        ] {
+            let result = p.process(m).await;
+            assert!(matches!(result, Err(Error::MessageFailed(_))), "{result:?}");
+        }
+
+        let p = processor(create_timestamp, "on_failure: skip").await;
+        let result = run(p.as_ref(), json!({"timestamp": "yesterday"})).await;
+        assert_eq!(body(&result), json!({"timestamp": "yesterday"}));
+
+        let p = processor(
+            create_timestamp,
+            "on_failure: ingest_time\noutput_format: unix",
+        )
+        .await;
+        let before = Utc::now().timestamp();
+        let result = run(p.as_ref(), json!({"other": 1})).await;
+        let ts = body(&result)["timestamp"].as_i64().unwrap();
+        assert!(ts >= before && ts <= Utc::now().timestamp());
+    }
+
+    #[tokio::test]
+    async fn invalid_config() {
+        for conf in [
+            "formats: []",
+            "timezone: Mars/Olympus_Mons",
+            "output_format: iso",
+            "on_failure: retry",
+            "field: 'a..b'",
+            "formats: [rfc3999]",
+            "formats: ['%Y-%m-%d %Q']",
+        ] {
+            let value: Value = serde_yaml::from_str(conf).unwrap();
+            assert!(create_timestamp(value).await.is_err(), "{conf}");
+        }
+    }
+}
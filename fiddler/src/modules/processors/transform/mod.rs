@@ -1,3 +1,4 @@
+use super::path::{parse_path, set_path, variable, Expression};
 use crate::config::register_plugin;
 use crate::config::ItemType;
 use crate::config::{ConfigSpec, ExecutionType};
@@ -8,7 +9,6 @@ use async_trait::async_trait;
 use fiddler_macros::fiddler_registration_func;
 use serde::{Deserialize, Serialize};
 use serde_yaml::Value;
-use std::collections::HashMap;
 
 #[derive(Deserialize, Serialize)]
 struct Mapping {
@@ -16,38 +16,72 @@ struct Mapping {
     target: String,
 }
 
+/// Determines how mapped results are combined with the original document
+#[derive(Deserialize, Serialize, Clone, Default, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// Output contains only the mapped fields
+    #[default]
+    Replace,
+    /// Mapped fields are layered on top of the original document
+    Merge,
+}
+
 #[derive(Deserialize, Serialize)]
 struct TransformConfig {
     label: Option<String>,
     mappings: Vec<Mapping>,
+    #[serde(default)]
+    mode: Mode,
+}
+
+/// Mapping with its expression compiled and its target path parsed
+struct ParsedMapping {
+    source: Expression,
+    target: Vec<String>,
 }
 
 pub struct Transform {
-    mappings: Vec<Mapping>,
+    mappings: Vec<ParsedMapping>,
+    mode: Mode,
+}
+
+impl Transform {
+    fn new(mappings: Vec<Mapping>, mode: Mode) -> Result<Self, Error> {
+        let mappings = mappings
+            .iter()
+            .map(|m| {
+                Ok(ParsedMapping {
+                    source: Expression::compile(&m.source)?,
+                    target: parse_path(&m.target)?,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Transform { mappings, mode })
+    }
 }
 
 #[async_trait]
 impl Processor for Transform {
     async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
-        let json_str = String::from_utf8(message.bytes.clone())
-            .map_err(|e| Error::ProcessingError(format!("{e}")))?;
-
-        let mut runtime = jmespath::Runtime::new();
-        runtime.register_builtin_functions();
+        let data = variable(&message.bytes)?;
+
+        let mut results = match self.mode {
+            Mode::Replace => serde_json::Value::Object(serde_json::Map::new()),
+            Mode::Merge => {
+                let original: serde_json::Value = serde_json::from_slice(&message.bytes)
+                    .map_err(|e| Error::MessageFailed(format!("{e}")))?;
+                if !original.is_object() {
+                    return Err(Error::MessageFailed(
+                        "merge mode requires the message to be a JSON object".into(),
+                    ));
+                }
+                original
+            }
+        };
 
-        let mut results = HashMap::new();
         for m in &self.mappings {
-            let expr = runtime
-                .compile(&m.source)
-                .map_err(|e| Error::ProcessingError(format!("{e}")))?;
-
-            let data = jmespath::Variable::from_json(&json_str)
-                .map_err(|e| Error::ProcessingError(format!("{e}")))?;
-
-            let result = expr
-                .search(data)
-                .map_err(|e| Error::ProcessingError(format!("{e}")))?;
-            results.insert(&m.target, result);
+            set_path(&mut results, &m.target, m.source.search(&data)?)?;
         }
 
         let new_msg =
@@ -70,14 +104,8 @@ impl Closer for Transform {
 
 #[fiddler_registration_func]
 fn create_transform(conf: Value) -> Result<ExecutionType, Error> {
-    let c: TransformConfig = serde_yaml::from_value(conf.clone())?;
-    for m in &c.mappings {
-        let _ = jmespath::compile(&m.source)
-            .map_err(|e| Error::ConfigFailedValidation(format!("{e}")))?;
-    }
-    let s = Transform {
-        mappings: c.mappings,
-    };
+    let c: TransformConfig = serde_yaml::from_value(conf)?;
+    let s = Transform::new(c.mappings, c.mode)?;
 
     Ok(ExecutionType::Processor(Box::new(s)))
 }
@@ -87,6 +115,11 @@ pub(super) fn register_transform() -> Result<(), Error> {
 properties:
   label:
     type: string
+  mode:
+    type: string
+    enum:
+      - replace
+      - merge
   mappings:
     type: array
     items:
@@ -111,6 +144,7 @@ required:
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::collections::HashMap;
 
     #[test]
     fn register_plugin() {
@@ -119,8 +153,8 @@ mod test {
 
     #[tokio::test]
     async fn test_simple_field_mapping() {
-        let processor = Transform {
-            mappings: vec![
+        let processor = Transform::new(
+            vec![
                 Mapping {
                     source: "name".to_string(),
                     target: "user_name".to_string(),
@@ -130,7 +164,9 @@ mod test {
                     target: "user_age".to_string(),
                 },
             ],
-        };
+            Mode::Replace,
+        )
+        .unwrap();
 
         let message = Message {
             bytes: br#"{"name": "Alice", "age": 30, "city": "NYC"}"#.to_vec(),
@@ -150,8 +186,8 @@ mod test {
 
     #[tokio::test]
     async fn test_nested_field_extraction() {
-        let processor = Transform {
-            mappings: vec![
+        let processor = Transform::new(
+            vec![
                 Mapping {
                     source: "user.profile.email".to_string(),
                     target: "email".to_string(),
@@ -161,7 +197,9 @@ mod test {
                     target: "name".to_string(),
                 },
             ],
-        };
+            Mode::Replace,
+        )
+        .unwrap();
 
         let message = Message {
             bytes: br#"{"user": {"name": "Bob", "profile": {"email": "bob@example.com", "phone": "555-1234"}}}"#.to_vec(),
@@ -178,8 +216,8 @@ mod test {
 
     #[tokio::test]
     async fn test_array_extraction() {
-        let processor = Transform {
-            mappings: vec![
+        let processor = Transform::new(
+            vec![
                 Mapping {
                     source: "items[0]".to_string(),
                     target: "first_item".to_string(),
@@ -189,7 +227,9 @@ mod test {
                     target: "last_item".to_string(),
                 },
             ],
-        };
+            Mode::Replace,
+        )
+        .unwrap();
 
         let message = Message {
             bytes: br#"{"items": ["apple", "banana", "cherry"]}"#.to_vec(),
@@ -206,12 +246,14 @@ mod test {
 
     #[tokio::test]
     async fn test_array_projection() {
-        let processor = Transform {
-            mappings: vec![Mapping {
+        let processor = Transform::new(
+            vec![Mapping {
                 source: "users[*].name".to_string(),
                 target: "names".to_string(),
             }],
-        };
+            Mode::Replace,
+        )
+        .unwrap();
 
         let message = Message {
             bytes: br#"{"users": [{"name": "Alice", "age": 30}, {"name": "Bob", "age": 25}]}"#
@@ -231,8 +273,8 @@ mod test {
 
     #[tokio::test]
     async fn test_jmespath_function() {
-        let processor = Transform {
-            mappings: vec![
+        let processor = Transform::new(
+            vec![
                 Mapping {
                     source: "length(items)".to_string(),
                     target: "item_count".to_string(),
@@ -242,7 +284,9 @@ mod test {
                     target: "items_string".to_string(),
                 },
             ],
-        };
+            Mode::Replace,
+        )
+        .unwrap();
 
         let message = Message {
             bytes: br#"{"items": ["a", "b", "c"]}"#.to_vec(),
@@ -259,8 +303,8 @@ mod test {
 
     #[tokio::test]
     async fn test_null_value_extraction() {
-        let processor = Transform {
-            mappings: vec![
+        let processor = Transform::new(
+            vec![
                 Mapping {
                     source: "existing".to_string(),
                     target: "found".to_string(),
@@ -270,7 +314,9 @@ mod test {
                     target: "missing".to_string(),
                 },
             ],
-        };
+            Mode::Replace,
+        )
+        .unwrap();
 
         let message = Message {
             bytes: br#"{"existing": "value"}"#.to_vec(),
@@ -287,12 +333,14 @@ mod test {
 
     #[tokio::test]
     async fn test_preserves_metadata() {
-        let processor = Transform {
-            mappings: vec![Mapping {
+        let processor = Transform::new(
+            vec![Mapping {
                 source: "data".to_string(),
                 target: "value".to_string(),
             }],
-        };
+            Mode::Replace,
+        )
+        .unwrap();
 
         let mut metadata = HashMap::new();
         metadata.insert(
@@ -313,12 +361,14 @@ mod test {
 
     #[tokio::test]
     async fn test_complex_object_extraction() {
-        let processor = Transform {
-            mappings: vec![Mapping {
+        let processor = Transform::new(
+            vec![Mapping {
                 source: "user".to_string(),
                 target: "profile".to_string(),
             }],
-        };
+            Mode::Replace,
+        )
+        .unwrap();
 
         let message = Message {
             bytes: br#"{"user": {"name": "Alice", "settings": {"theme": "dark"}}}"#.to_vec(),
@@ -335,12 +385,14 @@ mod test {
 
     #[tokio::test]
     async fn test_invalid_json_error() {
-        let processor = Transform {
-            mappings: vec![Mapping {
+        let processor = Transform::new(
+            vec![Mapping {
                 source: "field".to_string(),
                 target: "output".to_string(),
             }],
-        };
+            Mode::Replace,
+        )
+        .unwrap();
 
         let message = Message {
             bytes: b"not valid json".to_vec(),
@@ -348,17 +400,19 @@ mod test {
         };
 
         let result = processor.process(message).await;
-        assert!(matches!(result, Err(Error::ProcessingError(_))));
+        assert!(matches!(result, Err(Error::MessageFailed(_))));
     }
 
     #[tokio::test]
     async fn test_invalid_utf8_error() {
-        let processor = Transform {
-            mappings: vec![Mapping {
+        let processor = Transform::new(
+            vec![Mapping {
                 source: "field".to_string(),
                 target: "output".to_string(),
             }],
-        };
+            Mode::Replace,
+        )
+        .unwrap();
 
         let message = Message {
             bytes: vec![0xff, 0xfe, 0x00, 0x01],
@@ -366,17 +420,19 @@ mod test {
         };
 
         let result = processor.process(message).await;
-        assert!(matches!(result, Err(Error::ProcessingError(_))));
+        assert!(matches!(result, Err(Error::MessageFailed(_))));
     }
 
     #[tokio::test]
     async fn test_filter_expression() {
-        let processor = Transform {
-            mappings: vec![Mapping {
+        let processor = Transform::new(
+            vec![Mapping {
                 source: "items[?price > `10`]".to_string(),
                 target: "expensive_items".to_string(),
             }],
-        };
+            Mode::Replace,
+        )
+        .unwrap();
 
         let message = Message {
             bytes: br#"{"items": [{"name": "A", "price": 5}, {"name": "B", "price": 15}, {"name": "C", "price": 25}]}"#.to_vec(),
@@ -397,12 +453,14 @@ mod test {
     async fn test_pipe_expression() {
         // users[*].scores returns [[90, 85], [75, 80]]
         // | [0] takes the first element: [90, 85]
-        let processor = Transform {
-            mappings: vec![Mapping {
+        let processor = Transform::new(
+            vec![Mapping {
                 source: "users[*].scores | [0]".to_string(),
                 target: "first_user_scores".to_string(),
             }],
-        };
+            Mode::Replace,
+        )
+        .unwrap();
 
         let message = Message {
             bytes: br#"{"users": [{"scores": [90, 85]}, {"scores": [75, 80]}]}"#.to_vec(),
@@ -421,12 +479,14 @@ mod test {
 
     #[tokio::test]
     async fn test_multiselect_hash() {
-        let processor = Transform {
-            mappings: vec![Mapping {
+        let processor = Transform::new(
+            vec![Mapping {
                 source: "{full_name: name, user_email: email}".to_string(),
                 target: "contact".to_string(),
             }],
-        };
+            Mode::Replace,
+        )
+        .unwrap();
 
         let message = Message {
             bytes: br#"{"name": "Alice", "email": "alice@example.com", "phone": "555-1234"}"#
@@ -441,4 +501,130 @@ mod test {
         assert_eq!(output["contact"]["full_name"], "Alice");
         assert_eq!(output["contact"]["user_email"], "alice@example.com");
     }
+
+    #[tokio::test]
+    async fn test_nested_target_paths() {
+        let processor = Transform::new(
+            vec![
+                Mapping {
+                    source: "name".to_string(),
+                    target: "user.name".to_string(),
+                },
+                Mapping {
+                    source: "email".to_string(),
+                    target: "/user/contact/email".to_string(),
+                },
+            ],
+            Mode::Replace,
+        )
+        .unwrap();
+
+        let message = Message {
+            bytes: br#"{"name": "Alice", "email": "alice@example.com"}"#.to_vec(),
+            ..Default::default()
+        };
+
+        let result = processor.process(message).await.unwrap();
+        let output: serde_json::Value = serde_json::from_slice(&result[0].bytes).unwrap();
+        assert_eq!(
+            output,
+            serde_json::json!({"user": {"name": "Alice", "contact": {"email": "alice@example.com"}}})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_merge_mode_keeps_original_fields() {
+        let processor = Transform::new(
+            vec![
+                Mapping {
+                    source: "join(' ', [first, last])".to_string(),
+                    target: "user.full_name".to_string(),
+                },
+                Mapping {
+                    source: "`true`".to_string(),
+                    target: "processed".to_string(),
+                },
+            ],
+            Mode::Merge,
+        )
+        .unwrap();
+
+        let message = Message {
+            bytes: br#"{"first": "Alice", "last": "Smith", "user": {"id": 7}}"#.to_vec(),
+            ..Default::default()
+        };
+
+        let result = processor.process(message).await.unwrap();
+        let output: serde_json::Value = serde_json::from_slice(&result[0].bytes).unwrap();
+        assert_eq!(output["first"], "Alice");
+        assert_eq!(output["last"], "Smith");
+        assert_eq!(output["user"]["id"], 7);
+        assert_eq!(output["user"]["full_name"], "Alice Smith");
+        assert_eq!(output["processed"], true);
+    }
+
+    #[tokio::test]
+    async fn test_merge_mode_requires_object() {
+        let processor = Transform::new(
+            vec![Mapping {
+                source: "[0]".to_string(),
+                target: "first".to_string(),
+            }],
+            Mode::Merge,
+        )
+        .unwrap();
+
+        let message = Message {
+            bytes: br#"["a", "b"]"#.to_vec(),
+            ..Default::default()
+        };
+
+        let result = processor.process(message).await;
+        assert!(matches!(result, Err(Error::MessageFailed(_))));
+    }
+
+    #[tokio::test]
+    async fn test_merge_mode_target_conflict() {
+        let processor = Transform::new(
+            vec![Mapping {
+                source: "name".to_string(),
+                target: "user.name".to_string(),
+            }],
+            Mode::Merge,
+        )
+        .unwrap();
+
+        let message = Message {
+            bytes: br#"{"name": "Alice", "user": "alice"}"#.to_vec(),
+            ..Default::default()
+        };
+
+        let result = processor.process(message).await;
+        assert!(matches!(result, Err(Error::MessageFailed(_))));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_target_path_rejected() {
+        let conf: Value = serde_yaml::from_str(
+            "mappings:
+  - source: name
+    target: user..name",
+        )
+        .unwrap();
+        let result = create_transform(conf).await;
+        assert!(matches!(result, Err(Error::ConfigFailedValidation(_))));
+    }
+
+    #[tokio::test]
+    async fn test_mode_from_config() {
+        let conf: Value = serde_yaml::from_str(
+            "mode: merge
+mappings:
+  - source: name
+    target: copy",
+        )
+        .unwrap();
+        let c: TransformConfig = serde_yaml::from_value(conf).unwrap();
+        assert_eq!(c.mode, Mode::Merge);
+    }
 }
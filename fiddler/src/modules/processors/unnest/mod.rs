@@ -0,0 +1,282 @@
+//! Unnest processor for splitting a JSON array into individual messages.
+//!
+//! The `path` expression selects an array within the message, and each
+//! element is emitted as its own message.  Fields of the parent document can
+//! be copied into every element, which then must be an object.  Each message
+//! records its position and the id of the parent in metadata; without a
+//! `parent_id` expression a random id is generated per parent.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - unnest:
+//!       path: "Records"              # Required: JMESPath expression selecting the array
+//!       include:                     # Optional: parent fields copied into each element
+//!         - source: "account"        # JMESPath expression against the parent
+//!           target: "parent.account" # Field within the element
+//!       parent_id: "requestId"       # Optional: JMESPath expression for the parent id
+//! ```
+
+use super::path::{parse_path, set_path, variable, Expression};
+use crate::config::register_plugin;
+use crate::config::ItemType;
+use crate::config::{ConfigSpec, ExecutionType};
+use crate::Message;
+use crate::MessageBatch;
+use crate::{Closer, Error, Processor};
+use async_trait::async_trait;
+use fiddler_macros::fiddler_registration_func;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use serde_yaml::Value;
+use uuid::Uuid;
+
+/// Metadata key holding the position of the element within the array
+const INDEX_METADATA: &str = "unnest_index";
+/// Metadata key holding the number of elements in the array
+const COUNT_METADATA: &str = "unnest_count";
+/// Metadata key holding the id of the parent message
+const PARENT_ID_METADATA: &str = "unnest_parent_id";
+
+#[derive(Deserialize)]
+struct Include {
+    source: String,
+    target: String,
+}
+
+#[derive(Deserialize)]
+struct UnnestConfig {
+    path: String,
+    #[serde(default)]
+    include: Vec<Include>,
+    parent_id: Option<String>,
+}
+
+pub struct Unnest {
+    path: Expression,
+    include: Vec<(Expression, Vec<String>)>,
+    parent_id: Option<Expression>,
+}
+
+#[async_trait]
+impl Processor for Unnest {
+    async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
+        let data = variable(&message.bytes)?;
+
+        let elements = match self.path.search(&data)? {
+            JsonValue::Array(elements) => elements,
+            JsonValue::Null => Vec::new(),
+            other => {
+                return Err(Error::MessageFailed(format!(
+                    "'{}' did not select an array, got {other}",
+                    self.path
+                )))
+            }
+        };
+
+        let parent_id = match &self.parent_id {
+            Some(expression) => match expression.search(&data)? {
+                JsonValue::String(s) => s,
+                JsonValue::Null => {
+                    return Err(Error::MessageFailed(format!(
+                        "parent id '{expression}' not found"
+                    )))
+                }
+                other => other.to_string(),
+            },
+            None => Uuid::new_v4().to_string(),
+        };
+
+        let included = self
+            .include
+            .iter()
+            .map(|(source, target)| Ok((source.search(&data)?, target)))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let count = elements.len();
+        let mut batch = Vec::with_capacity(count);
+        for (index, mut element) in elements.into_iter().enumerate() {
+            if !included.is_empty() && !element.is_object() {
+                return Err(Error::MessageFailed(format!(
+                    "element {index} is not an object, unable to include parent fields"
+                )));
+            }
+            for (value, target) in &included {
+                set_path(&mut element, target, value.clone())?;
+            }
+
+            let mut metadata = message.metadata.clone();
+            metadata.insert(INDEX_METADATA.into(), Value::from(index as u64));
+            metadata.insert(COUNT_METADATA.into(), Value::from(count as u64));
+            metadata.insert(PARENT_ID_METADATA.into(), Value::String(parent_id.clone()));
+
+            batch.push(Message {
+                bytes: serde_json::to_vec(&element)
+                    .map_err(|e| Error::ProcessingError(format!("{e}")))?,
+                metadata,
+                ..Default::default()
+            });
+        }
+
+        Ok(batch)
+    }
+}
+
+impl Closer for Unnest {}
+
+#[fiddler_registration_func]
+fn create_unnest(conf: Value) -> Result<ExecutionType, Error> {
+    let c: UnnestConfig = serde_yaml::from_value(conf)?;
+
+    let include = c
+        .include
+        .iter()
+        .map(|i| Ok((Expression::compile(&i.source)?, parse_path(&i.target)?)))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    Ok(ExecutionType::Processor(Box::new(Unnest {
+        path: Expression::compile(&c.path)?,
+        include,
+        parent_id: c
+            .parent_id
+            .as_deref()
+            .map(Expression::compile)
+            .transpose()?,
+    })))
+}
+
+pub(super) fn register_unnest() -> Result<(), Error> {
+    let config = "type: object
+properties:
+  path:
+    type: string
+  include:
+    type: array
+    items:
+      type: object
+      properties:
+        source:
+          type: string
+        target:
+          type: string
+      required:
+        - source
+        - target
+  parent_id:
+    type: string
+required:
+  - path";
+    let conf_spec = ConfigSpec::from_schema(config)?;
+
+    register_plugin(
+        "unnest".into(),
+        ItemType::Processor,
+        conf_spec,
+        create_unnest,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::modules::processors::test_util::{body, message, processor, text_message};
+    use serde_json::json;
+
+    #[test]
+    fn register_plugin() {
+        register_unnest().unwrap()
+    }
+
+    #[tokio::test]
+    async fn splits_records() {
+        let p = processor(
+            create_unnest,
+            "path: Records
+include:
+  - source: account
+    target: parent.account
+parent_id: requestId",
+        )
+        .await;
+        let mut input = message(json!({
+            "requestId": "abc",
+            "account": 42,
+            "Records": [{"event": "a"}, {"event": "b"}]
+        }));
+        input
+            .metadata
+            .insert("source".into(), Value::String("sqs".into()));
+
+        let output = p.process(input).await.unwrap();
+        assert_eq!(output.len(), 2);
+        assert_eq!(
+            body(&output[1]),
+            json!({"event": "b", "parent": {"account": 42}})
+        );
+        assert_eq!(
+            output[1].metadata.get(INDEX_METADATA),
+            Some(&Value::from(1))
+        );
+        assert_eq!(
+            output[1].metadata.get(COUNT_METADATA),
+            Some(&Value::from(2))
+        );
+        assert_eq!(
+            output[1].metadata.get(PARENT_ID_METADATA),
+            Some(&Value::String("abc".into()))
+        );
+        assert_eq!(
+            output[0].metadata.get("source"),
+            Some(&Value::String("sqs".into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn generated_parent_id() {
+        let p = processor(create_unnest, "path: '[*]'").await;
+        let output = p.process(message(json!([1, "two"]))).await.unwrap();
+        assert_eq!(body(&output[0]), json!(1));
+        assert_eq!(body(&output[1]), json!("two"));
+
+        let first = output[0].metadata.get(PARENT_ID_METADATA).unwrap();
+        assert_eq!(output[1].metadata.get(PARENT_ID_METADATA), Some(first));
+        assert!(Uuid::parse_str(first.as_str().unwrap()).is_ok());
+    }
+
+    #[tokio::test]
+    async fn missing_and_invalid_arrays() {
+        let p = processor(create_unnest, "path: Records").await;
+        assert!(p.process(message(json!({}))).await.unwrap().is_empty());
+        assert!(p
+            .process(message(json!({"Records": []})))
+            .await
+            .unwrap()
+            .is_empty());
+        for input in [message(json!({"Records": 1})), text_message("not json")] {
+            let result = p.process(input).await;
+            assert!(matches!(result, Err(Error::MessageFailed(_))), "{result:?}");
+        }
+
+        let p = processor(
+            create_unnest,
+            "path: Records\ninclude: [{source: id, target: id}]",
+        )
+        .await;
+        let result = p.process(message(json!({"id": 1, "Records": ["a"]}))).await;
+        assert!(matches!(result, Err(Error::MessageFailed(_))));
+    }
+
+    #[tokio::test]
+    async fn invalid_config() {
+        for conf in [
+            "include: []",
+            "path: 'Records[['",
+            "path: Records\nparent_id: 'a[['",
+            "path: Records\ninclude: [{source: id, target: 'a..b'}]",
+        ] {
+            let value: Value = serde_yaml::from_str(conf).unwrap();
+            assert!(create_unnest(value).await.is_err(), "{conf}");
+        }
+    }
+}
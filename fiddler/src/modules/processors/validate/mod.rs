@@ -0,0 +1,302 @@
+//! Validate processor for checking JSON messages against a JSON Schema.
+//!
+//! The schema is given inline or read from a JSON or YAML file.  Invalid
+//! messages either fail individually, so the input is notified of the error
+//! while the pipeline continues, or are tagged with the list of violations so
+//! later steps can route them.
+//! Violations are written to metadata, and to a field of the message when
+//! `target` is set, since switch conditions are evaluated against the body.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - validate:
+//!       schema:                            # Required unless schema_path is set
+//!         type: object
+//!         required: [id]
+//!       schema_path: "schemas/event.json"  # Optional: JSON or YAML schema file
+//!       on_invalid: tag                    # Optional: fail or tag (default: fail)
+//!       target: "_validation"              # Optional: field to also write violations to
+//! ```
+
+use crate::config::register_plugin;
+use crate::config::ItemType;
+use crate::config::{ConfigSpec, ExecutionType};
+use crate::modules::processors::path::{parse_path, set_path};
+use crate::Message;
+use crate::MessageBatch;
+use crate::{Closer, Error, Processor};
+use async_trait::async_trait;
+use fiddler_macros::fiddler_registration_func;
+use jsonschema::JSONSchema;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+use serde_yaml::{Mapping, Value};
+
+const VALID_METADATA: &str = "schema_valid";
+const ERRORS_METADATA: &str = "schema_errors";
+
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum OnInvalid {
+    #[default]
+    Fail,
+    Tag,
+}
+
+#[derive(Deserialize)]
+struct ValidateConfig {
+    schema: Option<Value>,
+    schema_path: Option<String>,
+    #[serde(default)]
+    on_invalid: OnInvalid,
+    target: Option<String>,
+}
+
+/// A single schema violation
+struct Violation {
+    path: String,
+    message: String,
+}
+
+pub struct Validate {
+    schema: JSONSchema,
+    on_invalid: OnInvalid,
+    target: Option<Vec<String>>,
+}
+
+impl Validate {
+    fn violations(&self, doc: &JsonValue) -> Vec<Violation> {
+        match self.schema.validate(doc) {
+            Ok(()) => Vec::new(),
+            Err(errors) => errors
+                .map(|e| Violation {
+                    path: e.instance_path.to_string(),
+                    message: e.to_string(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl Processor for Validate {
+    async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
+        let mut doc: JsonValue = serde_json::from_slice(&message.bytes)
+            .map_err(|e| Error::MessageFailed(format!("{e}")))?;
+
+        let violations = self.violations(&doc);
+        let mut message = message;
+
+        if !violations.is_empty() && self.on_invalid == OnInvalid::Fail {
+            let details: Vec<String> = violations
+                .iter()
+                .map(|v| format!("{}: {}", v.path, v.message))
+                .collect();
+            return Err(Error::MessageFailed(format!(
+                "schema validation failed: {}",
+                details.join("; ")
+            )));
+        }
+
+        if !violations.is_empty() {
+            if let Some(target) = &self.target {
+                let errors = violations
+                    .iter()
+                    .map(|v| serde_json::json!({"path": v.path, "message": v.message}))
+                    .collect();
+                set_path(&mut doc, target, JsonValue::Array(errors))?;
+                message.bytes =
+                    serde_json::to_vec(&doc).map_err(|e| Error::ProcessingError(format!("{e}")))?;
+            }
+        }
+
+        let errors = violations
+            .into_iter()
+            .map(|v| {
+                let mut entry = Mapping::new();
+                entry.insert("path".into(), v.path.into());
+                entry.insert("message".into(), v.message.into());
+                Value::Mapping(entry)
+            })
+            .collect::<Vec<_>>();
+        message
+            .metadata
+            .insert(VALID_METADATA.into(), Value::Bool(errors.is_empty()));
+        message
+            .metadata
+            .insert(ERRORS_METADATA.into(), Value::Sequence(errors));
+
+        Ok(vec![message])
+    }
+}
+
+impl Closer for Validate {}
+
+/// Load the schema from the inline value or the schema file
+fn load_schema(c: &ValidateConfig) -> Result<JsonValue, Error> {
+    let schema: Value = match (&c.schema, &c.schema_path) {
+        (Some(schema), None) => schema.clone(),
+        (None, Some(path)) => {
+            let content = std::fs::read_to_string(path).map_err(|e| {
+                Error::ConfigFailedValidation(format!("unable to read schema {path}: {e}"))
+            })?;
+            // YAML is a superset of JSON, so both formats are accepted
+            serde_yaml::from_str(&content).map_err(|e| {
+                Error::ConfigFailedValidation(format!("unable to parse schema {path}: {e}"))
+            })?
+        }
+        _ => {
+            return Err(Error::ConfigFailedValidation(
+                "exactly one of schema or schema_path is required".into(),
+            ))
+        }
+    };
+
+    serde_json::to_value(schema).map_err(|e| Error::ConfigFailedValidation(format!("{e}")))
+}
+
+#[fiddler_registration_func]
+fn create_validate(conf: Value) -> Result<ExecutionType, Error> {
+    let c: ValidateConfig = serde_yaml::from_value(conf.clone())?;
+
+    let schema = load_schema(&c)?;
+    let schema = JSONSchema::compile(&schema)
+        .map_err(|e| Error::ConfigFailedValidation(format!("invalid JSON Schema: {e}")))?;
+
+    Ok(ExecutionType::Processor(Box::new(Validate {
+        schema,
+        on_invalid: c.on_invalid,
+        target: c.target.as_deref().map(parse_path).transpose()?,
+    })))
+}
+
+pub(super) fn register_validate() -> Result<(), Error> {
+    let config = "type: object
+properties:
+  schema:
+    type: [object, boolean]
+  schema_path:
+    type: string
+  on_invalid:
+    type: string
+    enum: [\"fail\", \"tag\"]
+  target:
+    type: string";
+    let conf_spec = ConfigSpec::from_schema(config)?;
+
+    register_plugin(
+        "validate".into(),
+        ItemType::Processor,
+        conf_spec,
+        create_validate,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::modules::processors::test_util::{processor, text_message};
+    use serde_json::json;
+
+    const SCHEMA: &str = "schema:
+  type: object
+  required: [id]
+  properties:
+    id:
+      type: integer
+    tags:
+      type: array
+      items:
+        type: string";
+
+    #[test]
+    fn register_plugin() {
+        register_validate().unwrap()
+    }
+
+    #[tokio::test]
+    async fn valid_message_passes() {
+        let p = processor(create_validate, SCHEMA).await;
+        let result = p
+            .process(text_message(r#"{"id": 1, "tags": ["a"]}"#))
+            .await
+            .unwrap();
+        assert_eq!(result[0].bytes, br#"{"id": 1, "tags": ["a"]}"#.to_vec());
+        assert_eq!(
+            result[0].metadata.get(VALID_METADATA),
+            Some(&Value::Bool(true))
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_message_fails() {
+        let p = processor(create_validate, SCHEMA).await;
+        let err = p
+            .process(text_message(r#"{"id": "x", "tags": [1]}"#))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::MessageFailed(_)), "{err}");
+        let text = format!("{err}");
+        assert!(text.contains("/id"), "{text}");
+        assert!(text.contains("/tags/0"), "{text}");
+
+        assert!(p.process(text_message("not json")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn invalid_message_tagged() {
+        let p = processor(
+            create_validate,
+            &format!("{SCHEMA}\non_invalid: tag\ntarget: _validation"),
+        )
+        .await;
+        let result = p.process(text_message(r#"{"tags": [1]}"#)).await.unwrap();
+
+        assert_eq!(
+            result[0].metadata.get(VALID_METADATA),
+            Some(&Value::Bool(false))
+        );
+        let errors = result[0].metadata.get(ERRORS_METADATA).unwrap();
+        let paths: Vec<&str> = errors
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|e| e.get("path").unwrap().as_str().unwrap())
+            .collect();
+        assert!(paths.contains(&""));
+        assert!(paths.contains(&"/tags/0"));
+
+        let body: JsonValue = serde_json::from_slice(&result[0].bytes).unwrap();
+        assert_eq!(body["_validation"].as_array().unwrap().len(), 2);
+        assert_eq!(body["tags"], json!([1]));
+    }
+
+    #[tokio::test]
+    async fn schema_from_file() {
+        let path =
+            std::env::temp_dir().join(format!("fiddler_schema_{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, r#"{"type": "object", "required": ["name"]}"#).unwrap();
+        let p = processor(create_validate, &format!("schema_path: {}", path.display())).await;
+
+        assert!(p.process(text_message(r#"{"name": "a"}"#)).await.is_ok());
+        assert!(p.process(text_message(r#"{"other": "a"}"#)).await.is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn invalid_config() {
+        for conf in [
+            "on_invalid: tag",
+            "schema: {type: object}\nschema_path: schema.json",
+            "schema_path: /does/not/exist.json",
+            "schema: {type: 12}",
+            "schema: {type: object}\ntarget: 'a..b'",
+        ] {
+            let value: Value = serde_yaml::from_str(conf).unwrap();
+            assert!(create_validate(value).await.is_err(), "{conf}");
+        }
+    }
+}
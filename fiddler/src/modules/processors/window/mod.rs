@@ -0,0 +1,941 @@
+//! Window processor for aggregating messages over time windows.
+//!
+//! Messages are grouped by the result of a JMESPath `key` expression and
+//! assigned to tumbling, sliding or session windows.  Once a window closes a
+//! single JSON message holding the key, the window bounds and the configured
+//! aggregations is emitted.  Aggregated messages are reported as filtered.
+//!
+//! Windows use processing time unless `timestamp` selects an event-time field.
+//! With event time a window closes once an event newer than its end plus
+//! `allowed_lateness` arrives; events for windows that already closed are
+//! dropped.  Windows still open are emitted on shutdown.  A body that is not
+//! JSON, or an event time that cannot be parsed, fails the message unless
+//! `on_failure` skips it; invalid event times may also fall back to
+//! processing time.
+//!
+//! # Configuration
+//!
+//! ```yaml
+//! processors:
+//!   - window:
+//!       type: tumbling                # Optional: tumbling, sliding or session (default: tumbling)
+//!       size: 1m                      # Required for tumbling and sliding windows
+//!       slide: 10s                    # Required for sliding windows
+//!       gap: 30s                      # Required for session windows
+//!       key: host                     # Optional: JMESPath expression to group by
+//!       timestamp: event.time         # Optional: JMESPath expression for event time
+//!       allowed_lateness: 5s          # Optional: delay before event-time windows close
+//!       on_failure: skip              # Optional: fail, processing_time or skip (default: fail)
+//!       aggregations:                 # Required
+//!         - name: requests
+//!           function: count
+//!         - name: bytes.total
+//!           function: sum
+//!           field: bytes
+//! ```
+
+use super::path::{parse_path, set_path, variable, Expression};
+use crate::config::register_plugin;
+use crate::config::ItemType;
+use crate::config::{ConfigSpec, ExecutionType};
+use crate::Message;
+use crate::MessageBatch;
+use crate::{Closer, Error, Processor};
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, Utc};
+use fiddler_macros::fiddler_registration_func;
+use serde::Deserialize;
+use serde_json::{json, Value as JsonValue};
+use serde_yaml::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::debug;
+
+/// Kind of window messages are assigned to
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowType {
+    /// Fixed size, non-overlapping windows
+    #[default]
+    Tumbling,
+    /// Fixed size windows starting every `slide`
+    Sliding,
+    /// Windows closed after a gap of inactivity per key
+    Session,
+}
+
+/// Aggregation applied to the messages of a window
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Function {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg,
+    Distinct,
+}
+
+/// Handling of messages that are not JSON or have an invalid event time
+#[derive(Deserialize, Clone, Copy, Default, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum OnFailure {
+    /// Fail the message
+    #[default]
+    Fail,
+    /// Use the time the message is processed in place of an invalid event
+    /// time; messages that are not JSON still fail
+    ProcessingTime,
+    /// Drop the message without aggregating it
+    Skip,
+}
+
+#[derive(Deserialize)]
+struct AggregationConfig {
+    name: String,
+    function: Function,
+    field: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct WindowConfig {
+    #[serde(default, rename = "type")]
+    window_type: WindowType,
+    #[serde(default, deserialize_with = "crate::deserialize_optional_duration")]
+    size: Option<Duration>,
+    #[serde(default, deserialize_with = "crate::deserialize_optional_duration")]
+    slide: Option<Duration>,
+    #[serde(default, deserialize_with = "crate::deserialize_optional_duration")]
+    gap: Option<Duration>,
+    key: Option<String>,
+    timestamp: Option<String>,
+    #[serde(default, deserialize_with = "crate::deserialize_optional_duration")]
+    allowed_lateness: Option<Duration>,
+    #[serde(default)]
+    on_failure: OnFailure,
+    aggregations: Vec<AggregationConfig>,
+}
+
+/// Assigns event times, in milliseconds, to windows
+#[derive(Clone, Copy)]
+enum Assigner {
+    Fixed { size: i64, slide: i64 },
+    Session { gap: i64 },
+}
+
+struct Aggregation {
+    path: Vec<String>,
+    function: Function,
+    field: Option<Expression>,
+}
+
+enum Accumulator {
+    Count(u64),
+    Sum(f64),
+    Min(Option<f64>),
+    Max(Option<f64>),
+    Avg(f64, u64),
+    Distinct(HashSet<String>),
+}
+
+impl Accumulator {
+    fn new(function: Function) -> Self {
+        match function {
+            Function::Count => Accumulator::Count(0),
+            Function::Sum => Accumulator::Sum(0.0),
+            Function::Min => Accumulator::Min(None),
+            Function::Max => Accumulator::Max(None),
+            Function::Avg => Accumulator::Avg(0.0, 0),
+            Function::Distinct => Accumulator::Distinct(HashSet::new()),
+        }
+    }
+
+    /// Add a single value; non-numeric values are ignored by numeric aggregations
+    fn add(&mut self, value: &JsonValue) {
+        let number = value.as_f64();
+        match self {
+            Accumulator::Count(c) => *c += 1,
+            Accumulator::Sum(s) => *s += number.unwrap_or(0.0),
+            Accumulator::Min(m) => {
+                if let Some(n) = number {
+                    *m = Some(m.map_or(n, |c| c.min(n)));
+                }
+            }
+            Accumulator::Max(m) => {
+                if let Some(n) = number {
+                    *m = Some(m.map_or(n, |c| c.max(n)));
+                }
+            }
+            Accumulator::Avg(s, c) => {
+                if let Some(n) = number {
+                    *s += n;
+                    *c += 1;
+                }
+            }
+            Accumulator::Distinct(d) => {
+                if !value.is_null() {
+                    d.insert(value.to_string());
+                }
+            }
+        }
+    }
+
+    fn merge(&mut self, other: Accumulator) {
+        match (self, other) {
+            (Accumulator::Count(a), Accumulator::Count(b)) => *a += b,
+            (Accumulator::Sum(a), Accumulator::Sum(b)) => *a += b,
+            (Accumulator::Min(a), Accumulator::Min(b)) => {
+                *a = match (*a, b) {
+                    (Some(x), Some(y)) => Some(x.min(y)),
+                    (x, y) => x.or(y),
+                }
+            }
+            (Accumulator::Max(a), Accumulator::Max(b)) => {
+                *a = match (*a, b) {
+                    (Some(x), Some(y)) => Some(x.max(y)),
+                    (x, y) => x.or(y),
+                }
+            }
+            (Accumulator::Avg(s, c), Accumulator::Avg(os, oc)) => {
+                *s += os;
+                *c += oc;
+            }
+            (Accumulator::Distinct(a), Accumulator::Distinct(b)) => a.extend(b),
+            _ => {}
+        }
+    }
+
+    fn result(&self) -> JsonValue {
+        match self {
+            Accumulator::Count(c) => json!(c),
+            Accumulator::Sum(s) => json!(s),
+            Accumulator::Min(m) => json!(m),
+            Accumulator::Max(m) => json!(m),
+            Accumulator::Avg(_, 0) => JsonValue::Null,
+            Accumulator::Avg(s, c) => json!(s / *c as f64),
+            Accumulator::Distinct(d) => json!(d.len()),
+        }
+    }
+}
+
+struct Window {
+    start: i64,
+    end: i64,
+    accumulators: Vec<Accumulator>,
+}
+
+struct KeyState {
+    key: JsonValue,
+    windows: Vec<Window>,
+}
+
+#[derive(Default)]
+struct State {
+    keys: HashMap<String, KeyState>,
+    /// Newest event time seen, in milliseconds
+    max_event_time: Option<i64>,
+}
+
+/// Values extracted from a single message
+struct Event {
+    key: JsonValue,
+    time: i64,
+    values: Vec<JsonValue>,
+}
+
+pub struct WindowProcessor {
+    assigner: Assigner,
+    key: Option<Expression>,
+    timestamp: Option<Expression>,
+    allowed_lateness: i64,
+    on_failure: OnFailure,
+    aggregations: Vec<Aggregation>,
+    state: Mutex<State>,
+}
+
+fn millis(d: Duration) -> i64 {
+    i64::try_from(d.as_millis()).unwrap_or(i64::MAX)
+}
+
+fn now_millis() -> i64 {
+    Utc::now().timestamp_millis()
+}
+
+fn format_time(ms: i64) -> JsonValue {
+    match DateTime::<Utc>::from_timestamp_millis(ms) {
+        Some(t) => JsonValue::String(t.to_rfc3339_opts(SecondsFormat::Millis, true)),
+        None => JsonValue::Null,
+    }
+}
+
+/// Parse an event time given as epoch seconds or an RFC 3339 string
+fn parse_event_time(value: &JsonValue) -> Result<i64, Error> {
+    match value {
+        JsonValue::Number(n) => n
+            .as_f64()
+            .map(|s| (s * 1000.0) as i64)
+            .ok_or_else(|| Error::MessageFailed(format!("invalid event time {n}"))),
+        JsonValue::String(s) => DateTime::parse_from_rfc3339(s)
+            .map(|t| t.timestamp_millis())
+            .map_err(|e| Error::MessageFailed(format!("invalid event time '{s}': {e}"))),
+        other => Err(Error::MessageFailed(format!(
+            "event time must be a number or RFC 3339 string, got {other}"
+        ))),
+    }
+}
+
+impl WindowProcessor {
+    fn new_accumulators(&self) -> Vec<Accumulator> {
+        self.aggregations
+            .iter()
+            .map(|a| Accumulator::new(a.function))
+            .collect()
+    }
+
+    /// Read the key, time and aggregated values, or `None` when the event is skipped
+    fn extract(&self, message: &Message) -> Result<Option<Event>, Error> {
+        let data = match variable(&message.bytes) {
+            Ok(data) => data,
+            Err(e) => return self.failed(e),
+        };
+
+        let key = match &self.key {
+            Some(k) => k.search(&data)?,
+            None => JsonValue::Null,
+        };
+
+        let time = match &self.timestamp {
+            Some(t) => match parse_event_time(&t.search(&data)?) {
+                Ok(time) => time,
+                Err(e) => match self.on_failure {
+                    OnFailure::ProcessingTime => now_millis(),
+                    _ => return self.failed(e),
+                },
+            },
+            None => now_millis(),
+        };
+
+        let values = self
+            .aggregations
+            .iter()
+            .map(|a| match &a.field {
+                Some(f) => f.search(&data),
+                None => Ok(JsonValue::Null),
+            })
+            .collect::<Result<Vec<JsonValue>, Error>>()?;
+
+        Ok(Some(Event { key, time, values }))
+    }
+
+    /// Skip the message with `on_failure: skip`, otherwise fail it
+    fn failed(&self, e: Error) -> Result<Option<Event>, Error> {
+        match self.on_failure {
+            OnFailure::Skip => {
+                debug!("skipping event: {e}");
+                Ok(None)
+            }
+            _ => Err(e),
+        }
+    }
+
+    /// Add the event to its windows, returning `false` if every window already closed
+    fn assign(&self, key_state: &mut KeyState, event: &Event, watermark: i64) -> bool {
+        match self.assigner {
+            Assigner::Fixed { size, slide } => {
+                let mut assigned = false;
+                let mut start = event.time.div_euclid(slide) * slide;
+                while start + size > event.time {
+                    if start + size > watermark {
+                        let window =
+                            match key_state.windows.iter_mut().position(|w| w.start == start) {
+                                Some(i) => &mut key_state.windows[i],
+                                None => {
+                                    key_state.windows.push(Window {
+                                        start,
+                                        end: start + size,
+                                        accumulators: self.new_accumulators(),
+                                    });
+                                    let last = key_state.windows.len() - 1;
+                                    &mut key_state.windows[last]
+                                }
+                            };
+                        window
+                            .accumulators
+                            .iter_mut()
+                            .zip(&event.values)
+                            .for_each(|(a, v)| a.add(v));
+                        assigned = true;
+                    }
+                    start -= slide;
+                }
+                assigned
+            }
+            Assigner::Session { gap } => {
+                if event.time + gap <= watermark {
+                    return false;
+                }
+                let mut session = Window {
+                    start: event.time,
+                    end: event.time + gap,
+                    accumulators: self.new_accumulators(),
+                };
+                session
+                    .accumulators
+                    .iter_mut()
+                    .zip(&event.values)
+                    .for_each(|(a, v)| a.add(v));
+
+                let (overlapping, rest): (Vec<Window>, Vec<Window>) = key_state
+                    .windows
+                    .drain(..)
+                    .partition(|w| w.start < event.time + gap && event.time < w.end);
+                key_state.windows = rest;
+
+                for w in overlapping {
+                    session.start = session.start.min(w.start);
+                    session.end = session.end.max(w.end);
+                    session
+                        .accumulators
+                        .iter_mut()
+                        .zip(w.accumulators)
+                        .for_each(|(a, o)| a.merge(o));
+                }
+                key_state.windows.push(session);
+                true
+            }
+        }
+    }
+
+    fn output(&self, key: &JsonValue, window: Window) -> Result<Message, Error> {
+        let mut body = json!({
+            "key": key,
+            "window_start": format_time(window.start),
+            "window_end": format_time(window.end),
+        });
+        for (aggregation, accumulator) in self.aggregations.iter().zip(&window.accumulators) {
+            set_path(&mut body, &aggregation.path, accumulator.result())?;
+        }
+        Ok(Message {
+            bytes: serde_json::to_vec(&body).map_err(|e| Error::ProcessingError(format!("{e}")))?,
+            ..Default::default()
+        })
+    }
+
+    /// Remove and emit every window ending at or before the watermark
+    fn close_windows(&self, state: &mut State, watermark: i64) -> Result<MessageBatch, Error> {
+        let mut closed: Vec<(JsonValue, Window)> = Vec::new();
+        for key_state in state.keys.values_mut() {
+            let (done, open): (Vec<Window>, Vec<Window>) = key_state
+                .windows
+                .drain(..)
+                .partition(|w| w.end <= watermark);
+            key_state.windows = open;
+            closed.extend(done.into_iter().map(|w| (key_state.key.clone(), w)));
+        }
+        state.keys.retain(|_, k| !k.windows.is_empty());
+
+        closed.sort_by(|(ka, a), (kb, b)| {
+            (a.end, a.start, ka.to_string()).cmp(&(b.end, b.start, kb.to_string()))
+        });
+        closed
+            .into_iter()
+            .map(|(key, window)| self.output(&key, window))
+            .collect()
+    }
+
+    fn watermark(&self, state: &State) -> i64 {
+        match &self.timestamp {
+            Some(_) => state
+                .max_event_time
+                .map_or(i64::MIN, |t| t.saturating_sub(self.allowed_lateness)),
+            None => now_millis(),
+        }
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, State>, Error> {
+        self.state
+            .lock()
+            .map_err(|e| Error::ProcessingError(format!("{e}")))
+    }
+}
+
+#[async_trait]
+impl Processor for WindowProcessor {
+    async fn process(&self, message: Message) -> Result<MessageBatch, Error> {
+        let Some(event) = self.extract(&message)? else {
+            return Ok(Vec::new());
+        };
+        let mut state = self.lock()?;
+
+        if self.timestamp.is_some() {
+            state.max_event_time = Some(
+                state
+                    .max_event_time
+                    .map_or(event.time, |t| t.max(event.time)),
+            );
+        }
+        let watermark = self.watermark(&state);
+
+        let key_id = event.key.to_string();
+        let key_state = state.keys.entry(key_id).or_insert_with(|| KeyState {
+            key: event.key.clone(),
+            windows: Vec::new(),
+        });
+        if !self.assign(key_state, &event, watermark) {
+            debug!(key = %event.key, "dropping late event");
+        }
+
+        self.close_windows(&mut state, watermark)
+    }
+
+    async fn flush(&self, shutdown: bool) -> Result<MessageBatch, Error> {
+        let mut state = self.lock()?;
+        let watermark = match shutdown {
+            true => i64::MAX,
+            false => self.watermark(&state),
+        };
+        self.close_windows(&mut state, watermark)
+    }
+}
+
+impl Closer for WindowProcessor {}
+
+fn require(duration: Option<Duration>, field: &str, window_type: &str) -> Result<i64, Error> {
+    match duration.map(millis) {
+        Some(ms) if ms > 0 => Ok(ms),
+        _ => Err(Error::ConfigFailedValidation(format!(
+            "{field} must be a positive duration for {window_type} windows"
+        ))),
+    }
+}
+
+#[fiddler_registration_func]
+fn create_window(conf: Value) -> Result<ExecutionType, Error> {
+    let c: WindowConfig = serde_yaml::from_value(conf)?;
+
+    let assigner = match c.window_type {
+        WindowType::Tumbling => {
+            let size = require(c.size, "size", "tumbling")?;
+            Assigner::Fixed { size, slide: size }
+        }
+        WindowType::Sliding => {
+            let size = require(c.size, "size", "sliding")?;
+            let slide = require(c.slide, "slide", "sliding")?;
+            // Larger slides would leave gaps that no window covers
+            if slide > size {
+                return Err(Error::ConfigFailedValidation(
+                    "slide must not be greater than size for sliding windows".into(),
+                ));
+            }
+            Assigner::Fixed { size, slide }
+        }
+        WindowType::Session => Assigner::Session {
+            gap: require(c.gap, "gap", "session")?,
+        },
+    };
+
+    let key = c.key.as_deref().map(Expression::compile).transpose()?;
+    let timestamp = c
+        .timestamp
+        .as_deref()
+        .map(Expression::compile)
+        .transpose()?;
+
+    if c.aggregations.is_empty() {
+        return Err(Error::ConfigFailedValidation(
+            "at least one aggregation is required".into(),
+        ));
+    }
+
+    let mut aggregations = Vec::with_capacity(c.aggregations.len());
+    for a in c.aggregations {
+        if a.field.is_none() && a.function != Function::Count {
+            return Err(Error::ConfigFailedValidation(format!(
+                "aggregation '{}' requires a field",
+                a.name
+            )));
+        }
+        aggregations.push(Aggregation {
+            path: parse_path(&a.name)?,
+            function: a.function,
+            field: a.field.as_deref().map(Expression::compile).transpose()?,
+        });
+    }
+
+    Ok(ExecutionType::Processor(Box::new(WindowProcessor {
+        assigner,
+        key,
+        timestamp,
+        allowed_lateness: c.allowed_lateness.map(millis).unwrap_or(0),
+        on_failure: c.on_failure,
+        aggregations,
+        state: Mutex::new(State::default()),
+    })))
+}
+
+pub(super) fn register_window() -> Result<(), Error> {
+    let config = "type: object
+properties:
+  type:
+    type: string
+    enum: [tumbling, sliding, session]
+  size:
+    type: string
+  slide:
+    type: string
+  gap:
+    type: string
+  key:
+    type: string
+  timestamp:
+    type: string
+  allowed_lateness:
+    type: string
+  on_failure:
+    type: string
+    enum: [fail, processing_time, skip]
+  aggregations:
+    type: array
+    items:
+      type: object
+      properties:
+        name:
+          type: string
+        function:
+          type: string
+          enum: [count, sum, min, max, avg, distinct]
+        field:
+          type: string
+      required:
+        - name
+        - function
+required:
+  - aggregations";
+    let conf_spec = ConfigSpec::from_schema(config)?;
+
+    register_plugin(
+        "window".into(),
+        ItemType::Processor,
+        conf_spec,
+        create_window,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::modules::processors::test_util::{message, processor, text_message};
+
+    #[test]
+    fn register_plugin() {
+        register_window().unwrap()
+    }
+
+    fn bodies(messages: MessageBatch) -> Vec<JsonValue> {
+        messages
+            .into_iter()
+            .map(|m| serde_json::from_slice(&m.bytes).unwrap())
+            .collect()
+    }
+
+    const EVENT_TIME: &str = "key: host
+timestamp: ts
+aggregations:
+  - name: count
+    function: count
+  - name: total
+    function: sum
+    field: bytes
+  - name: stats.min
+    function: min
+    field: bytes
+  - name: stats.max
+    function: max
+    field: bytes
+  - name: stats.avg
+    function: avg
+    field: bytes
+  - name: paths
+    function: distinct
+    field: path";
+
+    #[tokio::test]
+    async fn tumbling_event_time() {
+        let p = processor(create_window, &format!("size: 1m\n{EVENT_TIME}")).await;
+
+        let mut output = Vec::new();
+        for (host, ts, bytes, path) in [
+            ("a", 0, 10, "/"),
+            ("b", 10, 5, "/"),
+            ("a", 20, 30, "/x"),
+            ("a", 30, 20, "/"),
+            ("a", 60, 1, "/"),
+        ] {
+            output.extend(
+                p.process(message(
+                    json!({"host": host, "ts": ts, "bytes": bytes, "path": path}),
+                ))
+                .await
+                .unwrap(),
+            );
+        }
+
+        assert_eq!(
+            bodies(output),
+            vec![
+                json!({
+                    "key": "a",
+                    "window_start": "1970-01-01T00:00:00.000Z",
+                    "window_end": "1970-01-01T00:01:00.000Z",
+                    "count": 3,
+                    "total": 60.0,
+                    "stats": {"min": 10.0, "max": 30.0, "avg": 20.0},
+                    "paths": 2
+                }),
+                json!({
+                    "key": "b",
+                    "window_start": "1970-01-01T00:00:00.000Z",
+                    "window_end": "1970-01-01T00:01:00.000Z",
+                    "count": 1,
+                    "total": 5.0,
+                    "stats": {"min": 5.0, "max": 5.0, "avg": 5.0},
+                    "paths": 1
+                }),
+            ]
+        );
+
+        let remaining = bodies(p.flush(true).await.unwrap());
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0]["window_start"], "1970-01-01T00:01:00.000Z");
+        assert_eq!(remaining[0]["count"], 1);
+    }
+
+    #[tokio::test]
+    async fn sliding_windows_overlap() {
+        let p = processor(
+            create_window,
+            "type: sliding
+size: 10s
+slide: 5s
+timestamp: ts
+aggregations:
+  - name: count
+    function: count",
+        )
+        .await;
+        assert!(p
+            .process(message(json!({"ts": 7})))
+            .await
+            .unwrap()
+            .is_empty());
+        let output = bodies(p.flush(true).await.unwrap());
+        assert_eq!(
+            output,
+            vec![
+                json!({"key": null, "window_start": "1970-01-01T00:00:00.000Z", "window_end": "1970-01-01T00:00:10.000Z", "count": 1}),
+                json!({"key": null, "window_start": "1970-01-01T00:00:05.000Z", "window_end": "1970-01-01T00:00:15.000Z", "count": 1}),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn session_windows_merge() {
+        let p = processor(
+            create_window,
+            "type: session
+gap: 10s
+key: user
+timestamp: ts
+aggregations:
+  - name: count
+    function: count",
+        )
+        .await;
+        for ts in [0, 5, 12] {
+            assert!(p
+                .process(message(json!({"user": "u", "ts": ts})))
+                .await
+                .unwrap()
+                .is_empty());
+        }
+        let output = bodies(
+            p.process(message(json!({"user": "u", "ts": 40})))
+                .await
+                .unwrap(),
+        );
+        assert_eq!(
+            output,
+            vec![json!({
+                "key": "u",
+                "window_start": "1970-01-01T00:00:00.000Z",
+                "window_end": "1970-01-01T00:00:22.000Z",
+                "count": 3
+            })]
+        );
+    }
+
+    #[tokio::test]
+    async fn allowed_lateness_and_late_events() {
+        let p = processor(
+            create_window,
+            "size: 10s
+timestamp: ts
+allowed_lateness: 5s
+aggregations:
+  - name: count
+    function: count",
+        )
+        .await;
+        assert!(p
+            .process(message(json!({"ts": 1})))
+            .await
+            .unwrap()
+            .is_empty());
+        // within allowed lateness, the first window is still open
+        assert!(p
+            .process(message(json!({"ts": 12})))
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(p
+            .process(message(json!({"ts": 3})))
+            .await
+            .unwrap()
+            .is_empty());
+        let output = bodies(p.process(message(json!({"ts": 16}))).await.unwrap());
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0]["count"], 2);
+        // the first window closed, so this event is dropped
+        assert!(p
+            .process(message(json!({"ts": 4})))
+            .await
+            .unwrap()
+            .is_empty());
+        let output = bodies(p.flush(true).await.unwrap());
+        assert_eq!(output.len(), 1);
+        assert_eq!(output[0]["count"], 2);
+    }
+
+    #[tokio::test]
+    async fn rfc3339_event_time() {
+        let p = processor(
+            create_window,
+            "size: 1h
+timestamp: ts
+aggregations:
+  - name: count
+    function: count",
+        )
+        .await;
+        p.process(message(json!({"ts": "2024-05-01T10:15:00+02:00"})))
+            .await
+            .unwrap();
+        let output = bodies(p.flush(true).await.unwrap());
+        assert_eq!(output[0]["window_start"], "2024-05-01T08:00:00.000Z");
+    }
+
+    #[tokio::test]
+    async fn processing_time_flush() {
+        let p = processor(
+            create_window,
+            "size: 1h
+aggregations:
+  - name: count
+    function: count",
+        )
+        .await;
+        assert!(p.process(message(json!({}))).await.unwrap().is_empty());
+        assert!(p.flush(false).await.unwrap().is_empty());
+        let output = bodies(p.flush(true).await.unwrap());
+        assert_eq!(output[0]["count"], 1);
+    }
+
+    #[tokio::test]
+    async fn body_not_json() {
+        let conf = "size: 1m
+aggregations:
+  - name: count
+    function: count";
+        for on_failure in ["fail", "processing_time"] {
+            let p = processor(create_window, &format!("on_failure: {on_failure}\n{conf}")).await;
+            let err = p.process(text_message("not json")).await.unwrap_err();
+            assert!(matches!(err, Error::MessageFailed(_)), "{on_failure}");
+        }
+
+        let p = processor(create_window, &format!("on_failure: skip\n{conf}")).await;
+        assert!(p
+            .process(text_message("not json"))
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(p.flush(true).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn invalid_event_time() {
+        let p = processor(
+            create_window,
+            "size: 1m
+timestamp: ts
+aggregations:
+  - name: count
+    function: count",
+        )
+        .await;
+        let err = p.process(message(json!({"ts": true}))).await.unwrap_err();
+        assert!(matches!(err, Error::MessageFailed(_)));
+
+        let p = processor(
+            create_window,
+            "size: 1m
+timestamp: ts
+on_failure: skip
+aggregations:
+  - name: count
+    function: count",
+        )
+        .await;
+        assert!(p
+            .process(message(json!({"ts": "soon"})))
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(p.flush(true).await.unwrap().is_empty());
+
+        let p = processor(
+            create_window,
+            "size: 1m
+timestamp: ts
+on_failure: processing_time
+aggregations:
+  - name: count
+    function: count",
+        )
+        .await;
+        assert!(p
+            .process(message(json!({"ts": "soon"})))
+            .await
+            .unwrap()
+            .is_empty());
+        let output = bodies(p.flush(true).await.unwrap());
+        assert_eq!(output[0]["count"], 1);
+    }
+
+    #[tokio::test]
+    async fn invalid_config() {
+        for conf in [
+            "aggregations: [{name: c, function: count}]",
+            "type: sliding\nsize: 1m\naggregations: [{name: c, function: count}]",
+            "type: sliding\nsize: 1m\nslide: 2m\naggregations: [{name: c, function: count}]",
+            "size: 1m\non_failure: retry\naggregations: [{name: c, function: count}]",
+            "type: session\nsize: 1m\naggregations: [{name: c, function: count}]",
+            "size: 1m\naggregations: []",
+            "size: 1m\naggregations: [{name: s, function: sum}]",
+            "size: 1m\nkey: '[['\naggregations: [{name: c, function: count}]",
+        ] {
+            let value: Value = serde_yaml::from_str(conf).unwrap();
+            assert!(create_window(value).await.is_err(), "{conf}");
+        }
+    }
+}
@@ -0,0 +1,32 @@
+//! Shared helpers for the Redis input, output and processors.
+
+use crate::Error;
+use redis::aio::ConnectionManager;
+use redis::{Client, ErrorKind};
+
+/// Check if a Redis error is an authentication failure.
+pub(crate) fn is_auth_error(e: &redis::RedisError) -> bool {
+    matches!(e.kind(), ErrorKind::AuthenticationFailed)
+        || e.to_string().to_lowercase().contains("noauth")
+        || e.to_string().to_lowercase().contains("wrongpass")
+        || e.to_string().to_lowercase().contains("invalid password")
+}
+
+/// Convert a Redis error to a fiddler Error, with special handling for auth failures.
+pub(crate) fn redis_error_to_fiddler_error(e: redis::RedisError, context: &str) -> Error {
+    if is_auth_error(&e) {
+        Error::ConfigFailedValidation(format!("Redis authentication failed: {}", e))
+    } else {
+        Error::ExecutionError(format!("{}: {}", context, e))
+    }
+}
+
+/// Open a reconnecting connection manager for the given Redis URL.
+pub(crate) async fn connection_manager(url: &str) -> Result<ConnectionManager, Error> {
+    let client =
+        Client::open(url).map_err(|e| redis_error_to_fiddler_error(e, "Invalid Redis URL"))?;
+
+    ConnectionManager::new(client)
+        .await
+        .map_err(|e| redis_error_to_fiddler_error(e, "Failed to connect to Redis"))
+}
@@ -569,7 +569,7 @@ impl Runtime {
             .output(self.config.output.clone(), &mut handles)
             .await?;
 
-        let processors = self.pipeline(output, &mut handles).await?;
+        let processors = self.pipeline(output, msg_tx.clone(), &mut handles).await?;
 
         // Kill switch only needs capacity of 1 - it's a signal channel
         let (ks_send, ks_recv) = bounded(1);
@@ -677,6 +677,7 @@ impl Runtime {
     async fn pipeline(
         &self,
         input: Sender<InternalMessage>,
+        handle_tx: Sender<MessageHandle>,
         handles: &mut JoinSet<Result<(), Error>>,
     ) -> Result<Sender<InternalMessage>, Error> {
         trace!("starting pipeline");
@@ -697,6 +698,7 @@ impl Runtime {
                     next_tx.clone(),
                     rx.clone(),
                     self.state_tx.clone(),
+                    handle_tx.clone(),
                 );
                 spawn_task(handles, proc);
             }
@@ -293,6 +293,70 @@ output:
     env.run().await.unwrap();
 }
 
+#[tokio::test]
+async fn fiddler_multiline_flush_test() {
+    let config = format!(
+        "input:
+  file: 
+    filename: tests{MAIN_SEPARATOR_STR}data{MAIN_SEPARATOR_STR}input.txt
+    codec: Lines
+num_threads: 1
+processors:
+  - label: join_lines
+    multiline:
+      pattern: '^This'
+      keys: [file_name]
+output:
+  validate:
+    expected: 
+      - \"Hello World\\nThis is the end\""
+    );
+
+    REGISTER.call_once(|| {
+        mock::register_mock_input().unwrap();
+        jsongenerator::register_json_generator().unwrap();
+        generator::register_generator().unwrap();
+        processor::register_echo().unwrap();
+        output::register_validate().unwrap();
+    });
+
+    let env = Runtime::from_config(&config).await.unwrap();
+    env.run().await.unwrap();
+}
+
+#[tokio::test]
+async fn fiddler_multiline_nested_flush_test() {
+    // The event still held by a multiline nested in try is emitted on shutdown
+    let config = format!(
+        "input:
+  file: 
+    filename: tests{MAIN_SEPARATOR_STR}data{MAIN_SEPARATOR_STR}input.txt
+    codec: Lines
+num_threads: 1
+processors:
+  - try:
+      processor:
+        multiline:
+          pattern: '^This'
+          keys: [file_name]
+output:
+  validate:
+    expected: 
+      - \"Hello World\\nThis is the end\""
+    );
+
+    REGISTER.call_once(|| {
+        mock::register_mock_input().unwrap();
+        jsongenerator::register_json_generator().unwrap();
+        generator::register_generator().unwrap();
+        processor::register_echo().unwrap();
+        output::register_validate().unwrap();
+    });
+
+    let env = Runtime::from_config(&config).await.unwrap();
+    env.run().await.unwrap();
+}
+
 #[tokio::test]
 async fn fiddler_file_reader_test_full() {
     let config = format!(
@@ -445,6 +509,111 @@ output:
     env.run().await.unwrap();
 }
 
+#[tokio::test]
+async fn processor_try_reraise() {
+    // The catch output continues while the failed message is reported to the input
+    let config = "input:
+  mock_input:
+    input:
+      - SGVsbG8gV29ybGQ=
+      - H4sIAAAAAAAC//NIzcnJVwjPL8pJAQBWsRdKCwAAAA==
+num_threads: 1
+processors:
+  - decode: {}
+  - try:
+      processor:
+        decompress: {}
+      catch:
+        - noop: {}
+      reraise: true
+output:
+  validate:
+    expected:
+      - 'Hello World'
+      - 'Hello World'";
+
+    REGISTER.call_once(|| {
+        mock::register_mock_input().unwrap();
+        jsongenerator::register_json_generator().unwrap();
+        generator::register_generator().unwrap();
+        processor::register_echo().unwrap();
+        output::register_validate().unwrap();
+    });
+
+    let env = Runtime::from_config(config).await.unwrap();
+    env.run().await.unwrap();
+}
+
+#[tokio::test]
+async fn processor_switch_flushes_archive() {
+    // Messages held back by a nested archive are emitted on shutdown
+    let config = r#"input:
+  mock_input:
+    input:
+      - '{"level":"error","id":1}'
+      - '{"level":"info","id":2}'
+      - '{"level":"error","id":3}'
+num_threads: 1
+processors:
+  - switch:
+      - check:
+          condition: "level == 'error'"
+          processors:
+            - archive:
+                format: ndjson
+                batch:
+                  size: 100
+output:
+  validate:
+    expected:
+      - '{"level":"info","id":2}'
+      - "{\"level\":\"error\",\"id\":1}\n{\"level\":\"error\",\"id\":3}\n""#;
+
+    REGISTER.call_once(|| {
+        mock::register_mock_input().unwrap();
+        jsongenerator::register_json_generator().unwrap();
+        generator::register_generator().unwrap();
+        processor::register_echo().unwrap();
+        output::register_validate().unwrap();
+    });
+
+    let env = Runtime::from_config(config).await.unwrap();
+    env.run().await.unwrap();
+}
+
+#[tokio::test]
+async fn processor_validate_failure_continues() {
+    // The invalid message fails on its own, and the pipeline keeps processing
+    let config = r#"input:
+  mock_input:
+    input:
+      - '{"id": 1}'
+      - '{"name": "missing id"}'
+      - '{"id": 2}'
+num_threads: 1
+processors:
+  - validate:
+      schema:
+        type: object
+        required: [id]
+output:
+  validate:
+    expected:
+      - '{"id": 1}'
+      - '{"id": 2}'"#;
+
+    REGISTER.call_once(|| {
+        mock::register_mock_input().unwrap();
+        jsongenerator::register_json_generator().unwrap();
+        generator::register_generator().unwrap();
+        processor::register_echo().unwrap();
+        output::register_validate().unwrap();
+    });
+
+    let env = Runtime::from_config(config).await.unwrap();
+    env.run().await.unwrap();
+}
+
 // ============================================================================
 // Filter Processor Integration Tests
 // ============================================================================
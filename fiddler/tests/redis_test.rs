@@ -470,3 +470,65 @@ output:
         _ => {}
     }
 }
+
+/// Test redis_lookup processor: GET and HGETALL results are merged into list messages
+#[cfg(feature = "redis")]
+#[cfg_attr(feature = "redis", tokio::test)]
+async fn fiddler_redis_lookup_processor_test() {
+    let container = Redis::default().start().await.unwrap();
+    let host_port = container.get_host_port_ipv4(6379).await.unwrap();
+    let redis_url = format!("redis://127.0.0.1:{}", host_port);
+
+    tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+
+    let client = redis::Client::open(redis_url.as_str()).unwrap();
+    let mut conn = client.get_multiplexed_async_connection().await.unwrap();
+
+    let _: () = conn.set("user:1", r#"{"name":"alice"}"#).await.unwrap();
+    let _: () = conn
+        .hset_multiple("host:web", &[("dc", "east"), ("rack", "4")])
+        .await
+        .unwrap();
+    let _: () = conn
+        .rpush("lookup_queue", r#"{"host":"web","user_id":1}"#)
+        .await
+        .unwrap();
+    let _: () = conn
+        .rpush("lookup_queue", r#"{"host":"db","user_id":2}"#)
+        .await
+        .unwrap();
+
+    let _ = output::register_validate();
+
+    let config = format!(
+        r#"input:
+  redis:
+    url: "{redis_url}"
+    mode: list
+    keys:
+      - lookup_queue
+    list_command: blpop
+    timeout: 1
+num_threads: 1
+processors:
+  - redis_lookup:
+      url: "{redis_url}"
+      lookups:
+        - key: "user:${{user_id}}"
+          target: user
+        - command: hgetall
+          key: "host:${{host}}"
+          target: host_info
+      cache_ttl: 1m
+output:
+  validate:
+    expected:
+      - '{{"host":"web","host_info":{{"dc":"east","rack":4}},"user":{{"name":"alice"}},"user_id":1}}'
+      - '{{"host":"db","user_id":2}}'"#
+    );
+
+    let mut env = Runtime::from_config(&config).await.unwrap();
+    env.set_timeout(Some(tokio::time::Duration::from_secs(10)))
+        .unwrap();
+    env.run().await.unwrap();
+}